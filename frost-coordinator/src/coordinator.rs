@@ -1,21 +1,24 @@
 use std::any::Any;
 use std::collections::BTreeMap;
-use std::time::Duration;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
 use frost_signer::config::Config;
-use frost_signer::net::{HttpNetError, Message, NetListen};
+use frost_signer::net::{HttpNetError, Message, NetListen, POLL_RETRY_DELAY};
 use frost_signer::signing_round::{
     DkgBegin, DkgPublicShare, MessageTypes, NonceRequest, NonceResponse, SignatureShareRequest,
 };
+use frost_signer::taproot::{self, SchnorrSignature};
 use hashbrown::HashSet;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 use wtfrost::common::PublicNonce;
-use wtfrost::{common::PolyCommitment, common::Signature, errors::AggregatorError, v1, Point};
+use wtfrost::{Point, Scalar};
 
 use serde::{Deserialize, Serialize};
 
 pub const DEVNET_COORDINATOR_ID: usize = 0;
 pub const DEVNET_COORDINATOR_DKG_ID: u64 = 0; //TODO: Remove, this is a correlation id
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
 
 #[derive(clap::Subcommand, Debug)]
 pub enum Command {
@@ -25,6 +28,13 @@ pub enum Command {
     GetAggregatePublicKey,
 }
 
+/// The outcome of a DKG round which is needed to sign with its aggregate public key
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DkgState {
+    pub dkg_id: u64,
+    pub public_shares: BTreeMap<u32, DkgPublicShare>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Coordinator<Network: NetListen> {
     id: u32, // Used for relay coordination
@@ -35,7 +45,7 @@ pub struct Coordinator<Network: NetListen> {
     network: Network,
     dkg_public_shares: BTreeMap<u32, DkgPublicShare>,
     public_nonces: BTreeMap<u32, NonceResponse>,
-    signature_shares: BTreeMap<u32, v1::SignatureShare>,
+    signature_shares: BTreeMap<u32, Scalar>,
     aggregate_public_key: Point,
 }
 
@@ -101,10 +111,14 @@ where
         result
     }
 
-    pub fn sign_message(&mut self, msg: &[u8]) -> Result<Signature, Error> {
+    /// Signs `msg` with the taproot output key of the aggregate public key, see [taproot]
+    pub fn sign_message(&mut self, msg: &[u8]) -> Result<SchnorrSignature, Error> {
         if self.aggregate_public_key == Point::default() {
             return Err(Error::NoAggregatePublicKey);
         }
+        // the nonces and shares of an earlier message don't sign this one
+        self.public_nonces.clear();
+        self.signature_shares.clear();
 
         let nonce_request_message = Message {
             msg: MessageTypes::NonceRequest(NonceRequest {
@@ -144,30 +158,6 @@ where
         let mut waiting_for_signature_shares: HashSet<u32> =
             HashSet::from_iter(self.public_nonces.keys().cloned());
 
-        // make an array of dkg public share polys for SignatureAggregator
-        info!(
-            "collecting commitments from 1..{} in {:?}",
-            self.total_keys,
-            self.dkg_public_shares.keys().collect::<Vec<&u32>>()
-        );
-        let polys: Vec<PolyCommitment> = self
-            .dkg_public_shares
-            .values()
-            .map(|ps| ps.public_share.clone())
-            .collect();
-
-        info!(
-            "SignatureAggregator::new total_keys: {} threshold: {} commitments: {} ",
-            self.total_keys,
-            self.threshold,
-            polys.len()
-        );
-        let mut aggregator =
-            match v1::SignatureAggregator::new(self.total_keys, self.threshold, polys) {
-                Ok(aggregator) => aggregator,
-                Err(e) => return Err(Error::Aggregator(e)),
-            };
-
         let id_nonces: Vec<(u32, PublicNonce)> = self
             .public_nonces
             .iter()
@@ -213,28 +203,24 @@ where
             }
         }
 
-        // call aggregator.sign()
-        let nonces = id_nonces
-            .iter()
-            .map(|(_i, n)| n.clone())
-            .collect::<Vec<PublicNonce>>();
         let shares = id_nonces
             .iter()
-            .map(|(i, _n)| self.signature_shares[i].clone())
-            .collect::<Vec<v1::SignatureShare>>();
+            .map(|(i, _n)| self.signature_shares[i])
+            .collect::<Vec<Scalar>>();
         info!(
-            "aggregator.sign({:?}, {:?}, {:?})",
+            "taproot::aggregate({:?}, {:?}, {:?})",
             msg,
-            nonces.len(),
+            id_nonces.len(),
             shares.len()
         );
 
-        let sig = match aggregator.sign(msg, &nonces, &shares) {
-            Ok(sig) => sig,
-            Err(e) => return Err(Error::Aggregator(e)),
-        };
+        let sig = taproot::aggregate(&self.aggregate_public_key, &id_nonces, &shares, msg);
+        // a wrong share makes a signature which doesn't verify
+        if !taproot::verify(&self.aggregate_public_key, msg, &sig) {
+            return Err(Error::InvalidSignature);
+        }
 
-        info!("Signature ({}, {})", sig.R, sig.z);
+        info!("Signature {:?}", sig);
 
         Ok(sig)
     }
@@ -255,6 +241,23 @@ where
         }
     }
 
+    /// The state of the last DKG round, which is saved to sign with its key after a restart
+    pub fn dkg_state(&self) -> DkgState {
+        DkgState {
+            dkg_id: self.current_dkg_id,
+            public_shares: self.dkg_public_shares.clone(),
+        }
+    }
+
+    /// Restores the state of an earlier DKG round instead of running a new one
+    pub fn set_dkg_state(&mut self, state: DkgState) -> Result<Point, Error> {
+        self.current_dkg_id = state.dkg_id;
+        self.dkg_public_shares = state.public_shares;
+        // an empty state has no key to sign with
+        self.calculate_aggregate_public_key()?;
+        self.get_aggregate_public_key()
+    }
+
     fn wait_for_dkg_end(&mut self) -> Result<Point, Error> {
         let mut ids_to_await: HashSet<usize> = (1..=self.total_signers).collect();
 
@@ -293,22 +296,29 @@ where
     }
 
     fn wait_for_next_message(&mut self) -> Result<Message, Error> {
-        let get_next_message = || {
-            self.network.poll(self.id);
-            self.network
-                .next_message()
-                .ok_or_else(|| "No message yet".to_owned())
-                .map_err(backoff::Error::transient)
-        };
-
-        let notify = |_err, dur| {
-            debug!("No message. Next poll in {:?}", dur);
-        };
-
-        let backoff_timer = backoff::ExponentialBackoffBuilder::new()
-            .with_max_interval(Duration::from_secs(3))
-            .build();
-        backoff::retry_notify(backoff_timer, get_next_message, notify).map_err(|_| Error::Timeout)
+        // the caller has handled the messages it received before
+        self.network.ack()?;
+        let start = Instant::now();
+        loop {
+            // a single poll may deliver several messages, drain those first
+            if let Some(message) = self.network.next_message() {
+                return Ok(message);
+            }
+            if start.elapsed() > MESSAGE_TIMEOUT {
+                return Err(Error::Timeout);
+            }
+            debug!("No message. Polling for the next one");
+            // blocks until a message is available or the long poll times out
+            if let Err(e) = self.network.poll(self.id) {
+                match Error::from(e) {
+                    Error::NetworkError(e) if e.is_transient() => {
+                        warn!("poll failed, retrying: {}", e);
+                        sleep(POLL_RETRY_DELAY);
+                    }
+                    e => return Err(e),
+                }
+            }
+        }
     }
 }
 
@@ -318,8 +328,8 @@ pub enum Error {
     NetworkError(#[from] HttpNetError),
     #[error("No aggregate public key")]
     NoAggregatePublicKey,
-    #[error("Aggregate failed to sign")]
-    Aggregator(AggregatorError),
+    #[error("The aggregate signature is invalid")]
+    InvalidSignature,
     #[error("Operation timed out")]
     Timeout,
 }
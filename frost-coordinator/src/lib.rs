@@ -4,6 +4,7 @@ use coordinator::Coordinator;
 use frost_signer::{
     config::Config,
     net::{HttpNet, HttpNetListen},
+    tcp_net::TcpNetListen,
 };
 
 pub const DEVNET_COORDINATOR_ID: usize = 0;
@@ -14,8 +15,26 @@ pub fn create_coordinator(
 ) -> Result<Coordinator<HttpNetListen>, String> {
     let config = Config::from_path(path)?;
 
-    let net: HttpNet = HttpNet::new(config.http_relay_url.clone());
-    let net_listen: HttpNetListen = HttpNetListen::new(net, vec![]);
+    let net: HttpNet =
+        HttpNet::from_config(&config, DEVNET_COORDINATOR_ID as u32).map_err(|e| e.to_string())?;
+    let net_listen = HttpNetListen::from_config(net, &config, DEVNET_COORDINATOR_ID as u32)
+        .map_err(|e| e.to_string())?;
+
+    Ok(Coordinator::new(
+        DEVNET_COORDINATOR_ID,
+        DEVNET_COORDINATOR_DKG_ID,
+        &config,
+        net_listen,
+    ))
+}
+
+pub fn create_tcp_coordinator(
+    path: impl AsRef<std::path::Path>,
+) -> Result<Coordinator<TcpNetListen>, String> {
+    let config = Config::from_path(path)?;
+
+    let net_listen = TcpNetListen::from_config(&config, DEVNET_COORDINATOR_ID as u32)
+        .map_err(|e| e.to_string())?;
 
     Ok(Coordinator::new(
         DEVNET_COORDINATOR_ID,
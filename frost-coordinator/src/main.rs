@@ -1,8 +1,10 @@
 use clap::Parser;
 
-use frost_coordinator::coordinator::Command;
-use frost_coordinator::create_coordinator;
+use frost_coordinator::coordinator::{Command, Coordinator, Error};
+use frost_coordinator::{create_coordinator, create_tcp_coordinator};
+use frost_signer::config::{Config, Transport};
 use frost_signer::logging;
+use frost_signer::net::NetListen;
 use tracing::warn;
 
 #[derive(Parser, Debug)]
@@ -20,9 +22,22 @@ fn main() {
     logging::initiate_tracing_subscriber(tracing::Level::INFO).unwrap();
 
     let cli = Cli::parse();
-    match create_coordinator(cli.config) {
+    match Config::from_path(&cli.config).map(|config| config.transport) {
+        Ok(Transport::Tcp) => run(create_tcp_coordinator(cli.config), &cli.command),
+        Ok(Transport::Http) => run(create_coordinator(cli.config), &cli.command),
+        Err(e) => {
+            warn!("Failed to create coordinator: {}", e);
+        }
+    }
+}
+
+fn run<Network: NetListen>(coordinator: Result<Coordinator<Network>, String>, command: &Command)
+where
+    Error: From<Network::Error>,
+{
+    match coordinator {
         Ok(mut coordinator) => {
-            let result = coordinator.run(&cli.command);
+            let result = coordinator.run(command);
             if let Err(e) = result {
                 warn!("Failed to execute command: {}", e);
             }
@@ -6,11 +6,54 @@ use toml;
 #[derive(Clone, Deserialize, Default, Debug)]
 pub struct Config {
     pub http_relay_url: String,
+    /// Further relays of a replicated cluster, used when `http_relay_url` fails
+    #[serde(default)]
+    pub http_relay_urls: Vec<String>,
     pub total_signers: usize,
     pub total_keys: usize,
     pub keys_threshold: usize,
     pub max_party_id: usize,
     pub frost_state_file: String,
+    #[serde(default)]
+    pub transport: Transport,
+    /// Listen addresses of all nodes, used by the `tcp` transport
+    #[serde(default)]
+    pub peers: Vec<Peer>,
+    /// Private key (32 bytes in hex) which signs the requests of this node to a relay
+    /// which requires authentication, see `relay-server`
+    #[serde(default)]
+    pub relay_private_key: Option<String>,
+    /// Path to the CA certificates (PEM) which an `https://` relay must be signed by
+    #[serde(default)]
+    pub relay_ca: Option<String>,
+    /// Paths to the certificate and the private key (PEM) of this node, for a relay
+    /// which verifies client certificates
+    #[serde(default)]
+    pub relay_client_cert: Option<String>,
+    #[serde(default)]
+    pub relay_client_key: Option<String>,
+    /// File where a node keeps its read position in the relay, followed by the node id, like
+    /// `signer.cursor.1`. The node then receives every message at least once, also after a
+    /// restart
+    #[serde(default)]
+    pub relay_cursor_file: Option<String>,
+}
+
+/// How the signers and the coordinator exchange messages
+#[derive(Clone, Copy, Deserialize, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// Through the relay at `http_relay_url`
+    #[default]
+    Http,
+    /// Directly over TCP between all `peers`
+    Tcp,
+}
+
+#[derive(Clone, Deserialize, Default, Debug)]
+pub struct Peer {
+    pub id: u32,
+    pub address: String,
 }
 
 #[derive(Parser)]
@@ -38,4 +81,12 @@ impl Config {
         let content = fs::read_to_string(path).map_err(|e| format!("Invalid path: {}", &e))?;
         toml::from_str(&content).map_err(|e| format!("Invalid toml: {}", e))
     }
+
+    /// The `relay_cursor_file` of the node `id`, so the nodes which share a config don't
+    /// share their read positions
+    pub fn relay_cursor_file(&self, id: u32) -> Option<String> {
+        self.relay_cursor_file
+            .as_ref()
+            .map(|path| format!("{}.{}", path, id))
+    }
 }
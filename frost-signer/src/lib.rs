@@ -1,9 +1,12 @@
 pub mod config;
 pub mod logging;
+pub mod mem_net;
 pub mod net;
 pub mod signer;
 pub mod signing_round;
 pub mod state_machine;
+pub mod taproot;
+pub mod tcp_net;
 
 // set via _compile-time_ envars
 const GIT_BRANCH: Option<&'static str> = option_env!("GIT_BRANCH");
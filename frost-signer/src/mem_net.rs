@@ -0,0 +1,111 @@
+use relay_server::{MemState, State};
+use std::collections::VecDeque;
+use std::sync::{mpsc::RecvError, Arc, Condvar, Mutex};
+use std::time::Duration;
+use tracing::debug;
+
+use crate::net::{route, HttpNetError, Message, Net, NetListen};
+
+// how long a poll waits for a message before giving up
+const POLL_WAIT: Duration = Duration::from_millis(100);
+
+// In-process send (does not require mutable access, can be cloned to pass to threads).
+// All clones share one relay state, so every node of a test or an embedding application
+// sees the same message order with the same per-node highwaters as with relay-server.
+#[derive(Clone, Default)]
+pub struct MemNet {
+    relay: Arc<(Mutex<MemState>, Condvar)>,
+}
+
+impl MemNet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl MemNet {
+    fn post(&self, to: Option<u32>, msg: &Message) -> Result<(), HttpNetError> {
+        let bytes = bincode::serialize(msg)?;
+        let (state, posted) = &*self.relay;
+        let mut state = state.lock().map_err(|_| HttpNetError::SendError)?;
+        match to {
+            Some(id) => state.post_to(id.to_string(), bytes)?,
+            None => state.post(bytes)?,
+        }
+        posted.notify_all();
+        debug!("sent {:?}", &msg.msg);
+        Ok(())
+    }
+}
+
+impl Net for MemNet {
+    type Error = HttpNetError;
+
+    fn send_message(&self, msg: Message) -> Result<(), Self::Error> {
+        for (to, msg) in route(msg) {
+            self.post(to, &msg)?;
+        }
+        Ok(())
+    }
+
+    fn send_message_to(&self, id: u32, msg: Message) -> Result<(), Self::Error> {
+        self.post(Some(id), &msg)
+    }
+}
+
+// In-process listen/poll with queue (requires mutable access, is configured by passing in MemNet)
+pub struct MemNetListen {
+    pub net: MemNet,
+    in_queue: VecDeque<Message>,
+}
+
+impl MemNetListen {
+    pub fn new(net: MemNet) -> Self {
+        MemNetListen {
+            net,
+            in_queue: VecDeque::new(),
+        }
+    }
+}
+
+impl NetListen for MemNetListen {
+    type Error = HttpNetError;
+
+    fn listen(&self) {}
+
+    fn poll(&mut self, id: u32) -> Result<(), Self::Error> {
+        let (state, posted) = &*self.net.relay;
+        let mut state = state
+            .lock()
+            .map_err(|_| HttpNetError::RecvError(RecvError))?;
+        let mut batch = state.get_all(id.to_string())?;
+        if batch.is_empty() {
+            // like a long poll on the relay, wait for the next post
+            state = posted
+                .wait_timeout(state, POLL_WAIT)
+                .map_err(|_| HttpNetError::RecvError(RecvError))?
+                .0;
+            batch = state.get_all(id.to_string())?;
+        }
+        for bytes in batch {
+            if let Ok(msg) = bincode::deserialize::<Message>(&bytes) {
+                debug!("received {:?}", msg);
+                self.in_queue.push_back(msg);
+            }
+        }
+        Ok(())
+    }
+
+    fn next_message(&mut self) -> Option<Message> {
+        self.in_queue.pop_front()
+    }
+
+    // pass-thru to immutable net function
+    fn send_message(&self, msg: Message) -> Result<(), Self::Error> {
+        self.net.send_message(msg)
+    }
+
+    fn send_message_to(&self, id: u32, msg: Message) -> Result<(), Self::Error> {
+        self.net.send_message_to(id, msg)
+    }
+}
@@ -1,9 +1,26 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::fmt::Debug;
-use std::sync::mpsc;
+use std::fs;
+use std::io::{ErrorKind, Read};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
 use tracing::{debug, info, warn};
 
-use crate::signing_round;
+use relay_server::{client_config, Credentials, Cursor, Indexed};
+use rustls::ClientConfig;
+
+use crate::config::Config;
+use crate::signing_round::{self, signer_id_for_party, DkgPrivateShares, KeyShares, MessageTypes};
+
+// how long the relay may hold a poll open while waiting for a message
+const POLL_WAIT: Duration = Duration::from_secs(10);
+// how long to wait before polling again after a failed poll
+pub const POLL_RETRY_DELAY: Duration = Duration::from_millis(500);
+
 // Message is the format over the wire
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Message {
@@ -11,15 +28,173 @@ pub struct Message {
     pub sig: [u8; 32],
 }
 
+// how many origins of messages a node remembers, to ignore the messages when another relay
+// returns them again
+const SEEN_MESSAGES: usize = 4096;
+
 // Http listen/poll with queue (requires mutable access, is configured by passing in HttpNet)
 pub struct HttpNetListen {
     pub net: HttpNet,
-    in_queue: Vec<Message>,
+    // every message with the relay cursors it moves, for cursor reads
+    in_queue: VecDeque<(Message, Vec<(String, Cursor)>)>,
+    cursor: Option<CursorFile>,
+    // the origins of the last messages received, whether they were direct
+    seen: HashSet<(bool, String)>,
+    seen_order: VecDeque<(bool, String)>,
+}
+
+// The read positions of a node which reads with a cursor, one for every relay. The messages
+// taken by `next_message` are only saved as processed by `ack`. Without a path, the positions
+// are only kept in memory.
+struct CursorFile {
+    path: Option<PathBuf>,
+    // the cursors which are saved and sent to the relays
+    processed: BTreeMap<String, Cursor>,
+    // the cursors of the messages handed out since the last `ack`
+    pending: Vec<(String, Cursor)>,
+    // the cursors after the last messages received, received messages are not queued again
+    received: BTreeMap<String, Cursor>,
+}
+
+impl CursorFile {
+    // every line is a relay URL and its cursor
+    fn load(path: PathBuf) -> Result<Self, HttpNetError> {
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut processed = BTreeMap::new();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let (relay, cursor) = line.split_once(' ').ok_or_else(|| {
+                std::io::Error::new(ErrorKind::InvalidData, format!("invalid cursor {}", line))
+            })?;
+            processed.insert(relay.to_string(), cursor.trim().parse()?);
+        }
+        Ok(CursorFile {
+            path: Some(path),
+            received: processed.clone(),
+            processed,
+            pending: Vec::new(),
+        })
+    }
+
+    fn in_memory() -> Self {
+        CursorFile {
+            path: None,
+            processed: BTreeMap::new(),
+            pending: Vec::new(),
+            received: BTreeMap::new(),
+        }
+    }
+
+    fn processed(&self, relay: &str) -> Cursor {
+        self.processed.get(relay).copied().unwrap_or_default()
+    }
+
+    fn commit(&mut self) -> Result<(), HttpNetError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.processed.extend(self.pending.drain(..));
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let content: String = self
+            .processed
+            .iter()
+            .map(|(relay, cursor)| format!("{} {}\n", relay, cursor))
+            .collect();
+        // a partially written file would lose the positions
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
 }
 
 impl HttpNetListen {
+    // With several relays, the node reads with a cursor in memory, because only the messages
+    // of a cursor read carry the origin which tells the messages returned by every relay apart.
     pub fn new(net: HttpNet, in_queue: Vec<Message>) -> Self {
-        HttpNetListen { net, in_queue }
+        let cursor = (net.relays.len() > 1).then(CursorFile::in_memory);
+        HttpNetListen {
+            net,
+            in_queue: in_queue.into_iter().map(|msg| (msg, Vec::new())).collect(),
+            cursor,
+            seen: HashSet::new(),
+            seen_order: VecDeque::new(),
+        }
+    }
+
+    // Reads with a cursor kept in `path` instead of moving the read position on the relay,
+    // so a restarted node receives again every message it had not processed.
+    pub fn with_cursor_file(mut self, path: impl Into<PathBuf>) -> Result<Self, HttpNetError> {
+        self.cursor = Some(CursorFile::load(path.into())?);
+        Ok(self)
+    }
+
+    // uses `relay_cursor_file` of the node `id` if it is configured
+    pub fn from_config(net: HttpNet, config: &Config, id: u32) -> Result<Self, HttpNetError> {
+        let listen = HttpNetListen::new(net, vec![]);
+        match config.relay_cursor_file(id) {
+            Some(path) => listen.with_cursor_file(path),
+            None => Ok(listen),
+        }
+    }
+
+    fn commit(&mut self) -> Result<(), HttpNetError> {
+        match &mut self.cursor {
+            Some(cursor) => cursor.commit(),
+            None => Ok(()),
+        }
+    }
+
+    // With several relays a message can arrive more than once. Every relay returns it with the
+    // same origin, while a message which is posted again gets a new one.
+    fn is_duplicate(&mut self, message: &Indexed) -> bool {
+        let origin = match &message.origin {
+            Some(origin) => (message.direct, origin.clone()),
+            None => return false,
+        };
+        if !self.seen.insert(origin.clone()) {
+            return true;
+        }
+        self.seen_order.push_back(origin);
+        if self.seen_order.len() > SEEN_MESSAGES {
+            if let Some(oldest) = self.seen_order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        false
+    }
+
+    // `bytes` is `None` for a message which is skipped
+    fn push(&mut self, bytes: Option<&[u8]>, cursor: Option<(String, Cursor)>, url: &str) {
+        let msg = bytes.and_then(|bytes| match bincode::deserialize::<Message>(bytes) {
+            Ok(msg) => Some(msg),
+            Err(e) => {
+                warn!("invalid message from {}: {}", url, e);
+                None
+            }
+        });
+        match msg {
+            Some(msg) => {
+                debug!("received {:?}", msg);
+                self.in_queue.push_back((msg, cursor.into_iter().collect()));
+            }
+            // a skipped message counts as processed with the one before it
+            None => {
+                if let (Some(file), Some(cursor)) = (&mut self.cursor, cursor) {
+                    match self.in_queue.back_mut() {
+                        Some((_, cursors)) => cursors.push(cursor),
+                        None => file.pending.push(cursor),
+                    }
+                }
+            }
+        }
     }
 }
 
@@ -27,11 +202,94 @@ impl HttpNetListen {
 #[derive(Clone)]
 pub struct HttpNet {
     pub http_relay_url: String,
+    // the relays of a replicated cluster, `http_relay_url` first
+    relays: Vec<String>,
+    // the index of the relay which is used, shared by the clones
+    healthy: Arc<AtomicUsize>,
+    agent: ureq::Agent,
+    // signs the requests for a relay which requires authentication
+    credentials: Option<Credentials>,
 }
 
 impl HttpNet {
     pub fn new(http_relay_url: String) -> Self {
-        HttpNet { http_relay_url }
+        HttpNet {
+            relays: vec![http_relay_url.clone()],
+            http_relay_url,
+            healthy: Arc::new(AtomicUsize::new(0)),
+            agent: ureq::Agent::new(),
+            credentials: None,
+        }
+    }
+
+    // Further relays of a replicated cluster. A relay is used until it fails, then the next.
+    pub fn with_relays(mut self, relays: Vec<String>) -> Self {
+        self.relays.extend(relays);
+        self
+    }
+
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    // an `https://` relay is only trusted with a certificate signed by a CA of `tls`
+    pub fn with_tls(mut self, tls: Arc<ClientConfig>) -> Self {
+        self.agent = ureq::AgentBuilder::new().tls_config(tls).build();
+        self
+    }
+
+    // the node `id` signs its requests with `relay_private_key` if it is configured,
+    // and trusts the relay with `relay_ca`
+    pub fn from_config(config: &Config, id: u32) -> Result<Self, HttpNetError> {
+        let mut net =
+            HttpNet::new(config.http_relay_url.clone()).with_relays(config.http_relay_urls.clone());
+        if let Some(key) = &config.relay_private_key {
+            net = net.with_credentials(Credentials::new(id.to_string(), key)?);
+        }
+        if let Some(ca) = &config.relay_ca {
+            let identity = match (&config.relay_client_cert, &config.relay_client_key) {
+                (Some(cert), Some(key)) => Some((fs::read(cert)?, fs::read(key)?)),
+                _ => None,
+            };
+            let tls = client_config(
+                &fs::read(ca)?,
+                identity
+                    .as_ref()
+                    .map(|(cert, key)| (cert.as_slice(), key.as_slice())),
+            )?;
+            net = net.with_tls(tls);
+        }
+        Ok(net)
+    }
+
+    // the relay which is used
+    fn relay(&self) -> &str {
+        &self.relays[self.healthy.load(Ordering::Relaxed) % self.relays.len()]
+    }
+
+    // moves on to the relay after `relay`, unless another thread already did
+    fn fail_over(&self, relay: &str) {
+        let current = self.healthy.load(Ordering::Relaxed) % self.relays.len();
+        if self.relays[current] == relay {
+            let next = (current + 1) % self.relays.len();
+            if next != current {
+                warn!("relay {} failed, using {}", relay, self.relays[next]);
+            }
+            let _ =
+                self.healthy
+                    .compare_exchange(current, next, Ordering::Relaxed, Ordering::Relaxed);
+        }
+    }
+
+    fn request(&self, method: &str, url: &str, content: &[u8]) -> ureq::Request {
+        let mut request = self.agent.request(method, url);
+        if let Some(credentials) = &self.credentials {
+            for (name, value) in credentials.headers(method, url, content) {
+                request = request.set(name, &value);
+            }
+        }
+        request
     }
 }
 
@@ -40,9 +298,20 @@ pub trait NetListen {
     type Error: Debug;
 
     fn listen(&self);
-    fn poll(&mut self, id: u32);
+    fn poll(&mut self, id: u32) -> Result<(), Self::Error>;
     fn next_message(&mut self) -> Option<Message>;
     fn send_message(&self, msg: Message) -> Result<(), Self::Error>;
+
+    // Marks the messages taken by `next_message` so far as processed. A transport which keeps
+    // a read position saves it, the messages which are not acknowledged are received again.
+    fn ack(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    // sends a message to a single node, see `Net::send_message_to`
+    fn send_message_to(&self, _id: u32, msg: Message) -> Result<(), Self::Error> {
+        self.send_message(msg)
+    }
 }
 
 impl NetListen for HttpNetListen {
@@ -50,34 +319,86 @@ impl NetListen for HttpNetListen {
 
     fn listen(&self) {}
 
-    fn poll(&mut self, id: u32) {
-        let url = url_with_id(&self.net.http_relay_url, id);
+    // The relay answers `204 No Content` when there is no message for us, any other
+    // failure is returned. The next poll uses the next relay if the relay is down.
+    fn poll(&mut self, id: u32) -> Result<(), Self::Error> {
+        let relay = self.net.relay().to_string();
+        let query = match &self.cursor {
+            Some(file) => query_with_cursor(id, &file.processed(&relay)),
+            None => query_with_id(id),
+        };
+        let url = format!("{}{}", relay, query);
         debug!("poll {}", url);
-        match ureq::get(&url).call() {
-            Ok(response) => {
-                if response.status() == 200 {
-                    match bincode::deserialize_from::<_, Message>(response.into_reader()) {
-                        Ok(msg) => {
-                            debug!("received {:?}", msg);
-                            self.in_queue.push(msg);
+        // give the relay enough time to answer a long poll
+        let result = self
+            .net
+            .request("GET", &url, &[])
+            .timeout(POLL_WAIT * 2)
+            .call()
+            .map_err(relay_error);
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                if e.is_transient() {
+                    self.net.fail_over(&relay);
+                }
+                return Err(e);
+            }
+        };
+        if response.status() == 204 {
+            return Ok(());
+        }
+        let mut content = Vec::new();
+        response.into_reader().read_to_end(&mut content)?;
+        if self.cursor.is_some() {
+            for message in relay_server::decode_indexed(&content)? {
+                let cursor = match &mut self.cursor {
+                    Some(file) => {
+                        let received = file.received.entry(relay.clone()).or_default();
+                        // still queued from an earlier poll
+                        if !received.is_before(&message) {
+                            continue;
                         }
-                        Err(_e) => {}
-                    };
+                        received.advance(&message);
+                        *received
+                    }
+                    None => continue,
+                };
+                let bytes = if self.is_duplicate(&message) {
+                    debug!("duplicate message {:?} from {}", message.origin, url);
+                    None
+                } else {
+                    Some(&message.msg[..])
                 };
+                self.push(bytes, Some((relay.clone(), cursor)), &url);
             }
-            Err(e) => {
-                warn!("{} U: {}", e, url)
+        } else {
+            for bytes in relay_server::decode_batch(&content)? {
+                self.push(Some(&bytes), None, &url);
             }
-        };
+        }
+        Ok(())
     }
     fn next_message(&mut self) -> Option<Message> {
-        self.in_queue.pop()
+        let (msg, cursors) = self.in_queue.pop_front()?;
+        if let Some(file) = &mut self.cursor {
+            file.pending.extend(cursors);
+        }
+        Some(msg)
+    }
+
+    fn ack(&mut self) -> Result<(), Self::Error> {
+        self.commit()
     }
 
     // pass-thru to immutable net function
     fn send_message(&self, msg: Message) -> Result<(), Self::Error> {
         self.net.send_message(msg)
     }
+
+    fn send_message_to(&self, id: u32, msg: Message) -> Result<(), Self::Error> {
+        self.net.send_message_to(id, msg)
+    }
 }
 
 // for threads that only send data, use immutable Net
@@ -85,34 +406,104 @@ pub trait Net {
     type Error: Debug;
 
     fn send_message(&self, msg: Message) -> Result<(), Self::Error>;
+
+    // Sends a message to a single node. Transports without directed delivery broadcast it
+    // and every other node ignores what is not meant for it.
+    fn send_message_to(&self, _id: u32, msg: Message) -> Result<(), Self::Error> {
+        self.send_message(msg)
+    }
+}
+
+impl HttpNet {
+    // Posts to the relay which is used. With a replicated cluster, the message reaches the
+    // other relays from there, so the next relay is only tried if the relay is down.
+    fn post(&self, query: &str, msg: &Message) -> Result<(), HttpNetError> {
+        let bytes = bincode::serialize(msg)?;
+        let mut result = Ok(());
+        for _ in 0..self.relays.len() {
+            let relay = self.relay().to_string();
+            let url = format!("{}{}", relay, query);
+            match self.request("POST", &url, &bytes).send_bytes(&bytes[..]) {
+                Ok(response) => {
+                    debug!(
+                        "sent {:?} {} bytes {:?} to {}",
+                        &msg.msg,
+                        bytes.len(),
+                        &response,
+                        url
+                    );
+                    return Ok(());
+                }
+                Err(e) => {
+                    info!("post failed to {} {}", url, e);
+                    let e = relay_error(e);
+                    if !e.is_transient() {
+                        return Err(e);
+                    }
+                    self.fail_over(&relay);
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
 }
 
 impl Net for HttpNet {
     type Error = HttpNetError;
 
     fn send_message(&self, msg: Message) -> Result<(), Self::Error> {
-        let req = ureq::post(&self.http_relay_url);
-        let bytes = bincode::serialize(&msg)?;
-        let result = req.send_bytes(&bytes[..]);
-
-        match result {
-            Ok(response) => {
-                debug!(
-                    "sent {:?} {} bytes {:?} to {}",
-                    &msg.msg,
-                    bytes.len(),
-                    &response,
-                    self.http_relay_url
-                )
-            }
-            Err(e) => {
-                info!("post failed to {} {}", self.http_relay_url, e);
-                return Err(Box::new(e).into());
+        for (to, msg) in route(msg) {
+            match to {
+                Some(id) => self.send_message_to(id, msg)?,
+                None => self.post("", &msg)?,
             }
-        };
-
+        }
         Ok(())
     }
+
+    fn send_message_to(&self, id: u32, msg: Message) -> Result<(), Self::Error> {
+        self.post(&query_with_to(id), &msg)
+    }
+}
+
+// Splits a message into the messages each node needs, `None` is for all nodes.
+// Private shares and signature share requests only go to the signers which hold the parties.
+pub fn route(msg: Message) -> Vec<(Option<u32>, Message)> {
+    let sig = msg.sig;
+    match msg.msg {
+        MessageTypes::SignShareRequest(request) => {
+            let id = signer_id_for_party(request.party_id);
+            vec![(
+                Some(id),
+                Message {
+                    msg: MessageTypes::SignShareRequest(request),
+                    sig,
+                },
+            )]
+        }
+        MessageTypes::DkgPrivateShares(shares) => {
+            let mut shares_by_signer: BTreeMap<u32, KeyShares> = BTreeMap::new();
+            for (party_id, share) in shares.private_shares {
+                shares_by_signer
+                    .entry(signer_id_for_party(party_id as u32))
+                    .or_default()
+                    .insert(party_id, share);
+            }
+            shares_by_signer
+                .into_iter()
+                .map(|(id, private_shares)| {
+                    let msg = MessageTypes::DkgPrivateShares(DkgPrivateShares {
+                        dkg_id: shares.dkg_id,
+                        party_id: shares.party_id,
+                        private_shares,
+                    });
+                    (Some(id), Message { msg, sig })
+                })
+                .collect()
+        }
+        msg => vec![(None, Message { msg, sig })],
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -129,8 +520,38 @@ pub enum HttpNetError {
     #[error("Send Error")]
     SendError,
 
+    #[error("IO Error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Unknown peer: {0}")]
+    UnknownPeer(u32),
+
     #[error("DKG Error: {0}")]
     DKGError(String),
+
+    #[error("Relay error {0}: {1}")]
+    RelayError(u16, String),
+}
+
+impl HttpNetError {
+    // errors which may go away when the request is repeated
+    pub fn is_transient(&self) -> bool {
+        match self {
+            HttpNetError::NetworkError(_) | HttpNetError::IoError(_) => true,
+            HttpNetError::RelayError(code, _) => *code >= 500,
+            _ => false,
+        }
+    }
+}
+
+// a response with an error status carries the relay's explanation
+fn relay_error(e: ureq::Error) -> HttpNetError {
+    match e {
+        ureq::Error::Status(code, response) => {
+            HttpNetError::RelayError(code, response.into_string().unwrap_or_default())
+        }
+        e => Box::new(e).into(),
+    }
 }
 
 impl From<mpsc::SendError<Message>> for HttpNetError {
@@ -139,8 +560,14 @@ impl From<mpsc::SendError<Message>> for HttpNetError {
     }
 }
 
-fn url_with_id(base: &str, id: u32) -> String {
-    let mut url = base.to_owned();
-    url.push_str(&format!("?id={}", id));
-    url
+fn query_with_id(id: u32) -> String {
+    format!("?id={}&batch&wait={}", id, POLL_WAIT.as_millis())
+}
+
+fn query_with_cursor(id: u32, cursor: &Cursor) -> String {
+    format!("?id={}&{}&wait={}", id, cursor, POLL_WAIT.as_millis())
+}
+
+fn query_with_to(id: u32) -> String {
+    format!("?to={}", id)
 }
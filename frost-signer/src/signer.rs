@@ -1,11 +1,12 @@
-use crate::config::Config;
-use crate::net::{HttpNet, HttpNetError as Error, HttpNetListen, Message, Net, NetListen};
+use crate::config::{Config, Transport};
+use crate::net::{
+    HttpNet, HttpNetError as Error, HttpNetListen, Message, NetListen, POLL_RETRY_DELAY,
+};
 use crate::signing_round::SigningRound;
+use crate::tcp_net::TcpNetListen;
 use serde::Deserialize;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
-use std::thread::spawn;
-use std::{thread, time};
+use std::thread;
+use tracing::warn;
 
 // on-disk format for frost save data
 #[derive(Clone, Deserialize, Default, Debug)]
@@ -20,46 +21,46 @@ impl Signer {
     }
 
     pub fn start_p2p_sync(&mut self) -> Result<(), Error> {
-        //Create http relay
-        let net: HttpNet = HttpNet::new(self.config.http_relay_url.clone());
-        let net_queue = HttpNetListen::new(net.clone(), vec![]);
-        // thread coordination
-        let (tx, rx): (Sender<Message>, Receiver<Message>) = mpsc::channel();
-
-        // start p2p sync
-        let id = self.frost_id;
-        spawn(move || poll_loop(net_queue, tx, id));
-
-        // listen to p2p messages
-        self.start_signing_round(&net, rx)
+        match self.config.transport {
+            Transport::Http => {
+                //Create http relay
+                let net: HttpNet = HttpNet::from_config(&self.config, self.frost_id)?;
+                let net_queue = HttpNetListen::from_config(net, &self.config, self.frost_id)?;
+                self.start(net_queue)
+            }
+            Transport::Tcp => {
+                //Connect directly to the peers
+                let net_queue = TcpNetListen::from_config(&self.config, self.frost_id)?;
+                self.start(net_queue)
+            }
+        }
     }
 
-    fn start_signing_round(&self, net: &HttpNet, rx: Receiver<Message>) -> Result<(), Error> {
+    // Every message is acknowledged once it is processed and the answers are sent, so a
+    // signer which stops before receives it again.
+    fn start(&self, mut net: impl NetListen<Error = Error>) -> Result<(), Error> {
         let mut round = SigningRound::from(self);
 
         loop {
-            let inbound = rx.recv()?; // blocking
-            let outbounds = round.process(inbound.msg).map_err(Error::DKGError)?;
-            for out in outbounds {
-                let msg = Message {
-                    msg: out,
-                    sig: [0; 32],
-                };
-                net.send_message(msg)?;
+            // blocks until there are messages for us or the poll times out
+            if let Err(e) = net.poll(self.frost_id) {
+                if !e.is_transient() {
+                    return Err(e);
+                }
+                warn!("poll failed, retrying: {}", e);
+                thread::sleep(POLL_RETRY_DELAY);
             }
-        }
-    }
-}
-
-fn poll_loop(mut net: HttpNetListen, tx: Sender<Message>, id: u32) -> Result<(), Error> {
-    loop {
-        net.poll(id);
-        match net.next_message() {
-            None => {}
-            Some(m) => {
-                tx.send(m)?;
+            while let Some(inbound) = net.next_message() {
+                let outbounds = round.process(inbound.msg).map_err(Error::DKGError)?;
+                for out in outbounds {
+                    let msg = Message {
+                        msg: out,
+                        sig: [0; 32],
+                    };
+                    net.send_message(msg)?;
+                }
+                net.ack()?;
             }
-        };
-        thread::sleep(time::Duration::from_millis(500));
+        }
     }
 }
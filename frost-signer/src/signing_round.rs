@@ -1,4 +1,5 @@
 use crate::signer::Signer as FrostSigner;
+use crate::taproot;
 use hashbrown::HashMap;
 use rand_core::OsRng;
 use serde::{Deserialize, Serialize};
@@ -7,12 +8,21 @@ use tracing::{debug, info, warn};
 pub use wtfrost;
 use wtfrost::{
     common::{PolyCommitment, PublicNonce},
-    v1, Scalar,
+    v1, Point, Scalar,
 };
 
 use crate::state_machine::{StateMachine, States};
 
-type KeyShares = HashMap<usize, Scalar>;
+pub type KeyShares = HashMap<usize, Scalar>;
+
+// every signer holds two parties
+pub fn party_ids_for_signer(signer_id: u32) -> Vec<usize> {
+    vec![(signer_id * 2 - 2) as usize, (signer_id * 2 - 1) as usize]
+}
+
+pub fn signer_id_for_party(party_id: u32) -> u32 {
+    party_id / 2 + 1
+}
 
 pub struct SigningRound {
     pub dkg_id: u64,
@@ -23,6 +33,9 @@ pub struct SigningRound {
     pub commitments: BTreeMap<u32, PolyCommitment>,
     pub shares: HashMap<u32, HashMap<usize, Scalar>>,
     pub public_nonces: Vec<PublicNonce>,
+    pub group_key: Point,
+    pub private_keys: HashMap<usize, Scalar>,
+    pub nonces: HashMap<usize, taproot::Nonce>,
 }
 
 pub struct Signer {
@@ -128,7 +141,7 @@ pub struct SignatureShareResponse {
     pub dkg_id: u64,
     pub correlation_id: u64,
     pub party_id: u32,
-    pub signature_share: wtfrost::v1::SignatureShare,
+    pub signature_share: Scalar,
 }
 
 impl SigningRound {
@@ -155,6 +168,9 @@ impl SigningRound {
             commitments: BTreeMap::new(),
             shares: HashMap::new(),
             public_nonces: vec![],
+            group_key: Point::default(),
+            private_keys: HashMap::new(),
+            nonces: HashMap::new(),
         }
     }
 
@@ -162,6 +178,8 @@ impl SigningRound {
         self.dkg_id = dkg_id;
         self.commitments.clear();
         self.shares.clear();
+        self.private_keys.clear();
+        self.nonces.clear();
     }
 
     pub fn process(&mut self, message: MessageTypes) -> Result<Vec<MessageTypes>, String> {
@@ -217,11 +235,21 @@ impl SigningRound {
                 party.id,
                 shares.keys()
             );
-            if let Err(secret_error) = party.compute_secret(shares, &commitments) {
-                warn!(
+            // the key share of the party, which signs for the taproot output key
+            let private_key = shares
+                .values()
+                .fold(Scalar::new(), |key, share| key + *share);
+            match party.compute_secret(shares, &commitments) {
+                Ok(_) => {
+                    self.private_keys.insert(party.id, private_key);
+                    self.group_key = commitments
+                        .iter()
+                        .fold(Point::default(), |key, commitment| key + commitment.A[0]);
+                }
+                Err(secret_error) => warn!(
                     "DKG round #{}: party {} compute_secret failed in : {}",
                     self.dkg_id, party.id, secret_error
-                );
+                ),
             }
         }
         let dkg_end = MessageTypes::DkgEnd(DkgEnd {
@@ -257,12 +285,14 @@ impl SigningRound {
     ) -> Result<Vec<MessageTypes>, String> {
         let mut rng = OsRng::default();
         let mut msgs = vec![];
-        for party in &mut self.signer.frost_signer.parties {
+        for party in &self.signer.frost_signer.parties {
+            let nonce = taproot::Nonce::random(&mut rng);
             let response = MessageTypes::NonceResponse(NonceResponse {
                 dkg_id: nonce_request.dkg_id,
                 party_id: party.id as u32,
-                nonce: party.gen_nonce(&mut rng),
+                nonce: nonce.public(),
             });
+            self.nonces.insert(party.id, nonce);
             info!(
                 "nonce request with dkg_id {:?}. response sent from party_id {}",
                 nonce_request.dkg_id, party.id
@@ -281,22 +311,17 @@ impl SigningRound {
             .party_id
             .try_into()
             .map_err(|_| "Invalid party id")?;
-        if let Some(party) = self
-            .signer
-            .frost_signer
-            .parties
-            .iter()
-            .find(|p| p.id == party_id)
-        {
-            //let party_nonces = &self.public_nonces;
-            let signer_ids: Vec<usize> = sign_request
-                .nonces
-                .iter()
-                .map(|(id, _)| *id as usize)
-                .collect();
-            let signer_nonces: Vec<PublicNonce> =
-                sign_request.nonces.iter().map(|(_, n)| n.clone()).collect();
-            let share = party.sign(&sign_request.message, &signer_ids, &signer_nonces);
+        // a nonce signs a single message
+        let nonce = self.nonces.remove(&party_id);
+        if let (Some(private_key), Some(nonce)) = (self.private_keys.get(&party_id), nonce) {
+            let share = taproot::signature_share(
+                sign_request.party_id,
+                private_key,
+                &nonce,
+                &self.group_key,
+                &sign_request.nonces,
+                &sign_request.message,
+            );
 
             let response = MessageTypes::SignShareResponse(SignatureShareResponse {
                 dkg_id: sign_request.dkg_id,
@@ -378,7 +403,7 @@ impl From<&FrostSigner> for SigningRound {
     fn from(signer: &FrostSigner) -> Self {
         let signer_id = signer.frost_id;
         assert!(signer_id > 0 && signer_id as usize <= signer.config.max_party_id);
-        let party_ids = vec![(signer_id * 2 - 2) as usize, (signer_id * 2 - 1) as usize]; // make two party_ids based on signer_id
+        let party_ids = party_ids_for_signer(signer_id); // make two party_ids based on signer_id
 
         assert!(signer.config.keys_threshold <= signer.config.total_keys);
         let mut rng = OsRng::default();
@@ -401,6 +426,9 @@ impl From<&FrostSigner> for SigningRound {
             commitments: BTreeMap::new(),
             shares: HashMap::new(),
             public_nonces: vec![],
+            group_key: Point::default(),
+            private_keys: HashMap::new(),
+            nonces: HashMap::new(),
         }
     }
 }
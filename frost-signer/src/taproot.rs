@@ -0,0 +1,237 @@
+//! BIP-340 signatures of the taproot output key of a FROST aggregate key.
+//!
+//! The peg wallet is a taproot output without a script tree. Its output key `Q` is the
+//! aggregate key `P` with an even `y`, tweaked with `t = hash_TapTweak(P)` as BIP-341
+//! describes. A BIP-340 signature of `Q` has an even `R`, the challenge
+//! `e = hash_BIP0340/challenge(R || Q || m)` and `z = k + e * q`, where `q` is the secret key of
+//! `Q` with an even `y`. Every party negates its nonce when `R` is odd and its key share when
+//! the parity of `P` or `Q` requires it, the aggregator adds `e * t`.
+
+use rand_core::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256};
+use wtfrost::{common::PublicNonce, Point, Scalar};
+
+/// A BIP-340 signature, the x coordinate of `R` and `z`
+pub type SchnorrSignature = [u8; 64];
+
+/// The secret nonce of a party, which signs a single message
+pub struct Nonce {
+    d: Scalar,
+    e: Scalar,
+}
+
+impl Nonce {
+    pub fn random<RNG: RngCore + CryptoRng>(rng: &mut RNG) -> Self {
+        Self {
+            d: Scalar::random(rng),
+            e: Scalar::random(rng),
+        }
+    }
+
+    pub fn public(&self) -> PublicNonce {
+        PublicNonce {
+            D: Point::from(&self.d),
+            E: Point::from(&self.e),
+        }
+    }
+}
+
+/// The output key of an aggregate key, with what a signature of it needs
+struct OutputKey {
+    // the output key with an even y
+    key: Point,
+    // the sign of the key shares in the secret key of `key`
+    key_sign: Scalar,
+    // the part of the secret key of `key` which isn't shared
+    tweak: Scalar,
+}
+
+impl OutputKey {
+    fn new(aggregate_key: &Point) -> Self {
+        let tweak = Scalar::from(tagged_hash("TapTweak", &[&x_only(aggregate_key)]));
+        let internal_sign = parity(aggregate_key);
+        let key = internal_sign * *aggregate_key + Point::from(&tweak);
+        let output_sign = parity(&key);
+        Self {
+            key: output_sign * key,
+            key_sign: output_sign * internal_sign,
+            tweak: output_sign * tweak,
+        }
+    }
+}
+
+/// The x coordinate of the output key of an aggregate key, which a taproot output pays
+pub fn output_key(aggregate_key: &Point) -> [u8; 32] {
+    x_only(&OutputKey::new(aggregate_key).key)
+}
+
+/// The signature share of a party for `msg`. `nonces` are the public nonces of all the
+/// signing parties, `private_key` the key share of the party from the DKG round.
+pub fn signature_share(
+    party_id: u32,
+    private_key: &Scalar,
+    nonce: &Nonce,
+    aggregate_key: &Point,
+    nonces: &[(u32, PublicNonce)],
+    msg: &[u8],
+) -> Scalar {
+    let output_key = OutputKey::new(aggregate_key);
+    let (r, r_sign) = aggregate_nonce(nonces, msg);
+    let e = challenge(&x_only(&r), &output_key.key, msg);
+    let party_ids: Vec<u32> = nonces.iter().map(|(id, _)| *id).collect();
+    let lambda = lambda(party_id, &party_ids);
+    r_sign * (nonce.d + binding(party_id, nonces, msg) * nonce.e)
+        + e * output_key.key_sign * lambda * *private_key
+}
+
+/// Aggregates the signature shares of the parties, which signed with `nonces`
+pub fn aggregate(
+    aggregate_key: &Point,
+    nonces: &[(u32, PublicNonce)],
+    shares: &[Scalar],
+    msg: &[u8],
+) -> SchnorrSignature {
+    let output_key = OutputKey::new(aggregate_key);
+    let (r, _) = aggregate_nonce(nonces, msg);
+    let r = x_only(&r);
+    let e = challenge(&r, &output_key.key, msg);
+    let z = shares
+        .iter()
+        .fold(e * output_key.tweak, |z, share| z + *share);
+
+    let mut signature = [0; 64];
+    signature[..32].copy_from_slice(&r);
+    signature[32..].copy_from_slice(&z.to_bytes());
+    signature
+}
+
+/// Whether a signature is a BIP-340 signature of `msg` by the output key of an aggregate key
+pub fn verify(aggregate_key: &Point, msg: &[u8], signature: &SchnorrSignature) -> bool {
+    let output_key = OutputKey::new(aggregate_key);
+    let mut r = [0; 32];
+    r.copy_from_slice(&signature[..32]);
+    let mut z = [0; 32];
+    z.copy_from_slice(&signature[32..]);
+    let e = challenge(&r, &output_key.key, msg);
+    let expected = Point::from(&Scalar::from(z)) - e * output_key.key;
+    parity(&expected) == Scalar::from(1u32) && x_only(&expected) == r
+}
+
+// The nonce of the signature, with an even y, and the sign of the nonces of the parties in it
+fn aggregate_nonce(nonces: &[(u32, PublicNonce)], msg: &[u8]) -> (Point, Scalar) {
+    let r = nonces.iter().fold(Point::default(), |r, (id, nonce)| {
+        r + nonce.D + binding(*id, nonces, msg) * nonce.E
+    });
+    let r_sign = parity(&r);
+    (r_sign * r, r_sign)
+}
+
+// The binding factor of a party, which ties its nonce to the message and the other nonces
+fn binding(party_id: u32, nonces: &[(u32, PublicNonce)], msg: &[u8]) -> Scalar {
+    let mut commitments = Vec::new();
+    for (id, nonce) in nonces {
+        commitments.extend_from_slice(&id.to_be_bytes());
+        commitments.extend_from_slice(nonce.D.compress().as_bytes());
+        commitments.extend_from_slice(nonce.E.compress().as_bytes());
+    }
+    Scalar::from(tagged_hash(
+        "FROST/binding",
+        &[&party_id.to_be_bytes(), &commitments, msg],
+    ))
+}
+
+fn challenge(r: &[u8; 32], output_key: &Point, msg: &[u8]) -> Scalar {
+    Scalar::from(tagged_hash(
+        "BIP0340/challenge",
+        &[r, &x_only(output_key), msg],
+    ))
+}
+
+// The Lagrange coefficient of a party, whose key share is the polynomial at `party_id + 1`
+fn lambda(party_id: u32, party_ids: &[u32]) -> Scalar {
+    let i = Scalar::from(party_id + 1);
+    party_ids
+        .iter()
+        .filter(|id| **id != party_id)
+        .fold(Scalar::from(1u32), |lambda, id| {
+            let j = Scalar::from(id + 1);
+            lambda * (j / (j - i))
+        })
+}
+
+// 1 for a point with an even y, -1 otherwise
+fn parity(point: &Point) -> Scalar {
+    if point.compress().as_bytes()[0] == 2 {
+        Scalar::from(1u32)
+    } else {
+        Scalar::new() - Scalar::from(1u32)
+    }
+}
+
+fn x_only(point: &Point) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    bytes.copy_from_slice(&point.compress().as_bytes()[1..]);
+    bytes
+}
+
+/// The tagged hash of BIP-340
+pub fn tagged_hash(tag: &str, chunks: &[&[u8]]) -> [u8; 32] {
+    let tag = Sha256::digest(tag.as_bytes());
+    let mut hasher = Sha256::new();
+    hasher.update(tag);
+    hasher.update(tag);
+    for chunk in chunks {
+        hasher.update(chunk);
+    }
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand_core::OsRng;
+
+    use super::*;
+
+    #[test]
+    fn threshold_signature_verifies() {
+        let mut rng = OsRng::default();
+        // keys and nonces of either parity
+        for _ in 0..8 {
+            // a key shared by 3 parties with a threshold of 2
+            let (a0, a1) = (Scalar::random(&mut rng), Scalar::random(&mut rng));
+            let aggregate_key = Point::from(&a0);
+            let msg = "sighash".as_bytes();
+            for party_ids in [vec![0, 1], vec![1, 2], vec![0, 1, 2]] {
+                let nonces: Vec<_> = party_ids
+                    .iter()
+                    .map(|id| (*id, Nonce::random(&mut rng)))
+                    .collect();
+                let public_nonces: Vec<_> = nonces
+                    .iter()
+                    .map(|(id, nonce)| (*id, nonce.public()))
+                    .collect();
+                let shares: Vec<_> = nonces
+                    .iter()
+                    .map(|(id, nonce)| {
+                        let private_key = a0 + a1 * Scalar::from(id + 1);
+                        signature_share(
+                            *id,
+                            &private_key,
+                            nonce,
+                            &aggregate_key,
+                            &public_nonces,
+                            msg,
+                        )
+                    })
+                    .collect();
+                let signature = aggregate(&aggregate_key, &public_nonces, &shares, msg);
+                assert!(verify(&aggregate_key, msg, &signature));
+                assert!(!verify(
+                    &aggregate_key,
+                    "another message".as_bytes(),
+                    &signature
+                ));
+            }
+        }
+    }
+}
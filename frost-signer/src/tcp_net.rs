@@ -0,0 +1,325 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, RecvError, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, spawn};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+use crate::net::{route, HttpNetError, Message, Net, NetListen};
+
+// how long a poll waits for a message before giving up
+const POLL_WAIT: Duration = Duration::from_secs(1);
+// how long to wait for a peer to accept a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+// how long to wait for a peer to acknowledge a frame
+const ACK_TIMEOUT: Duration = Duration::from_secs(3);
+// the byte a node answers every frame with once it has queued the message
+const ACK: u8 = 1;
+// frames are length prefixed, reject lengths no message can have
+const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+// Direct TCP send (does not require mutable access, can be cloned to pass to threads).
+// Every message is written as a big-endian u32 length followed by the bincode message, and
+// the peer acknowledges it with a single byte. Messages from one peer arrive in the order
+// they were sent, there is no order between peers.
+#[derive(Clone)]
+pub struct TcpNet {
+    pub id: u32,
+    peers: Arc<HashMap<u32, Connection>>,
+    // messages a node sends to itself never leave the process
+    loopback: Sender<Message>,
+}
+
+// A persistent connection to a peer, (re)connected when a send needs it. A connection which
+// the peer has closed still accepts writes for a while, the missing acknowledgement tells.
+// Sends to a peer take turns, so its frames are only ever written to one connection.
+struct Connection {
+    address: String,
+    stream: Mutex<Option<TcpStream>>,
+}
+
+impl Connection {
+    fn new(address: String) -> Self {
+        Connection {
+            address,
+            stream: Mutex::new(None),
+        }
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no address");
+        for addr in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    stream.set_read_timeout(Some(ACK_TIMEOUT))?;
+                    debug!("connected to {}", self.address);
+                    return Ok(stream);
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    // The lock is held until the peer acknowledged the frame, so a concurrent send to the
+    // same peer waits instead of overtaking it on another connection. A peer which is down
+    // only delays the sends to it.
+    fn send(&self, frame: &[u8]) -> io::Result<()> {
+        let mut stream = self.lock();
+        // an existing connection may have been closed by the peer, reconnect once
+        if let Some(s) = stream.as_mut() {
+            match write_frame(s, frame) {
+                Ok(()) => return Ok(()),
+                Err(e) if is_closed(&e) => {
+                    info!("connection to {} lost, reconnecting: {}", self.address, e)
+                }
+                Err(e) => {
+                    // the peer may still answer the frame, the connection can't be reused
+                    *stream = None;
+                    return Err(e);
+                }
+            }
+        }
+        *stream = None;
+        let mut s = self.connect()?;
+        write_frame(&mut s, frame)?;
+        *stream = Some(s);
+        Ok(())
+    }
+
+    // a panic while holding the lock leaves at worst a broken stream, which is replaced
+    fn lock(&self) -> MutexGuard<Option<TcpStream>> {
+        self.stream.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// writes a frame and waits until the peer acknowledges it
+fn write_frame(stream: &mut TcpStream, frame: &[u8]) -> io::Result<()> {
+    stream.write_all(frame)?;
+    let mut ack = [0; 1];
+    stream.read_exact(&mut ack)?;
+    if ack[0] != ACK {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid acknowledgement",
+        ));
+    }
+    Ok(())
+}
+
+// Whether the peer closed the connection before it received the frame, so it can be sent
+// again. After a timeout, the peer may have received it.
+fn is_closed(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+impl TcpNet {
+    fn frame(msg: &Message) -> Result<Vec<u8>, HttpNetError> {
+        let bytes = bincode::serialize(msg)?;
+        let mut frame = (bytes.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&bytes);
+        Ok(frame)
+    }
+
+    // sends to all peers at the same time, so the peers which are down delay a broadcast
+    // only once by the time to connect
+    fn broadcast(&self, msg: Message) -> Result<(), HttpNetError> {
+        let frame = Self::frame(&msg)?;
+        thread::scope(|scope| {
+            for (id, peer) in self.peers.iter() {
+                let frame = &frame;
+                scope.spawn(move || {
+                    if let Err(e) = peer.send(frame) {
+                        warn!("send to peer #{} at {} failed: {}", id, peer.address, e);
+                    }
+                });
+            }
+        });
+        debug!("broadcast {:?} {} bytes", &msg.msg, frame.len());
+        self.loopback.send(msg)?;
+        Ok(())
+    }
+}
+
+impl Net for TcpNet {
+    type Error = HttpNetError;
+
+    // A peer that can not be reached misses the message, the others still get it.
+    fn send_message(&self, msg: Message) -> Result<(), Self::Error> {
+        for (to, msg) in route(msg) {
+            match to {
+                Some(id) => {
+                    if let Err(e) = self.send_message_to(id, msg) {
+                        warn!("send to peer #{} failed: {}", id, e);
+                    }
+                }
+                None => self.broadcast(msg)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn send_message_to(&self, id: u32, msg: Message) -> Result<(), Self::Error> {
+        if id == self.id {
+            self.loopback.send(msg)?;
+            return Ok(());
+        }
+        let peer = self.peers.get(&id).ok_or(HttpNetError::UnknownPeer(id))?;
+        let frame = Self::frame(&msg)?;
+        peer.send(&frame)?;
+        debug!("sent {:?} {} bytes to peer #{}", &msg.msg, frame.len(), id);
+        Ok(())
+    }
+}
+
+// Direct TCP listen/poll with queue (requires mutable access, is configured by passing in
+// the listener and the peers)
+pub struct TcpNetListen {
+    pub net: TcpNet,
+    incoming: Receiver<Message>,
+    in_queue: VecDeque<Message>,
+}
+
+impl TcpNetListen {
+    // `peers` maps node ids to their listen addresses. It may include this node.
+    pub fn new(id: u32, listener: TcpListener, peers: HashMap<u32, String>) -> Self {
+        let (tx, rx) = mpsc::channel();
+        {
+            let tx = tx.clone();
+            spawn(move || accept_loop(listener, tx));
+        }
+        let peers = peers
+            .into_iter()
+            .filter(|(peer_id, _)| *peer_id != id)
+            .map(|(peer_id, address)| (peer_id, Connection::new(address)))
+            .collect();
+        TcpNetListen {
+            net: TcpNet {
+                id,
+                peers: Arc::new(peers),
+                loopback: tx,
+            },
+            incoming: rx,
+            in_queue: VecDeque::new(),
+        }
+    }
+
+    // listens on the address configured for `id` in `config.peers`
+    pub fn from_config(config: &Config, id: u32) -> Result<Self, HttpNetError> {
+        let address = &config
+            .peers
+            .iter()
+            .find(|peer| peer.id == id)
+            .ok_or(HttpNetError::UnknownPeer(id))?
+            .address;
+        let listener = TcpListener::bind(address)?;
+        info!("node #{} listening on {}", id, address);
+        let peers = config
+            .peers
+            .iter()
+            .map(|peer| (peer.id, peer.address.clone()))
+            .collect();
+        Ok(Self::new(id, listener, peers))
+    }
+}
+
+impl NetListen for TcpNetListen {
+    type Error = HttpNetError;
+
+    fn listen(&self) {}
+
+    fn poll(&mut self, _id: u32) -> Result<(), Self::Error> {
+        match self.incoming.recv_timeout(POLL_WAIT) {
+            Ok(msg) => {
+                self.in_queue.push_back(msg);
+                self.in_queue.extend(self.incoming.try_iter());
+                Ok(())
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(()),
+            // the sender of the loopback is kept by `net`, so this doesn't happen
+            Err(RecvTimeoutError::Disconnected) => Err(HttpNetError::RecvError(RecvError)),
+        }
+    }
+
+    fn next_message(&mut self) -> Option<Message> {
+        self.in_queue.pop_front()
+    }
+
+    // pass-thru to immutable net function
+    fn send_message(&self, msg: Message) -> Result<(), Self::Error> {
+        self.net.send_message(msg)
+    }
+
+    fn send_message_to(&self, id: u32, msg: Message) -> Result<(), Self::Error> {
+        self.net.send_message_to(id, msg)
+    }
+}
+
+fn accept_loop(listener: TcpListener, tx: Sender<Message>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let tx = tx.clone();
+                spawn(move || read_loop(stream, tx));
+            }
+            Err(e) => warn!("accept failed: {}", e),
+        }
+    }
+}
+
+// reads frames from a peer until it disconnects
+fn read_loop(mut stream: TcpStream, tx: Sender<Message>) {
+    let peer = stream
+        .peer_addr()
+        .map_or_else(|_| "unknown".to_string(), |a| a.to_string());
+    debug!("peer {} connected", peer);
+    loop {
+        let bytes = match read_frame(&mut stream) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                debug!("peer {} disconnected: {}", peer, e);
+                return;
+            }
+        };
+        match bincode::deserialize::<Message>(&bytes) {
+            Ok(msg) => {
+                debug!("received {:?}", msg);
+                if tx.send(msg).is_err() {
+                    // the listener is gone
+                    return;
+                }
+            }
+            Err(e) => warn!("invalid message from {}: {}", peer, e),
+        }
+        // an invalid message is acknowledged too, sending it again doesn't help
+        if let Err(e) = stream.write_all(&[ACK]) {
+            debug!("peer {} disconnected: {}", peer, e);
+            return;
+        }
+    }
+}
+
+fn read_frame(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame too large",
+        ));
+    }
+    let mut bytes = vec![0; len];
+    stream.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
@@ -1,5 +1,22 @@
-use frost_signer::net::{HttpNet, HttpNetListen, Message, NetListen};
-use frost_signer::signing_round::{DkgBegin, MessageTypes};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+use frost_signer::config::Config;
+use frost_signer::mem_net::{MemNet, MemNetListen};
+use frost_signer::net::{route, HttpNet, HttpNetError, HttpNetListen, Message, Net, NetListen};
+use frost_signer::signing_round::wtfrost::Scalar;
+use frost_signer::signing_round::{
+    DkgBegin, DkgPrivateShares, KeyShares, MessageTypes, SignatureShareRequest,
+};
+use frost_signer::tcp_net::TcpNetListen;
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+use relay_server::{
+    client_config, server_config, Auth, Credentials, Replicator, Server, SharedServer, ThreadPool,
+    TlsStream,
+};
 
 #[test]
 fn receive_msg() {
@@ -13,10 +30,490 @@ fn receive_msg() {
     let in_queue = vec![m1];
     let net = HttpNet::new(stacks_node_url);
     let mut net_listen = HttpNetListen::new(net, in_queue);
-    match net_listen.next_message() {
-        Some(_msg) => {
-            assert!(true)
+    match net_listen.next_message().map(|m| m.msg) {
+        Some(MessageTypes::DkgBegin(DkgBegin { dkg_id })) => assert_eq!(dkg_id, 0),
+        other => panic!("unexpected message {:?}", other),
+    }
+    assert!(net_listen.next_message().is_none());
+}
+
+#[test]
+fn next_msg_is_fifo() {
+    let in_queue = (1..=3)
+        .map(|dkg_id| Message {
+            msg: MessageTypes::DkgBegin(DkgBegin { dkg_id }),
+            sig: [0; 32],
+        })
+        .collect();
+    let net = HttpNet::new("http://localhost:9775".to_owned());
+    let mut net_listen = HttpNetListen::new(net, in_queue);
+    for expected in 1..=3 {
+        match net_listen.next_message().map(|m| m.msg) {
+            Some(MessageTypes::DkgBegin(DkgBegin { dkg_id })) => assert_eq!(dkg_id, expected),
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+    assert!(net_listen.next_message().is_none());
+}
+
+#[test]
+fn mem_net_broadcast() {
+    let net = MemNet::new();
+    let mut alice = MemNetListen::new(net.clone());
+    let mut bob = MemNetListen::new(net.clone());
+    for dkg_id in 1..=2 {
+        alice
+            .send_message(Message {
+                msg: MessageTypes::DkgBegin(DkgBegin { dkg_id }),
+                sig: [0; 32],
+            })
+            .unwrap();
+    }
+
+    // every node, including the sender, receives every message in order
+    for (id, node) in [(1, &mut alice), (2, &mut bob)] {
+        node.poll(id).unwrap();
+        for expected in 1..=2 {
+            match node.next_message().map(|m| m.msg) {
+                Some(MessageTypes::DkgBegin(DkgBegin { dkg_id })) => assert_eq!(dkg_id, expected),
+                other => panic!("unexpected message {:?}", other),
+            }
+        }
+        assert!(node.next_message().is_none());
+    }
+
+    // the read position is kept per node id
+    net.send_message(Message {
+        msg: MessageTypes::DkgBegin(DkgBegin { dkg_id: 3 }),
+        sig: [0; 32],
+    })
+    .unwrap();
+    bob.poll(2).unwrap();
+    match bob.next_message().map(|m| m.msg) {
+        Some(MessageTypes::DkgBegin(DkgBegin { dkg_id })) => assert_eq!(dkg_id, 3),
+        other => panic!("unexpected message {:?}", other),
+    }
+    let mut carol = MemNetListen::new(net);
+    carol.poll(3).unwrap();
+    for expected in 1..=3 {
+        match carol.next_message().map(|m| m.msg) {
+            Some(MessageTypes::DkgBegin(DkgBegin { dkg_id })) => assert_eq!(dkg_id, expected),
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn mem_net_poll_without_messages() {
+    let mut node = MemNetListen::new(MemNet::new());
+    node.poll(1).unwrap();
+    assert!(node.next_message().is_none());
+}
+
+fn private_shares() -> Message {
+    Message {
+        msg: MessageTypes::DkgPrivateShares(DkgPrivateShares {
+            dkg_id: 1,
+            party_id: 0,
+            private_shares: (0..6).map(|id| (id, Scalar::from(id as u32))).collect(),
+        }),
+        sig: [0; 32],
+    }
+}
+
+#[test]
+fn route_private_shares_to_owners() {
+    let routed = route(private_shares());
+    assert_eq!(routed.len(), 3);
+    for (i, (to, msg)) in routed.into_iter().enumerate() {
+        let signer_id = i as u32 + 1;
+        assert_eq!(to, Some(signer_id));
+        match msg.msg {
+            MessageTypes::DkgPrivateShares(shares) => {
+                assert_eq!(shares.party_id, 0);
+                let mut party_ids = shares.private_shares.keys().copied().collect::<Vec<_>>();
+                party_ids.sort();
+                assert_eq!(party_ids, vec![i * 2, i * 2 + 1]);
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn route_sign_share_request_to_owner() {
+    let request = Message {
+        msg: MessageTypes::SignShareRequest(SignatureShareRequest {
+            dkg_id: 1,
+            correlation_id: 0,
+            party_id: 3,
+            nonces: vec![],
+            message: vec![],
+        }),
+        sig: [0; 32],
+    };
+    let routed = route(request);
+    assert_eq!(routed.len(), 1);
+    assert_eq!(routed[0].0, Some(2));
+
+    // everything else is for all nodes
+    let routed = route(Message {
+        msg: MessageTypes::DkgBegin(DkgBegin { dkg_id: 1 }),
+        sig: [0; 32],
+    });
+    assert_eq!(routed.len(), 1);
+    assert_eq!(routed[0].0, None);
+}
+
+#[test]
+fn mem_net_private_shares_are_directed() {
+    let net = MemNet::new();
+    let mut nodes = (0..3)
+        .map(|_| MemNetListen::new(net.clone()))
+        .collect::<Vec<_>>();
+    nodes[0].send_message(private_shares()).unwrap();
+    for (i, node) in nodes.iter_mut().enumerate() {
+        let signer_id = i as u32 + 1;
+        node.poll(signer_id).unwrap();
+        match node.next_message().map(|m| m.msg) {
+            Some(MessageTypes::DkgPrivateShares(shares)) => {
+                let shares: KeyShares = shares.private_shares;
+                assert_eq!(shares.len(), 2);
+                assert!(shares.contains_key(&(i * 2)));
+                assert!(shares.contains_key(&(i * 2 + 1)));
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+        assert!(node.next_message().is_none());
+    }
+}
+
+fn tcp_nodes(ids: &[u32]) -> Vec<TcpNetListen> {
+    let listeners = ids
+        .iter()
+        .map(|id| (*id, TcpListener::bind("127.0.0.1:0").unwrap()))
+        .collect::<Vec<_>>();
+    let peers = listeners
+        .iter()
+        .map(|(id, l)| (*id, l.local_addr().unwrap().to_string()))
+        .collect::<HashMap<_, _>>();
+    listeners
+        .into_iter()
+        .map(|(id, l)| TcpNetListen::new(id, l, peers.clone()))
+        .collect()
+}
+
+fn dkg_begin(dkg_id: u64) -> Message {
+    Message {
+        msg: MessageTypes::DkgBegin(DkgBegin { dkg_id }),
+        sig: [0; 32],
+    }
+}
+
+fn expect_dkg_begin(node: &mut impl NetListen, id: u32, expected: u64) {
+    let msg = node.next_message().or_else(|| {
+        node.poll(id).unwrap();
+        node.next_message()
+    });
+    match msg.map(|m| m.msg) {
+        Some(MessageTypes::DkgBegin(DkgBegin { dkg_id })) => assert_eq!(dkg_id, expected),
+        other => panic!("unexpected message {:?}", other),
+    }
+}
+
+#[test]
+fn tcp_net_broadcast() {
+    let mut nodes = tcp_nodes(&[1, 2, 3]);
+    for dkg_id in 1..=3 {
+        nodes[0].send_message(dkg_begin(dkg_id)).unwrap();
+    }
+    // every node, including the sender, receives every message in order
+    for (i, node) in nodes.iter_mut().enumerate() {
+        for expected in 1..=3 {
+            expect_dkg_begin(node, i as u32 + 1, expected);
+        }
+    }
+}
+
+#[test]
+fn tcp_net_directed() {
+    let mut nodes = tcp_nodes(&[1, 2, 3]);
+    nodes[0].send_message_to(3, dkg_begin(7)).unwrap();
+    nodes[0].send_message_to(1, dkg_begin(8)).unwrap();
+    expect_dkg_begin(&mut nodes[2], 3, 7);
+    expect_dkg_begin(&mut nodes[0], 1, 8);
+    nodes[1].poll(2).unwrap();
+    assert!(nodes[1].next_message().is_none());
+    assert!(nodes[0].send_message_to(4, dkg_begin(9)).is_err());
+}
+
+#[test]
+fn tcp_net_reconnect() {
+    // reserve an address for a peer which is not running yet
+    let address = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let peers = HashMap::from([
+        (1, listener.local_addr().unwrap().to_string()),
+        (2, address.clone()),
+    ]);
+    let alice = TcpNetListen::new(1, listener, peers.clone());
+    assert!(alice.send_message_to(2, dkg_begin(1)).is_err());
+
+    // the connection is established once the peer is up
+    let mut bob = TcpNetListen::new(2, TcpListener::bind(&address).unwrap(), peers);
+    alice.send_message_to(2, dkg_begin(2)).unwrap();
+    expect_dkg_begin(&mut bob, 2, 2);
+    alice.send_message(dkg_begin(3)).unwrap();
+    expect_dkg_begin(&mut bob, 2, 3);
+}
+
+#[test]
+fn tcp_net_closed_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let bob_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let peers = HashMap::from([
+        (1, listener.local_addr().unwrap().to_string()),
+        (2, bob_listener.local_addr().unwrap().to_string()),
+    ]);
+    let alice = TcpNetListen::new(1, listener, peers.clone());
+
+    // the peer acknowledges the first frame and closes the connection
+    let peer = thread::spawn(move || {
+        let (mut stream, _) = bob_listener.accept().unwrap();
+        let mut len = [0; 4];
+        stream.read_exact(&mut len).unwrap();
+        let mut frame = vec![0; u32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut frame).unwrap();
+        stream.write_all(&[1]).unwrap();
+        bob_listener
+    });
+    alice.send_message_to(2, dkg_begin(1)).unwrap();
+    let bob_listener = peer.join().unwrap();
+
+    // the closed connection is noticed and the message is sent over a new one
+    let mut bob = TcpNetListen::new(2, bob_listener, peers);
+    alice.send_message_to(2, dkg_begin(2)).unwrap();
+    expect_dkg_begin(&mut bob, 2, 2);
+}
+
+#[test]
+fn tcp_net_concurrent_sends() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let bob_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let peers = HashMap::from([
+        (1, listener.local_addr().unwrap().to_string()),
+        (2, bob_listener.local_addr().unwrap().to_string()),
+    ]);
+    let alice = TcpNetListen::new(1, listener, peers);
+
+    // the peer acknowledges the frames of the first connection slowly
+    let peer = thread::spawn(move || {
+        let (mut stream, _) = bob_listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut frames = 0;
+        let mut len = [0; 4];
+        while stream.read_exact(&mut len).is_ok() {
+            let mut frame = vec![0; u32::from_be_bytes(len) as usize];
+            stream.read_exact(&mut frame).unwrap();
+            thread::sleep(Duration::from_millis(10));
+            stream.write_all(&[1]).unwrap();
+            frames += 1;
+            if frames == 20 {
+                break;
+            }
+        }
+        bob_listener.set_nonblocking(true).unwrap();
+        (frames, bob_listener.accept().is_err())
+    });
+
+    // concurrent sends to a peer share its connection
+    thread::scope(|scope| {
+        for sender in 0..4 {
+            let net = alice.net.clone();
+            scope.spawn(move || {
+                for i in 0..5 {
+                    net.send_message_to(2, dkg_begin(sender * 5 + i)).unwrap();
+                }
+            });
         }
-        None => {}
+    });
+    assert_eq!(peer.join().unwrap(), (20, true));
+}
+
+#[test]
+fn http_net_signed_requests() {
+    let signer = Credentials::new("1", &"01".repeat(32)).unwrap();
+    let keys = HashMap::from([("1".to_string(), signer.public_key())]);
+    let server = SharedServer::new(Server::default().with_auth(Auth::new(&keys).unwrap()));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        let pool = ThreadPool::new(4);
+        server.run(&pool, Duration::from_secs(10), listener.incoming());
+    });
+
+    let unsigned = HttpNet::new(url.clone());
+    match unsigned.send_message(dkg_begin(1)) {
+        Err(HttpNetError::RelayError(401, _)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    let mut node = HttpNetListen::new(HttpNet::new(url).with_credentials(signer), vec![]);
+    node.send_message(dkg_begin(2)).unwrap();
+    expect_dkg_begin(&mut node, 1, 2);
+}
+
+#[test]
+fn http_net_tls() {
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+    let key = KeyPair::generate().unwrap();
+    let cert = CertificateParams::new(vec!["localhost".to_string()])
+        .unwrap()
+        .signed_by(&key, &ca, &ca_key)
+        .unwrap();
+    let tls = server_config(cert.pem().as_bytes(), key.serialize_pem().as_bytes(), None).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!(
+        "https://localhost:{}",
+        listener.local_addr().unwrap().port()
+    );
+    thread::spawn(move || {
+        let pool = ThreadPool::new(4);
+        let incoming = listener
+            .incoming()
+            .map(move |stream| TlsStream::accept(tls.clone(), stream?));
+        SharedServer::default().run(&pool, Duration::from_secs(10), incoming);
+    });
+
+    // the relay's certificate is not signed by a public CA
+    let untrusted = HttpNet::new(url.clone());
+    assert!(untrusted.send_message(dkg_begin(1)).is_err());
+
+    let net = HttpNet::new(url).with_tls(client_config(ca.pem().as_bytes(), None).unwrap());
+    let mut node = HttpNetListen::new(net, vec![]);
+    node.send_message(dkg_begin(2)).unwrap();
+    expect_dkg_begin(&mut node, 1, 2);
+}
+
+#[test]
+fn http_net_cursor() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        let pool = ThreadPool::new(4);
+        SharedServer::default().run(&pool, Duration::from_secs(10), listener.incoming());
+    });
+    let path = std::env::temp_dir().join(format!("frost-signer-{}.cursor", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let net = HttpNet::new(url);
+    net.send_message(dkg_begin(1)).unwrap();
+    net.send_message_to(1, dkg_begin(2)).unwrap();
+    let listen = || {
+        HttpNetListen::new(net.clone(), vec![])
+            .with_cursor_file(&path)
+            .unwrap()
+    };
+
+    // the node stops before it has processed the first message
+    let mut node = listen();
+    expect_dkg_begin(&mut node, 1, 1);
+    drop(node);
+
+    let mut node = listen();
+    expect_dkg_begin(&mut node, 1, 1);
+    node.ack().unwrap();
+    // the second message is taken, but not acknowledged
+    expect_dkg_begin(&mut node, 1, 2);
+    drop(node);
+
+    let mut node = listen();
+    expect_dkg_begin(&mut node, 1, 2);
+    node.ack().unwrap();
+    assert!(node.next_message().is_none());
+    drop(node);
+
+    // another process with the same id doesn't take the messages
+    let mut other = HttpNetListen::new(net.clone(), vec![]);
+    expect_dkg_begin(&mut other, 1, 1);
+
+    net.send_message(dkg_begin(3)).unwrap();
+    let mut node = listen();
+    expect_dkg_begin(&mut node, 1, 3);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn cursor_file_per_node() {
+    let config = Config {
+        relay_cursor_file: Some("signer.cursor".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        config.relay_cursor_file(0).as_deref(),
+        Some("signer.cursor.0")
+    );
+    assert_eq!(
+        config.relay_cursor_file(1).as_deref(),
+        Some("signer.cursor.1")
+    );
+    assert_eq!(Config::default().relay_cursor_file(1), None);
+}
+
+// a relay `id` which forwards the messages posted to it to `peers`
+fn start_relay(listener: TcpListener, id: &str, peers: &[String]) {
+    let server = Server::default()
+        .with_id(id)
+        .with_replicator(Replicator::new(peers, None, None).unwrap());
+    thread::spawn(move || {
+        let pool = ThreadPool::new(4);
+        SharedServer::new(server).run(&pool, Duration::from_secs(10), listener.incoming());
+    });
+}
+
+#[test]
+fn http_net_relay_cluster() {
+    let listeners: Vec<_> = (0..2)
+        .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+        .collect();
+    let urls: Vec<_> = listeners
+        .iter()
+        .map(|listener| format!("http://{}", listener.local_addr().unwrap()))
+        .collect();
+    for (i, listener) in listeners.into_iter().enumerate() {
+        start_relay(listener, &format!("relay{}", i), &[urls[1 - i].clone()]);
     }
+    let down = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    };
+
+    // the first relay is down, so the message is posted to the next one
+    let net = HttpNet::new(down).with_relays(urls.clone());
+    net.send_message(dkg_begin(1)).unwrap();
+    let mut node = HttpNetListen::new(net.clone(), vec![]);
+    expect_dkg_begin(&mut node, 1, 1);
+
+    // the other relay returns the first message again
+    net.send_message(dkg_begin(2)).unwrap();
+    node.net = HttpNet::new(urls[1].clone()).with_relays(vec![urls[0].clone()]);
+    let msg = (0..10).find_map(|_| {
+        node.poll(1).unwrap();
+        node.next_message()
+    });
+    match msg.map(|m| m.msg) {
+        Some(MessageTypes::DkgBegin(DkgBegin { dkg_id })) => assert_eq!(dkg_id, 2),
+        other => panic!("unexpected message {:?}", other),
+    }
+
+    // a message which is posted again is received again
+    net.send_message(dkg_begin(2)).unwrap();
+    expect_dkg_begin(&mut node, 1, 2);
 }
@@ -1,8 +1,21 @@
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use std::net::TcpListener;
     use std::str::from_utf8;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+    use std::thread::{spawn, JoinHandle};
 
-    use frost_signer::signing_round::SigningRound;
+    use frost_coordinator::coordinator::{Coordinator, Error};
+    use frost_signer::config::Config;
+    use frost_signer::mem_net::{MemNet, MemNetListen};
+    use frost_signer::net::{Message, NetListen};
+    use frost_signer::signing_round::{party_ids_for_signer, SigningRound};
+    use frost_signer::taproot;
+    use frost_signer::tcp_net::TcpNetListen;
     use relay_server::Server;
 
     #[test]
@@ -27,4 +40,103 @@ mod tests {
             assert_eq!(from_utf8(&response).unwrap(), RESPONSE);
         }
     }
+
+    const TOTAL_SIGNERS: usize = 3;
+
+    fn config() -> Config {
+        Config {
+            total_signers: TOTAL_SIGNERS,
+            total_keys: TOTAL_SIGNERS * 2,
+            keys_threshold: TOTAL_SIGNERS * 2 * 2 / 3,
+            max_party_id: TOTAL_SIGNERS,
+            ..Default::default()
+        }
+    }
+
+    /// Runs a signer the same way `frost_signer::signer::Signer` does, until `stop` is set.
+    fn spawn_signer<N: NetListen + Send + 'static>(
+        signer_id: u32,
+        mut net: N,
+        stop: Arc<AtomicBool>,
+    ) -> JoinHandle<()> {
+        let config = config();
+        let party_ids = party_ids_for_signer(signer_id);
+        let mut round = SigningRound::new(
+            config.keys_threshold,
+            config.total_keys,
+            signer_id,
+            party_ids,
+        );
+        spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                net.poll(signer_id).unwrap();
+                while let Some(message) = net.next_message() {
+                    for out in round.process(message.msg).unwrap() {
+                        let msg = Message {
+                            msg: out,
+                            sig: [0; 32],
+                        };
+                        net.send_message(msg).unwrap();
+                    }
+                    net.ack().unwrap();
+                }
+            }
+        })
+    }
+
+    /// Runs DKG and signs a message with a coordinator (id 0) and signers (ids 1..) which
+    /// use the given transports.
+    fn dkg_sign<N: NetListen + Send + 'static>(mut nets: Vec<N>)
+    where
+        Error: From<N::Error>,
+    {
+        let coordinator_net = nets.remove(0);
+        let stop = Arc::new(AtomicBool::new(false));
+        let signers = nets
+            .into_iter()
+            .zip(1..)
+            .map(|(net, id)| spawn_signer(id, net, stop.clone()))
+            .collect::<Vec<_>>();
+
+        let mut coordinator = Coordinator::new(0, 0, &config(), coordinator_net);
+        let key = coordinator.run_distributed_key_generation().unwrap();
+        // a signature per message, as a peg-out signs every input
+        for msg in ["It was many and many a year ago", "In a kingdom by the sea"] {
+            let msg = msg.as_bytes();
+            let signature = coordinator.sign_message(msg).unwrap();
+            assert!(taproot::verify(&key, msg, &signature));
+        }
+
+        stop.store(true, Ordering::Relaxed);
+        for signer in signers {
+            signer.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn mem_net_dkg_sign_test() {
+        let net = MemNet::new();
+        dkg_sign(
+            (0..=TOTAL_SIGNERS)
+                .map(|_| MemNetListen::new(net.clone()))
+                .collect(),
+        );
+    }
+
+    #[test]
+    fn tcp_net_dkg_sign_test() {
+        let listeners = (0..=TOTAL_SIGNERS as u32)
+            .map(|id| (id, TcpListener::bind("127.0.0.1:0").unwrap()))
+            .collect::<Vec<_>>();
+        let peers = listeners
+            .iter()
+            .map(|(id, l)| (*id, l.local_addr().unwrap().to_string()))
+            .collect::<HashMap<_, _>>();
+        dkg_sign(
+            listeners
+                .into_iter()
+                .map(|(id, l)| TcpNetListen::new(id, l, peers.clone()))
+                .collect(),
+        );
+    }
 }
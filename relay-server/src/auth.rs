@@ -0,0 +1,329 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{Error, ErrorKind},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use k256::schnorr::{
+    signature::{RandomizedSigner, Verifier},
+    Signature, SigningKey, VerifyingKey,
+};
+use rand_core::OsRng;
+
+use crate::{
+    batch::encode_batch,
+    http::{HttpError, Request},
+};
+
+pub const NODE_HEADER: &str = "x-relay-node";
+pub const TIME_HEADER: &str = "x-relay-time";
+pub const SIGNATURE_HEADER: &str = "x-relay-signature";
+
+/// How far the time of a signed request may be from the server time. The server remembers
+/// the signatures of this time, so a request can't be replayed.
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// The private key of a node, which signs the node's requests to the relay.
+///
+/// Keys are BIP-340 Schnorr keys on secp256k1, written as 32 bytes in hex.
+#[derive(Clone)]
+pub struct Credentials {
+    node_id: String,
+    key: SigningKey,
+}
+
+impl Credentials {
+    pub fn new(node_id: impl Into<String>, private_key: &str) -> Result<Self, Error> {
+        let key = SigningKey::from_bytes(&decode_hex(private_key)?)
+            .map_err(|_| invalid_key("invalid private key"))?;
+        Ok(Credentials {
+            node_id: node_id.into(),
+            key,
+        })
+    }
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+    /// The key which the relay needs to authenticate this node.
+    pub fn public_key(&self) -> String {
+        hex::encode(self.key.verifying_key().to_bytes())
+    }
+    /// The headers which authenticate a request. `url` can be a full URL or a path with a
+    /// query, only the path and the query are signed.
+    ///
+    /// The signature is randomized, so the same request sent twice has different signatures.
+    pub fn headers(&self, method: &str, url: &str, content: &[u8]) -> Vec<(&'static str, String)> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .to_string();
+        let signature: Signature = self.key.sign_with_rng(
+            &mut OsRng,
+            &signed_data(&self.node_id, &time, method, url, content),
+        );
+        vec![
+            (NODE_HEADER, self.node_id.clone()),
+            (TIME_HEADER, time),
+            (SIGNATURE_HEADER, hex::encode(signature.to_bytes())),
+        ]
+    }
+    /// Replaces the signature headers of the request.
+    pub fn sign(&self, request: &mut Request) {
+        for (name, value) in self.headers(&request.method, &request.url, &request.content) {
+            request.headers.insert(name.to_string(), value);
+        }
+    }
+}
+
+/// The public keys of the nodes which may use the relay, the nodes which may use the admin
+/// requests, and the other relays of a cluster.
+///
+/// A clone shares the signatures seen, so a request can't be replayed to another clone.
+#[derive(Default, Clone)]
+pub struct Auth {
+    keys: HashMap<String, VerifyingKey>,
+    admins: HashSet<String>,
+    relays: HashSet<String>,
+    seen: Arc<Mutex<Seen>>,
+}
+
+// the signatures accepted within the last `2 * MAX_CLOCK_SKEW`, in the order they were seen.
+#[derive(Default)]
+struct Seen {
+    signatures: HashSet<[u8; 64]>,
+    order: VecDeque<(SystemTime, [u8; 64])>,
+}
+
+impl Seen {
+    // returns `false` if the signature was seen already.
+    fn insert(&mut self, now: SystemTime, signature: [u8; 64]) -> bool {
+        // a signature is valid from `MAX_CLOCK_SKEW` before its time to `MAX_CLOCK_SKEW` after
+        while let Some((time, old)) = self.order.front() {
+            if now.duration_since(*time).unwrap_or_default() <= 2 * MAX_CLOCK_SKEW {
+                break;
+            }
+            self.signatures.remove(old);
+            self.order.pop_front();
+        }
+        if !self.signatures.insert(signature) {
+            return false;
+        }
+        self.order.push_back((now, signature));
+        true
+    }
+}
+
+impl Auth {
+    /// `keys` maps a node id to the node's public key in hex.
+    pub fn new(keys: &HashMap<String, String>) -> Result<Self, Error> {
+        let keys = keys
+            .iter()
+            .map(|(node_id, public_key)| {
+                let key = VerifyingKey::from_bytes(&decode_hex(public_key)?)
+                    .map_err(|_| invalid_key("invalid public key"))?;
+                Ok((node_id.clone(), key))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Auth {
+            keys,
+            ..Default::default()
+        })
+    }
+    pub fn with_admins(mut self, admins: impl IntoIterator<Item = String>) -> Self {
+        self.admins = admins.into_iter().collect();
+        self
+    }
+    pub fn is_admin(&self, node_id: &str) -> bool {
+        self.admins.contains(node_id)
+    }
+    pub fn with_relays(mut self, relays: impl IntoIterator<Item = String>) -> Self {
+        self.relays = relays.into_iter().collect();
+        self
+    }
+    pub fn is_relay(&self, node_id: &str) -> bool {
+        self.relays.contains(node_id)
+    }
+    /// Returns the node which signed the request. A signature is only accepted once.
+    pub fn authenticate(&self, request: &Request) -> Result<String, HttpError> {
+        let header = |name| {
+            request
+                .headers
+                .get(name)
+                .ok_or_else(|| unauthorized(format!("no {name} header")))
+        };
+        let node_id = header(NODE_HEADER)?;
+        let time = header(TIME_HEADER)?;
+        let signature = header(SIGNATURE_HEADER)?;
+        let key = self
+            .keys
+            .get(node_id)
+            .ok_or_else(|| unauthorized(format!("unknown node {node_id}")))?;
+        let signed_at = UNIX_EPOCH
+            + Duration::from_secs(
+                time.parse()
+                    .map_err(|_| unauthorized(format!("invalid {TIME_HEADER} header")))?,
+            );
+        let now = SystemTime::now();
+        let skew = now
+            .duration_since(signed_at)
+            .or_else(|_| signed_at.duration_since(now))
+            .unwrap_or_default();
+        if skew > MAX_CLOCK_SKEW {
+            return Err(unauthorized("expired signature"));
+        }
+        let signature = hex::decode(signature)
+            .ok()
+            .and_then(|bytes| Signature::try_from(bytes.as_slice()).ok())
+            .ok_or_else(|| unauthorized("invalid signature"))?;
+        let data = signed_data(
+            node_id,
+            time,
+            &request.method,
+            &request.url,
+            &request.content,
+        );
+        key.verify(&data, &signature)
+            .map_err(|_| unauthorized("invalid signature"))?;
+        let mut seen = self
+            .seen
+            .lock()
+            .map_err(|_| HttpError::new(500, "poisoned auth"))?;
+        if !seen.insert(now, signature.to_bytes()) {
+            return Err(unauthorized("replayed signature"));
+        }
+        Ok(node_id.clone())
+    }
+}
+
+fn signed_data(node_id: &str, time: &str, method: &str, url: &str, content: &[u8]) -> Vec<u8> {
+    encode_batch(&[
+        node_id.as_bytes().to_vec(),
+        time.as_bytes().to_vec(),
+        method.as_bytes().to_vec(),
+        target(url).as_bytes().to_vec(),
+        content.to_vec(),
+    ])
+}
+
+// the path and the query of a URL, as the client sends them in the request line.
+fn target(url: &str) -> String {
+    let path = match url.split_once("://") {
+        Some((_, rest)) => rest.find(['/', '?']).map_or("", |i| &rest[i..]),
+        None => url,
+    };
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{path}")
+    }
+}
+
+fn decode_hex(key: &str) -> Result<Vec<u8>, Error> {
+    match hex::decode(key.trim()) {
+        Ok(bytes) if bytes.len() == 32 => Ok(bytes),
+        _ => Err(invalid_key("a key must be 32 bytes in hex")),
+    }
+}
+
+fn invalid_key(message: &'static str) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+fn unauthorized(message: impl Into<String>) -> HttpError {
+    HttpError::new(401, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime};
+
+    use super::{target, Auth, Credentials, Seen, MAX_CLOCK_SKEW, TIME_HEADER};
+    use crate::http::Request;
+
+    const PRIVATE_KEY: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+
+    fn request(url: &str, content: &str) -> Request {
+        Request::new(
+            "POST".to_string(),
+            url.to_string(),
+            Default::default(),
+            content.as_bytes().to_vec(),
+        )
+    }
+
+    #[test]
+    fn target_test() {
+        assert_eq!(target("http://127.0.0.1:9776"), "/");
+        assert_eq!(target("http://127.0.0.1:9776?id=1"), "/?id=1");
+        assert_eq!(target("https://relay/rounds/5?to=1"), "/rounds/5?to=1");
+        assert_eq!(target("/?id=1"), "/?id=1");
+    }
+
+    #[test]
+    fn seen_test() {
+        let mut seen = Seen::default();
+        let start = SystemTime::now();
+        assert!(seen.insert(start, [1; 64]));
+        assert!(!seen.insert(start, [1; 64]));
+        assert!(seen.insert(start + MAX_CLOCK_SKEW, [2; 64]));
+        // an expired signature is forgotten, its time isn't valid anymore
+        let later = start + 2 * MAX_CLOCK_SKEW + Duration::from_secs(1);
+        assert!(seen.insert(later, [1; 64]));
+        assert!(!seen.insert(later, [2; 64]));
+        assert_eq!(seen.order.len(), 2);
+    }
+
+    #[test]
+    fn auth_test() {
+        let credentials = Credentials::new("1", PRIVATE_KEY).unwrap();
+        let auth = Auth::new(&HashMap::from([(
+            "1".to_string(),
+            credentials.public_key(),
+        )]))
+        .unwrap();
+
+        // the client signs the full URL, the server sees the path
+        let mut signed = request("/?to=2", "Hello!");
+        for (name, value) in credentials.headers("POST", "http://localhost/?to=2", b"Hello!") {
+            signed.headers.insert(name.to_string(), value);
+        }
+        assert_eq!(auth.authenticate(&signed).unwrap(), "1");
+
+        // a replayed request, also to a clone
+        assert_eq!(auth.authenticate(&signed).unwrap_err().code, 401);
+        assert!(auth.clone().authenticate(&signed).is_err());
+        // the same request signed again
+        let mut again = request("/?to=2", "Hello!");
+        credentials.sign(&mut again);
+        assert_eq!(auth.authenticate(&again).unwrap(), "1");
+
+        // no signature
+        assert_eq!(auth.authenticate(&request("/", "")).unwrap_err().code, 401);
+
+        // changed content or URL
+        let mut changed = request("/?to=2", "Hello?");
+        changed.headers = signed.headers.clone();
+        assert!(auth.authenticate(&changed).is_err());
+        let mut changed = request("/?to=3", "Hello!");
+        changed.headers = signed.headers.clone();
+        assert!(auth.authenticate(&changed).is_err());
+
+        // an old request
+        let mut old = request("/", "");
+        credentials.sign(&mut old);
+        old.headers.insert(TIME_HEADER.to_string(), "0".to_string());
+        assert!(auth.authenticate(&old).is_err());
+
+        // an unknown node
+        let other = Credentials::new("2", PRIVATE_KEY).unwrap();
+        let mut unknown = request("/", "");
+        other.sign(&mut unknown);
+        assert!(auth.authenticate(&unknown).is_err());
+
+        assert!(Credentials::new("1", "xyz").is_err());
+        assert!(Auth::new(&HashMap::from([("1".to_string(), "00".to_string())])).is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use std::io::Error;
+
+use crate::http::{io_error, ToIoResult};
+
+const LENGTH_SIZE: usize = 4;
+
+/// Packs several messages into one response body.
+///
+/// Every message is prefixed with its length as a big-endian `u32`.
+pub fn encode_batch(messages: &[Vec<u8>]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(
+        messages
+            .iter()
+            .map(|message| LENGTH_SIZE + message.len())
+            .sum(),
+    );
+    for message in messages {
+        result.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        result.extend_from_slice(message);
+    }
+    result
+}
+
+/// Unpacks a response body created by `encode_batch`.
+pub fn decode_batch(mut content: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    let mut result = Vec::default();
+    while !content.is_empty() {
+        if content.len() < LENGTH_SIZE {
+            return Err(io_error("incomplete batch length"));
+        }
+        let (len, rest) = content.split_at(LENGTH_SIZE);
+        let len = u32::from_be_bytes(len.try_into().to_io_result("invalid batch length")?);
+        let len = len as usize;
+        if rest.len() < len {
+            return Err(io_error("incomplete batch message"));
+        }
+        let (message, rest) = rest.split_at(len);
+        result.push(message.to_vec());
+        content = rest;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_batch, encode_batch};
+
+    #[test]
+    fn empty_test() {
+        assert!(encode_batch(&[]).is_empty());
+        assert!(decode_batch(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let messages = [
+            "Msg # 0".as_bytes().to_vec(),
+            Vec::default(),
+            "Msg # 2".as_bytes().to_vec(),
+        ];
+        let content = encode_batch(&messages);
+        assert_eq!(content.len(), 3 * 4 + 7 + 7);
+        assert_eq!(decode_batch(&content).unwrap(), messages);
+    }
+
+    #[test]
+    fn incomplete_test() {
+        let content = encode_batch(&["Hello!".as_bytes().to_vec()]);
+        assert!(decode_batch(&content[..2]).is_err());
+        assert!(decode_batch(&content[..content.len() - 1]).is_err());
+    }
+}
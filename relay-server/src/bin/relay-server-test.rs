@@ -1,36 +1,76 @@
 use std::{net::TcpStream, thread::yield_now};
 
-use relay_server::{IoStream, RemoteState, Request, Response, State};
+use clap::Parser;
+use relay_server::{IoStream, RemoteState, Request, Response, State, DEFAULT_ADDRESS};
 
-const ADDR: &str = "127.0.0.1:9776";
-
-fn call(request: Request) -> Response {
-    TcpStream::connect(ADDR).unwrap().call(request)
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Address of the relay server
+    #[arg(short, long, default_value = DEFAULT_ADDRESS)]
+    address: String,
 }
 
 fn main() {
+    let addr = Cli::parse().address;
     // waiting for a server
-    while TcpStream::connect(ADDR).is_err() {
+    while TcpStream::connect(&addr).is_err() {
         yield_now()
     }
     //
-    let mut state = RemoteState(call);
+    let mut state = RemoteState(|request: Request| -> Response {
+        TcpStream::connect(&addr).unwrap().call(request)
+    });
     //
-    assert!(state.get(1.to_string()).is_empty());
-    assert!(state.get(3.to_string()).is_empty());
+    assert!(state.get(1.to_string()).unwrap().is_empty());
+    assert!(state.get(3.to_string()).unwrap().is_empty());
     // assert_eq!(0, state.highwaters.len());
-    state.post("Msg # 0".as_bytes().to_vec());
-    assert_eq!("Msg # 0".as_bytes().to_vec(), state.get(1.to_string()));
-    assert_eq!("Msg # 0".as_bytes().to_vec(), state.get(5.to_string()));
-    assert_eq!("Msg # 0".as_bytes().to_vec(), state.get(4.to_string()));
-    assert!(state.get(1.to_string()).is_empty());
-    state.post("Msg # 1".as_bytes().to_vec());
-    assert_eq!("Msg # 1".as_bytes().to_vec(), state.get(1.to_string()));
-    assert_eq!("Msg # 0".as_bytes().to_vec(), state.get(3.to_string()));
-    assert_eq!("Msg # 1".as_bytes().to_vec(), state.get(5.to_string()));
-    state.post("Msg # 2".as_bytes().to_vec());
-    assert_eq!("Msg # 2".as_bytes().to_vec(), state.get(1.to_string()));
-    assert_eq!("Msg # 1".as_bytes().to_vec(), state.get(4.to_string()));
-    assert_eq!("Msg # 2".as_bytes().to_vec(), state.get(4.to_string()));
+    state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+    assert_eq!(
+        "Msg # 0".as_bytes().to_vec(),
+        state.get(1.to_string()).unwrap()
+    );
+    assert_eq!(
+        "Msg # 0".as_bytes().to_vec(),
+        state.get(5.to_string()).unwrap()
+    );
+    assert_eq!(
+        "Msg # 0".as_bytes().to_vec(),
+        state.get(4.to_string()).unwrap()
+    );
+    assert!(state.get(1.to_string()).unwrap().is_empty());
+    state.post("Msg # 1".as_bytes().to_vec()).unwrap();
+    assert_eq!(
+        "Msg # 1".as_bytes().to_vec(),
+        state.get(1.to_string()).unwrap()
+    );
+    assert_eq!(
+        "Msg # 0".as_bytes().to_vec(),
+        state.get(3.to_string()).unwrap()
+    );
+    assert_eq!(
+        "Msg # 1".as_bytes().to_vec(),
+        state.get(5.to_string()).unwrap()
+    );
+    state.post("Msg # 2".as_bytes().to_vec()).unwrap();
+    assert_eq!(
+        "Msg # 2".as_bytes().to_vec(),
+        state.get(1.to_string()).unwrap()
+    );
+    assert_eq!(
+        "Msg # 1".as_bytes().to_vec(),
+        state.get(4.to_string()).unwrap()
+    );
+    assert_eq!(
+        "Msg # 2".as_bytes().to_vec(),
+        state.get(4.to_string()).unwrap()
+    );
+    state
+        .post_to(4.to_string(), "Msg # 3".as_bytes().to_vec())
+        .unwrap();
+    assert_eq!(
+        "Msg # 3".as_bytes().to_vec(),
+        state.get(4.to_string()).unwrap()
+    );
     println!("passed");
 }
@@ -1,20 +1,90 @@
-use std::{io::Error, net::TcpListener};
+use std::{fs, net::TcpListener};
 
-use relay_server::{IoStream, Server};
+use clap::Parser;
+use relay_server::{
+    Config, Credentials, FileStorage, MemStorage, Server, SharedServer, Storage, ThreadPool,
+    TlsStream,
+};
 
-pub fn run_server<T: IoStream>(i: &mut impl Iterator<Item = Result<T, Error>>) {
-    let mut server = Server::default();
-    for stream_or_error in i {
-        let f = || server.update(&mut stream_or_error?);
-        if let Err(e) = f() {
-            eprintln!("IO error: {e}");
+/// Relays messages between the signers and the coordinator over HTTP.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Config file path, the command line options override its settings
+    #[arg(short, long)]
+    config: Option<String>,
+
+    /// Print the public key for the private key in a file, and exit
+    #[arg(long)]
+    public_key: Option<String>,
+
+    #[command(flatten)]
+    options: Config,
+}
+
+fn run<S: Storage + Send + 'static>(server: Server<S>, config: &Config, listener: TcpListener)
+where
+    S::State: Send,
+{
+    let pool = ThreadPool::new(config.threads());
+    let timeout = config.timeout();
+    let mut server = server
+        .with_limits(config.limits())
+        .with_max_namespaces(config.max_namespaces());
+    if let Some(auth) = config.auth().unwrap() {
+        println!(
+            "Only signed requests of {} nodes are accepted",
+            config.keys.len()
+        );
+        server = server.with_auth(auth);
+    }
+    if let Some(replicator) = config.replicator().unwrap() {
+        println!("Messages are forwarded to {}", config.peers.join(", "));
+        server = server.with_replicator(replicator);
+    }
+    if let Some(id) = &config.id {
+        server = server.with_id(id);
+    }
+    let server = SharedServer::new(server);
+    match config.tls().unwrap() {
+        Some(tls) => {
+            println!("Connections use TLS");
+            let incoming = listener
+                .incoming()
+                .map(|stream| TlsStream::accept(tls.clone(), stream?));
+            server.run(&pool, timeout, incoming)
         }
+        None => server.run(&pool, timeout, listener.incoming()),
     }
 }
 
 fn main() {
-    let addr = "127.0.0.1:9776";
+    let cli = Cli::parse();
+    if let Some(path) = &cli.public_key {
+        let private_key = fs::read_to_string(path).unwrap();
+        println!(
+            "{}",
+            Credentials::new("", &private_key).unwrap().public_key()
+        );
+        return;
+    }
+    let config = match &cli.config {
+        Some(path) => cli.options.or(Config::from_path(path).unwrap()),
+        None => cli.options,
+    };
+    let retention = config.retention();
+    let addr = config.address();
     let listner = TcpListener::bind(addr).unwrap();
     println!("Listening {addr}...");
-    run_server(&mut listner.incoming());
+    match &config.log {
+        Some(path) => {
+            println!("Messages are stored in {}", path.display());
+            run(
+                Server::new(FileStorage::new(path, retention)),
+                &config,
+                listner,
+            );
+        }
+        None => run(Server::new(MemStorage(retention)), &config, listner),
+    }
 }
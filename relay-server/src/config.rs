@@ -0,0 +1,334 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use clap::Args;
+use rustls::ServerConfig;
+use serde::Deserialize;
+
+use crate::{
+    auth::{Auth, Credentials},
+    http::Limits,
+    mem_state::Retention,
+    replica::Replicator,
+    tls::{client_config, server_config},
+};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:9776";
+/// Every waiting client holds a thread.
+pub const DEFAULT_THREADS: usize = 256;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_MAX_NAMESPACES: usize = 1024;
+pub const DEFAULT_MAX_NODES: usize = 1024;
+
+/// Settings of the relay server. They can be read from a TOML file and given as command line
+/// options, a missing setting has a default value.
+#[derive(Args, Deserialize, Default, Clone, Debug, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address to listen on [default: 127.0.0.1:9776]
+    #[arg(short, long)]
+    pub address: Option<String>,
+
+    /// Keep the messages in files starting with this path, instead of memory, so they
+    /// survive a restart
+    #[arg(long)]
+    pub log: Option<PathBuf>,
+
+    /// Maximum number of threads serving connections [default: 256]
+    #[arg(long)]
+    pub threads: Option<usize>,
+
+    /// Read and write timeout of a connection in seconds [default: 10]
+    #[arg(long)]
+    pub timeout: Option<u64>,
+
+    /// Maximum content length of a request in bytes [default: 8 MiB]
+    #[arg(long)]
+    pub max_content_length: Option<usize>,
+
+    /// Maximum size of the request line and headers in bytes [default: 8 KiB]
+    #[arg(long)]
+    pub max_header_size: Option<usize>,
+
+    /// Maximum time to receive a request in seconds, a slower client is disconnected
+    /// [default: 30]
+    #[arg(long)]
+    pub max_read_time: Option<u64>,
+
+    /// Maximum number of messages kept in a namespace
+    #[arg(long)]
+    pub max_messages: Option<usize>,
+
+    /// Maximum age of a kept message in seconds
+    #[arg(long)]
+    pub max_age: Option<u64>,
+
+    /// Maximum size of the messages kept in a namespace in bytes
+    #[arg(long)]
+    pub max_bytes: Option<usize>,
+
+    /// Maximum number of namespaces, a message to another namespace is refused [default: 1024]
+    #[arg(long)]
+    pub max_namespaces: Option<usize>,
+
+    /// Maximum number of nodes with a read position or messages in a namespace, the node
+    /// which was used least recently is forgotten [default: 1024]
+    #[arg(long)]
+    pub max_nodes: Option<usize>,
+
+    /// Certificate chain of the server in PEM, the server accepts TLS connections with it
+    #[arg(long)]
+    pub tls_cert: Option<PathBuf>,
+
+    /// Private key for `tls-cert` in PEM
+    #[arg(long)]
+    pub tls_key: Option<PathBuf>,
+
+    /// CA certificates in PEM, a TLS client has to present a certificate signed by one of them
+    #[arg(long)]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// URL of another relay of a cluster, like `http://10.0.0.2:9776`. Messages posted
+    /// to this relay are forwarded to every peer
+    #[arg(long = "peer")]
+    pub peers: Vec<String>,
+
+    /// CA certificates in PEM which the certificates of `https://` peers are signed by
+    #[arg(long)]
+    pub peer_ca: Option<PathBuf>,
+
+    /// Id of this relay, a relay of a cluster needs a unique one. It is the origin of the
+    /// messages posted to this relay, and the node which signs the requests it forwards
+    #[arg(long)]
+    pub id: Option<String>,
+
+    /// File with the private key of `id` in hex
+    #[arg(long)]
+    pub private_key: Option<PathBuf>,
+
+    /// Public keys of the nodes by node id. Only the config file can set them, and then
+    /// every request has to be signed by one of the nodes.
+    #[arg(skip)]
+    pub keys: HashMap<String, String>,
+
+    /// Nodes which may use the admin requests when requests are signed.
+    #[arg(skip)]
+    pub admins: Vec<String>,
+
+    /// Nodes which are the other relays of a cluster. A request which one of them forwards
+    /// was authorized by that relay.
+    #[arg(skip)]
+    pub relays: Vec<String>,
+}
+
+impl Config {
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Config, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("Invalid path: {}", &e))?;
+        toml::from_str(&content).map_err(|e| format!("Invalid toml: {}", e))
+    }
+    /// Takes the settings which are missing in `self` from `other`.
+    pub fn or(self, other: Config) -> Config {
+        Config {
+            address: self.address.or(other.address),
+            log: self.log.or(other.log),
+            threads: self.threads.or(other.threads),
+            timeout: self.timeout.or(other.timeout),
+            max_content_length: self.max_content_length.or(other.max_content_length),
+            max_header_size: self.max_header_size.or(other.max_header_size),
+            max_read_time: self.max_read_time.or(other.max_read_time),
+            max_messages: self.max_messages.or(other.max_messages),
+            max_age: self.max_age.or(other.max_age),
+            max_bytes: self.max_bytes.or(other.max_bytes),
+            max_namespaces: self.max_namespaces.or(other.max_namespaces),
+            max_nodes: self.max_nodes.or(other.max_nodes),
+            tls_cert: self.tls_cert.or(other.tls_cert),
+            tls_key: self.tls_key.or(other.tls_key),
+            tls_client_ca: self.tls_client_ca.or(other.tls_client_ca),
+            peers: if self.peers.is_empty() {
+                other.peers
+            } else {
+                self.peers
+            },
+            peer_ca: self.peer_ca.or(other.peer_ca),
+            id: self.id.or(other.id),
+            private_key: self.private_key.or(other.private_key),
+            keys: if self.keys.is_empty() {
+                other.keys
+            } else {
+                self.keys
+            },
+            admins: if self.admins.is_empty() {
+                other.admins
+            } else {
+                self.admins
+            },
+            relays: if self.relays.is_empty() {
+                other.relays
+            } else {
+                self.relays
+            },
+        }
+    }
+    pub fn address(&self) -> &str {
+        self.address.as_deref().unwrap_or(DEFAULT_ADDRESS)
+    }
+    pub fn threads(&self) -> usize {
+        self.threads.unwrap_or(DEFAULT_THREADS)
+    }
+    pub fn timeout(&self) -> Duration {
+        self.timeout.map_or(DEFAULT_TIMEOUT, Duration::from_secs)
+    }
+    pub fn max_namespaces(&self) -> usize {
+        self.max_namespaces.unwrap_or(DEFAULT_MAX_NAMESPACES)
+    }
+    pub fn limits(&self) -> Limits {
+        let default = Limits::default();
+        Limits {
+            max_content_length: self
+                .max_content_length
+                .unwrap_or(default.max_content_length),
+            max_header_size: self.max_header_size.unwrap_or(default.max_header_size),
+            max_read_time: self
+                .max_read_time
+                .map_or(default.max_read_time, Duration::from_secs),
+        }
+    }
+    /// `None` if every client can use the relay.
+    pub fn auth(&self) -> Result<Option<Auth>, Error> {
+        if self.keys.is_empty() {
+            Ok(None)
+        } else {
+            Auth::new(&self.keys).map(|auth| {
+                Some(
+                    auth.with_admins(self.admins.clone())
+                        .with_relays(self.relays.clone()),
+                )
+            })
+        }
+    }
+    /// `None` if the server accepts plain TCP connections.
+    pub fn tls(&self) -> Result<Option<Arc<ServerConfig>>, Error> {
+        let (cert, key) = match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => (fs::read(cert)?, fs::read(key)?),
+            (None, None) if self.tls_client_ca.is_none() => return Ok(None),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "TLS requires both tls_cert and tls_key",
+                ))
+            }
+        };
+        let client_ca = match &self.tls_client_ca {
+            Some(path) => Some(fs::read(path)?),
+            None => None,
+        };
+        server_config(&cert, &key, client_ca.as_deref()).map(Some)
+    }
+    /// `None` if the relay is not part of a cluster.
+    pub fn replicator(&self) -> Result<Option<Replicator>, Error> {
+        if self.peers.is_empty() {
+            return Ok(None);
+        }
+        if self.id.is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "a relay of a cluster requires an id",
+            ));
+        }
+        let tls = match &self.peer_ca {
+            Some(path) => Some(client_config(&fs::read(path)?, None)?),
+            None => None,
+        };
+        Replicator::new(&self.peers, tls, self.credentials()?).map(Some)
+    }
+    /// The identity of this relay, `None` without `id` and `private_key`.
+    pub fn credentials(&self) -> Result<Option<Credentials>, Error> {
+        match (&self.id, &self.private_key) {
+            (Some(id), Some(path)) => Credentials::new(id, &fs::read_to_string(path)?).map(Some),
+            (None, None) => Ok(None),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "a relay identity requires both id and private_key",
+            )),
+        }
+    }
+    pub fn retention(&self) -> Retention {
+        Retention {
+            max_messages: self.max_messages,
+            max_age: self.max_age.map(Duration::from_secs),
+            max_bytes: self.max_bytes,
+            max_nodes: Some(self.max_nodes.unwrap_or(DEFAULT_MAX_NODES)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Config;
+
+    #[test]
+    fn config_test() {
+        let file: Config = toml::from_str(
+            r#"
+            address = "0.0.0.0:80"
+            timeout = 30
+            max_header_size = 1024
+            max_age = 60
+            admins = ["1"]
+            relays = ["2"]
+            peers = ["http://127.0.0.1:9777"]
+
+            [keys]
+            1 = "1b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f"
+            "#,
+        )
+        .unwrap();
+        let cli = Config {
+            address: Some("127.0.0.1:8080".to_string()),
+            max_messages: Some(10),
+            ..Default::default()
+        };
+        let config = cli.or(file);
+        // the command line wins
+        assert_eq!(config.address(), "127.0.0.1:8080");
+        assert_eq!(config.timeout(), Duration::from_secs(30));
+        assert_eq!(config.threads(), 256);
+        let limits = config.limits();
+        assert_eq!(limits.max_header_size, 1024);
+        assert_eq!(limits.max_content_length, 8 * 1024 * 1024);
+        assert_eq!(limits.max_read_time, Duration::from_secs(30));
+        let retention = config.retention();
+        assert_eq!(retention.max_messages, Some(10));
+        assert_eq!(retention.max_age, Some(Duration::from_secs(60)));
+        assert_eq!(retention.max_bytes, None);
+        assert_eq!(retention.max_nodes, Some(1024));
+        assert_eq!(config.max_namespaces(), 1024);
+        let auth = config.auth().unwrap().unwrap();
+        assert!(auth.is_admin("1"));
+        assert!(auth.is_relay("2"));
+        assert!(Config::default().auth().unwrap().is_none());
+        assert!(config.tls().unwrap().is_none());
+        assert_eq!(config.peers, ["http://127.0.0.1:9777"]);
+        assert!(config.replicator().is_err());
+        assert!(Config::default().replicator().unwrap().is_none());
+        let tls_key_only = Config {
+            tls_key: Some("relay.key".into()),
+            ..Default::default()
+        };
+        assert!(tls_key_only.tls().is_err());
+        let id_only = Config {
+            id: Some("relay".to_string()),
+            ..Default::default()
+        };
+        assert!(id_only.credentials().is_err());
+        assert!(toml::from_str::<Config>("port = 80").is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{Error, ErrorKind},
+    str::FromStr,
+};
+
+use crate::{
+    batch::{decode_batch, encode_batch},
+    http::{io_error, ToIoResult},
+    url::QueryEx,
+};
+
+const INDEX_SIZE: usize = 8;
+const ORIGIN_LENGTH_SIZE: usize = 2;
+
+const BROADCAST: u8 = 0;
+const DIRECT: u8 = 1;
+// the kinds of a message with an origin
+const BROADCAST_ORIGIN: u8 = 2;
+const DIRECT_ORIGIN: u8 = 3;
+
+/// A message returned by a cursor read with its index.
+///
+/// Broadcast messages are counted in a namespace, like the read positions, and messages
+/// posted to a node are counted for each node.
+///
+/// In a cluster, every relay counts the messages itself. `origin` is the same on every relay,
+/// the id of the relay which the message was posted to and its index there, like `relay0/5`.
+/// A node which reads from several relays recognizes a message by it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Indexed {
+    pub index: usize,
+    pub direct: bool,
+    pub msg: Vec<u8>,
+    pub origin: Option<String>,
+}
+
+/// The last messages a node has processed, `None` if it hasn't processed any message of
+/// the kind yet.
+///
+/// A cursor read (`GET /?id=alice&after=5&direct_after=2`) returns the messages after the
+/// cursor and doesn't change the read position on the server, so a message is returned
+/// until the node has processed it. The server only removes the messages to the node which
+/// are before the cursor.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub after: Option<usize>,
+    pub direct_after: Option<usize>,
+}
+
+impl Cursor {
+    /// Parses the `after` and `direct_after` parameters of a query, an empty or missing
+    /// parameter is `None`.
+    pub fn from_query(query: &HashMap<&str, &str>) -> Result<Self, Error> {
+        let index = |name| match query.get(name) {
+            None | Some(&"") => Ok(None),
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("invalid {name}"))),
+        };
+        Ok(Cursor {
+            after: index("after")?,
+            direct_after: index("direct_after")?,
+        })
+    }
+    /// Whether the message comes after the cursor.
+    pub fn is_before(&self, message: &Indexed) -> bool {
+        let after = if message.direct {
+            self.direct_after
+        } else {
+            self.after
+        };
+        !matches!(after, Some(after) if after >= message.index)
+    }
+    /// Moves the cursor to a processed message.
+    pub fn advance(&mut self, message: &Indexed) {
+        if self.is_before(message) {
+            let after = if message.direct {
+                &mut self.direct_after
+            } else {
+                &mut self.after
+            };
+            *after = Some(message.index);
+        }
+    }
+}
+
+/// The query parameters, for example `after=5&direct_after=`.
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let index = |index: Option<usize>| index.map(|i| i.to_string()).unwrap_or_default();
+        write!(
+            f,
+            "after={}&direct_after={}",
+            index(self.after),
+            index(self.direct_after)
+        )
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = Error;
+    fn from_str(query: &str) -> Result<Self, Error> {
+        Cursor::from_query(&format!("?{query}").url_query())
+    }
+}
+
+/// Packs the messages of a cursor read into one response body.
+///
+/// Every message is prefixed with its kind, `0` for broadcast and `1` for direct, and its
+/// index as a big-endian `u64`, and the results are packed with `encode_batch`. A message
+/// with an origin is of the kind `2` or `3`, and the index is followed by the length of the
+/// origin as a big-endian `u16` and the origin.
+pub fn encode_indexed(messages: &[Indexed]) -> Vec<u8> {
+    let messages: Vec<_> = messages
+        .iter()
+        .map(|message| {
+            let mut result = match (message.direct, &message.origin) {
+                (false, None) => vec![BROADCAST],
+                (true, None) => vec![DIRECT],
+                (false, Some(_)) => vec![BROADCAST_ORIGIN],
+                (true, Some(_)) => vec![DIRECT_ORIGIN],
+            };
+            result.extend((message.index as u64).to_be_bytes());
+            if let Some(origin) = &message.origin {
+                result.extend((origin.len() as u16).to_be_bytes());
+                result.extend(origin.as_bytes());
+            }
+            result.extend(&message.msg);
+            result
+        })
+        .collect();
+    encode_batch(&messages)
+}
+
+/// Unpacks a response body created by `encode_indexed`.
+pub fn decode_indexed(content: &[u8]) -> Result<Vec<Indexed>, Error> {
+    decode_batch(content)?
+        .into_iter()
+        .map(|message| {
+            if message.len() < 1 + INDEX_SIZE {
+                return Err(io_error("incomplete indexed message"));
+            }
+            let (direct, has_origin) = match message[0] {
+                BROADCAST => (false, false),
+                DIRECT => (true, false),
+                BROADCAST_ORIGIN => (false, true),
+                DIRECT_ORIGIN => (true, true),
+                _ => return Err(io_error("unknown message kind")),
+            };
+            let index = u64::from_be_bytes(
+                message[1..1 + INDEX_SIZE]
+                    .try_into()
+                    .to_io_result("invalid index")?,
+            );
+            let mut rest = &message[1 + INDEX_SIZE..];
+            let origin = if has_origin {
+                if rest.len() < ORIGIN_LENGTH_SIZE {
+                    return Err(io_error("incomplete origin"));
+                }
+                let (len, tail) = rest.split_at(ORIGIN_LENGTH_SIZE);
+                let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+                if tail.len() < len {
+                    return Err(io_error("incomplete origin"));
+                }
+                let (origin, tail) = tail.split_at(len);
+                rest = tail;
+                Some(String::from_utf8(origin.to_vec()).to_io_result("invalid origin")?)
+            } else {
+                None
+            };
+            Ok(Indexed {
+                index: index as usize,
+                direct,
+                msg: rest.to_vec(),
+                origin,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::url::QueryEx;
+
+    use super::{decode_indexed, encode_indexed, Cursor, Indexed};
+
+    #[test]
+    fn round_trip_test() {
+        let messages = [
+            Indexed {
+                index: 5,
+                direct: false,
+                msg: "Msg # 5".as_bytes().to_vec(),
+                origin: None,
+            },
+            Indexed {
+                index: 0,
+                direct: true,
+                msg: Vec::default(),
+                origin: None,
+            },
+            Indexed {
+                index: 7,
+                direct: true,
+                msg: "Msg # 7".as_bytes().to_vec(),
+                origin: Some("relay1/3".to_string()),
+            },
+            Indexed {
+                index: 6,
+                direct: false,
+                msg: Vec::default(),
+                origin: Some("relay1/2".to_string()),
+            },
+        ];
+        assert_eq!(
+            decode_indexed(&encode_indexed(&messages)).unwrap(),
+            messages
+        );
+        assert!(decode_indexed(&[0, 0, 0, 2, 0, 0]).is_err());
+        // an origin longer than the message
+        assert!(decode_indexed(&[0, 0, 0, 12, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 9, 0]).is_err());
+    }
+
+    #[test]
+    fn cursor_test() {
+        let mut cursor = Cursor::default();
+        assert_eq!(cursor.to_string(), "after=&direct_after=");
+        let message = |index, direct| Indexed {
+            index,
+            direct,
+            msg: Vec::default(),
+            origin: None,
+        };
+        cursor.advance(&message(3, false));
+        cursor.advance(&message(0, true));
+        assert!(!cursor.is_before(&message(3, false)));
+        assert!(cursor.is_before(&message(1, true)));
+        // a message before the cursor doesn't move it back
+        cursor.advance(&message(2, false));
+        assert_eq!(cursor.to_string(), "after=3&direct_after=0");
+        let url = format!("/?id=x&{cursor}");
+        assert_eq!(Cursor::from_query(&url.url_query()).unwrap(), cursor);
+        assert_eq!("after=3&direct_after=0".parse::<Cursor>().unwrap(), cursor);
+        assert!("after=x".parse::<Cursor>().is_err());
+    }
+}
@@ -0,0 +1,782 @@
+use std::{
+    collections::BTreeMap,
+    fs::{remove_file, rename, File, OpenOptions},
+    io::{Error, ErrorKind, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    batch::{decode_batch, encode_batch},
+    cursor::{Cursor, Indexed},
+    http::{io_error, ToIoResult},
+    mem_state::{MemState, Retention},
+    state::State,
+    storage::Storage,
+};
+
+const LENGTH_SIZE: usize = 4;
+
+// the log is rewritten once it has this many records more than the state needs.
+const COMPACT_MIN_RECORDS: usize = 1024;
+
+// a message with its time, and its origin if another relay forwarded it.
+const POST: u8 = 0;
+// a message to a node with the index of the broadcast message posted after it, its time and
+// its origin.
+const POST_TO: u8 = 1;
+// the index of the first unread message of a node.
+const POSITION: u8 = 2;
+// a number of messages a node read from its direct messages, only in old logs.
+const DIRECT_READ: u8 = 3;
+// the index of the first message, only at the start of a log.
+const OFFSET: u8 = 4;
+// the index of the first kept message to a node, the messages before it are dropped.
+const DIRECT_OFFSET: u8 = 5;
+// a node which the state forgot with its read position and messages.
+const FORGET: u8 = 6;
+
+/// A `MemState` which survives restarts.
+///
+/// Every change of the state is appended to a log file and synced to the disk before it is
+/// acknowledged, and the log is replayed by `open`, so messages and each node's read
+/// position are restored. A record which was only partially written, for example because
+/// the relay was killed, is dropped from the end of the log. The log is rewritten with only
+/// the records needed for the current state when it is opened and when it grows, so dropped
+/// messages don't take space.
+///
+/// A failed write to the log is returned, then the state may be ahead of the log and has to
+/// be opened again, so it doesn't serve messages which would be lost on the next restart.
+pub struct FileState {
+    state: MemState,
+    path: PathBuf,
+    log: File,
+    records: usize,
+    compacted_records: usize,
+}
+
+impl FileState {
+    /// Opens or creates the log file and restores the state from it.
+    pub fn open(path: impl AsRef<Path>, retention: Retention) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut content = Vec::default();
+        if path.exists() {
+            File::open(&path)?.read_to_end(&mut content)?;
+        }
+
+        let mut state = MemState::with_retention(retention);
+        let mut rest = content.as_slice();
+        while rest.len() >= LENGTH_SIZE {
+            let (len, tail) = rest.split_at(LENGTH_SIZE);
+            let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+            if tail.len() < len {
+                // an incomplete record is not written again by `compact`.
+                break;
+            }
+            let (record, tail) = tail.split_at(len);
+            replay(&mut state, decode_batch(record)?)?;
+            rest = tail;
+        }
+        // the forgotten nodes are logged already
+        state.take_forgotten();
+
+        let (log, records) = compact(&path, &state)?;
+        Ok(FileState {
+            state,
+            path,
+            log,
+            records,
+            compacted_records: records,
+        })
+    }
+
+    fn append(&mut self, fields: &[Vec<u8>]) -> Result<(), Error> {
+        self.log.write_all(&record(fields))?;
+        self.log.sync_data()?;
+        self.records += 1;
+        if self.records >= COMPACT_MIN_RECORDS.max(2 * self.compacted_records) {
+            let (log, records) = compact(&self.path, &self.state)?;
+            self.log = log;
+            self.records = records;
+            self.compacted_records = records;
+        }
+        Ok(())
+    }
+
+    // logs the changes of a read, `result` is not empty.
+    fn append_read(
+        &mut self,
+        node_id: String,
+        position: Option<usize>,
+        direct_offset: usize,
+    ) -> Result<(), Error> {
+        if self.state.highwaters.get(&node_id).copied() != position {
+            let position = self.state.highwaters[&node_id];
+            self.append(&[
+                vec![POSITION],
+                node_id.clone().into_bytes(),
+                number(position),
+            ])?;
+        }
+        let offset = self.state.direct_offset(&node_id);
+        if offset != direct_offset {
+            self.append(&[vec![DIRECT_OFFSET], node_id.into_bytes(), number(offset)])?;
+        }
+        Ok(())
+    }
+
+    fn post_from(&mut self, msg: Vec<u8>, origin: Option<String>) -> Result<(), Error> {
+        let time = SystemTime::now();
+        // the state is changed first, so a compaction while appending includes the message.
+        self.state.post_at(time, msg.clone(), origin.clone());
+        let mut fields = vec![vec![POST], number(millis(time)), msg];
+        fields.extend(origin.map(String::into_bytes));
+        self.append(&fields)
+    }
+
+    fn post_to_from(
+        &mut self,
+        node_id: String,
+        msg: Vec<u8>,
+        origin: Option<String>,
+    ) -> Result<(), Error> {
+        let before = self.state.queue().end;
+        let time = SystemTime::now();
+        match origin.clone() {
+            Some(origin) => self
+                .state
+                .post_to_replica(node_id.clone(), msg.clone(), origin)?,
+            None => self.state.post_to(node_id.clone(), msg.clone())?,
+        }
+        self.append_forgotten()?;
+        let mut fields = vec![
+            vec![POST_TO],
+            node_id.into_bytes(),
+            msg,
+            number(before),
+            number(millis(time)),
+        ];
+        fields.extend(origin.map(String::into_bytes));
+        self.append(&fields)
+    }
+
+    // logs the nodes which the state forgot to keep the number of nodes.
+    fn append_forgotten(&mut self) -> Result<(), Error> {
+        for node_id in self.state.take_forgotten() {
+            self.append(&[vec![FORGET], node_id.into_bytes()])?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps every namespace in a `FileState`. The messages of the root namespace are stored
+/// in `path`, the messages of a namespace `a/b` in `path.a.b`.
+pub struct FileStorage {
+    path: PathBuf,
+    retention: Retention,
+}
+
+impl FileStorage {
+    pub fn new(path: impl AsRef<Path>, retention: Retention) -> Self {
+        FileStorage {
+            path: path.as_ref().to_path_buf(),
+            retention,
+        }
+    }
+    fn path(&self, namespace: &str) -> PathBuf {
+        let mut path = self.path.as_os_str().to_owned();
+        for segment in namespace.split('/').filter(|s| !s.is_empty()) {
+            path.push(".");
+            path.push(segment);
+        }
+        path.into()
+    }
+}
+
+impl Storage for FileStorage {
+    type State = FileState;
+    fn open(&mut self, namespace: &str) -> Result<FileState, Error> {
+        FileState::open(self.path(namespace), self.retention)
+    }
+    fn exists(&self, namespace: &str) -> bool {
+        self.path(namespace).exists()
+    }
+    fn remove(&mut self, namespace: &str) -> Result<(), Error> {
+        match remove_file(self.path(namespace)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn record(fields: &[Vec<u8>]) -> Vec<u8> {
+    encode_batch(&[encode_batch(fields)])
+}
+
+// writes a new log with the records needed for `state` and replaces the old log with it.
+fn compact(path: &Path, state: &MemState) -> Result<(File, usize), Error> {
+    let mut records = vec![record(&[vec![OFFSET], number(state.offset)])];
+    records.extend(state.queue.iter().enumerate().map(|(i, (time, msg))| {
+        let mut fields = vec![vec![POST], number(millis(*time)), msg.clone()];
+        if let Some(origin) = state.origins.get(&(state.offset + i)) {
+            fields.push(origin.clone().into_bytes());
+        }
+        record(&fields)
+    }));
+    // the offsets come first, so they don't drop the messages
+    records.extend(state.direct_offsets.iter().map(|(node_id, offset)| {
+        record(&[
+            vec![DIRECT_OFFSET],
+            node_id.clone().into_bytes(),
+            number(*offset),
+        ])
+    }));
+    for (node_id, direct) in &state.direct {
+        records.extend(direct.iter().map(|direct| {
+            let mut fields = vec![
+                vec![POST_TO],
+                node_id.clone().into_bytes(),
+                direct.msg.clone(),
+                number(direct.before),
+                number(millis(direct.time)),
+            ];
+            if let Some(origin) = &direct.origin {
+                fields.push(origin.clone().into_bytes());
+            }
+            record(&fields)
+        }));
+    }
+    records.extend(state.highwaters.iter().map(|(node_id, position)| {
+        record(&[
+            vec![POSITION],
+            node_id.clone().into_bytes(),
+            number(*position),
+        ])
+    }));
+
+    // `~` is not in any namespace, so the file is not the log of another namespace.
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push("~");
+    {
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&records.concat())?;
+        tmp.sync_all()?;
+    }
+    rename(&tmp_path, path)?;
+    let log = OpenOptions::new().append(true).open(path)?;
+    Ok((log, records.len()))
+}
+
+fn replay(state: &mut MemState, record: Vec<Vec<u8>>) -> Result<(), Error> {
+    let mut fields = record.into_iter();
+    let tag = fields.next().unwrap_or_default();
+    let mut next = || {
+        fields
+            .next()
+            .ok_or_else(|| io_error("incomplete log record"))
+    };
+    match tag.as_slice() {
+        [POST] => {
+            let time = parse_time(next()?)?;
+            let msg = next()?;
+            let origin = fields.next().map(text).transpose()?;
+            state.post_at(time, msg, origin)
+        }
+        [POST_TO] => {
+            let node_id = node_id(next()?)?;
+            let msg = next()?;
+            // an old log has neither the index nor the time
+            let before = match fields.next() {
+                Some(before) => parse_number(before)?,
+                None => state.queue().end,
+            };
+            let time = match fields.next() {
+                Some(time) => parse_time(time)?,
+                None => SystemTime::now(),
+            };
+            let origin = fields.next().map(text).transpose()?;
+            state.post_to_at(node_id, before, time, msg, origin)
+        }
+        [POSITION] => {
+            let node_id = node_id(next()?)?;
+            state.set_position(node_id, parse_number(next()?)?)
+        }
+        [DIRECT_READ] => {
+            let node_id = node_id(next()?)?;
+            for _ in 0..parse_number(next()?)? {
+                state.get_direct(&node_id);
+            }
+        }
+        [OFFSET] => state.set_offset(parse_number(next()?)?),
+        [DIRECT_OFFSET] => {
+            let node_id = node_id(next()?)?;
+            state.set_direct_offset(node_id, parse_number(next()?)?)
+        }
+        [FORGET] => state.forget(node_id(next()?)?),
+        _ => return Err(io_error("unknown log record")),
+    }
+    Ok(())
+}
+
+fn number(value: usize) -> Vec<u8> {
+    (value as u64).to_be_bytes().to_vec()
+}
+
+fn millis(time: SystemTime) -> usize {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as usize
+}
+
+fn parse_time(field: Vec<u8>) -> Result<SystemTime, Error> {
+    Ok(UNIX_EPOCH + Duration::from_millis(parse_number(field)? as u64))
+}
+
+fn parse_number(field: Vec<u8>) -> Result<usize, Error> {
+    let bytes: [u8; 8] = field.try_into().to_io_result("invalid number")?;
+    Ok(u64::from_be_bytes(bytes) as usize)
+}
+
+fn node_id(field: Vec<u8>) -> Result<String, Error> {
+    text(field)
+}
+
+fn text(field: Vec<u8>) -> Result<String, Error> {
+    String::from_utf8(field).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+impl State for FileState {
+    // reads which return nothing don't change the read positions and are not logged.
+    fn get(&mut self, node_id: String) -> Result<Vec<u8>, Error> {
+        let position = self.state.highwaters.get(&node_id).copied();
+        let direct_offset = self.state.direct_offset(&node_id);
+        let result = self.state.get(node_id.clone())?;
+        self.append_forgotten()?;
+        if !result.is_empty() {
+            self.append_read(node_id, position, direct_offset)?;
+        }
+        Ok(result)
+    }
+    fn get_all(&mut self, node_id: String) -> Result<Vec<Vec<u8>>, Error> {
+        let position = self.state.highwaters.get(&node_id).copied();
+        let direct_offset = self.state.direct_offset(&node_id);
+        let result = self.state.get_all(node_id.clone())?;
+        self.append_forgotten()?;
+        if !result.is_empty() {
+            self.append_read(node_id, position, direct_offset)?;
+        }
+        Ok(result)
+    }
+    fn post(&mut self, msg: Vec<u8>) -> Result<(), Error> {
+        self.post_from(msg, None)
+    }
+    fn post_to(&mut self, node_id: String, msg: Vec<u8>) -> Result<(), Error> {
+        self.post_to_from(node_id, msg, None)
+    }
+    fn post_replica(&mut self, msg: Vec<u8>, origin: String) -> Result<(), Error> {
+        self.post_from(msg, Some(origin))
+    }
+    fn post_to_replica(
+        &mut self,
+        node_id: String,
+        msg: Vec<u8>,
+        origin: String,
+    ) -> Result<(), Error> {
+        self.post_to_from(node_id, msg, Some(origin))
+    }
+    fn start(&mut self, node_id: String) -> Result<(), Error> {
+        let started = !self.state.highwaters.contains_key(&node_id);
+        self.state.start(node_id.clone())?;
+        self.append_forgotten()?;
+        if !started {
+            return Ok(());
+        }
+        let position = self.state.highwaters[&node_id];
+        self.append(&[vec![POSITION], node_id.into_bytes(), number(position)])
+    }
+    fn read(&mut self, node_id: String, cursor: Cursor) -> Result<Vec<Indexed>, Error> {
+        let position = self.state.highwaters.get(&node_id).copied();
+        let direct_offset = self.state.direct_offset(&node_id);
+        let result = self.state.read(node_id.clone(), cursor)?;
+        self.append_forgotten()?;
+        self.append_read(node_id, position, direct_offset)?;
+        Ok(result)
+    }
+    fn reset(&mut self, node_id: String) -> Result<(), Error> {
+        self.state.reset(node_id.clone())?;
+        self.append_forgotten()?;
+        let position = self.state.highwaters[&node_id];
+        self.append(&[vec![POSITION], node_id.into_bytes(), number(position)])
+    }
+    fn queue(&self) -> Range<usize> {
+        self.state.queue()
+    }
+    fn highwaters(&self) -> BTreeMap<String, usize> {
+        self.state.highwaters()
+    }
+    fn direct_len(&self, node_id: &str) -> usize {
+        self.state.direct_len(node_id)
+    }
+    fn direct_end(&self, node_id: &str) -> usize {
+        self.state.direct_end(node_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{remove_file, OpenOptions},
+        io::Write,
+        path::PathBuf,
+    };
+
+    use super::{
+        Cursor, FileState, FileStorage, Indexed, Retention, State, Storage, COMPACT_MIN_RECORDS,
+    };
+
+    fn log_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("relay-server-{}-{}.log", name, std::process::id()));
+        let _ = remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn restart_test() {
+        let path = log_path("restart");
+        {
+            let mut state = FileState::open(&path, Retention::default()).unwrap();
+            state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+            state.post("Msg # 1".as_bytes().to_vec()).unwrap();
+            state
+                .post_to(2.to_string(), "Msg # 2".as_bytes().to_vec())
+                .unwrap();
+            state
+                .post_to(3.to_string(), "Msg # 3".as_bytes().to_vec())
+                .unwrap();
+            assert_eq!(
+                "Msg # 0".as_bytes().to_vec(),
+                state.get(1.to_string()).unwrap()
+            );
+            assert_eq!(
+                vec![
+                    "Msg # 0".as_bytes().to_vec(),
+                    "Msg # 1".as_bytes().to_vec(),
+                    "Msg # 2".as_bytes().to_vec()
+                ],
+                state.get_all(2.to_string()).unwrap()
+            );
+        }
+        {
+            let mut state = FileState::open(&path, Retention::default()).unwrap();
+            assert_eq!(
+                "Msg # 1".as_bytes().to_vec(),
+                state.get(1.to_string()).unwrap()
+            );
+            assert!(state.get(1.to_string()).unwrap().is_empty());
+            assert!(state.get_all(2.to_string()).unwrap().is_empty());
+            assert_eq!(
+                vec![
+                    "Msg # 0".as_bytes().to_vec(),
+                    "Msg # 1".as_bytes().to_vec(),
+                    "Msg # 3".as_bytes().to_vec()
+                ],
+                state.get_all(3.to_string()).unwrap()
+            );
+            state.post("Msg # 4".as_bytes().to_vec()).unwrap();
+        }
+        {
+            let mut state = FileState::open(&path, Retention::default()).unwrap();
+            for node_id in 1..=3 {
+                assert_eq!(
+                    vec!["Msg # 4".as_bytes().to_vec()],
+                    state.get_all(node_id.to_string()).unwrap()
+                );
+            }
+        }
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn order_test() {
+        let path = log_path("order");
+        {
+            let mut state = FileState::open(&path, Retention::default()).unwrap();
+            state
+                .post_to(1.to_string(), "Msg # 0".as_bytes().to_vec())
+                .unwrap();
+            state.post("Msg # 1".as_bytes().to_vec()).unwrap();
+        }
+        {
+            // the message to the node still comes first
+            let mut state = FileState::open(&path, Retention::default()).unwrap();
+            assert_eq!(
+                vec!["Msg # 0".as_bytes().to_vec(), "Msg # 1".as_bytes().to_vec()],
+                state.get_all(1.to_string()).unwrap()
+            );
+        }
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_test() {
+        let path = log_path("read");
+        {
+            let mut state = FileState::open(&path, Retention::default()).unwrap();
+            state
+                .post_to(1.to_string(), "Msg # 0".as_bytes().to_vec())
+                .unwrap();
+            state
+                .post_to(1.to_string(), "Msg # 1".as_bytes().to_vec())
+                .unwrap();
+            let cursor = Cursor {
+                after: None,
+                direct_after: Some(0),
+            };
+            assert_eq!(1, state.read(1.to_string(), cursor).unwrap().len());
+        }
+        {
+            // the indices survive a restart
+            let mut state = FileState::open(&path, Retention::default()).unwrap();
+            assert_eq!(
+                vec![Indexed {
+                    index: 1,
+                    direct: true,
+                    msg: "Msg # 1".as_bytes().to_vec(),
+                    origin: None,
+                }],
+                state.read(1.to_string(), Cursor::default()).unwrap()
+            );
+        }
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reset_test() {
+        let path = log_path("reset");
+        {
+            let mut state = FileState::open(&path, Retention::default()).unwrap();
+            state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+            state.post("Msg # 1".as_bytes().to_vec()).unwrap();
+            assert_eq!(2, state.get_all(1.to_string()).unwrap().len());
+            state.reset(1.to_string()).unwrap();
+        }
+        {
+            // the reset survives a restart
+            let mut state = FileState::open(&path, Retention::default()).unwrap();
+            assert_eq!(0..2, state.queue());
+            assert_eq!(Some(&0), state.highwaters().get("1"));
+            assert_eq!(2, state.get_all(1.to_string()).unwrap().len());
+        }
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn incomplete_record_test() {
+        let path = log_path("incomplete");
+        {
+            let mut state = FileState::open(&path, Retention::default()).unwrap();
+            state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+        }
+        // a record which was cut off while it was written
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[0, 0, 0, 20, 0, 0])
+            .unwrap();
+        {
+            let mut state = FileState::open(&path, Retention::default()).unwrap();
+            state.post("Msg # 1".as_bytes().to_vec()).unwrap();
+        }
+        {
+            let mut state = FileState::open(&path, Retention::default()).unwrap();
+            assert_eq!(
+                vec!["Msg # 0".as_bytes().to_vec(), "Msg # 1".as_bytes().to_vec()],
+                state.get_all(1.to_string()).unwrap()
+            );
+        }
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn compact_test() {
+        let path = log_path("compact");
+        let retention = Retention {
+            max_messages: Some(2),
+            ..Default::default()
+        };
+        {
+            let mut state = FileState::open(&path, retention).unwrap();
+            for i in 0..COMPACT_MIN_RECORDS {
+                state.post(format!("Msg # {i}").into_bytes()).unwrap();
+            }
+            assert!(state.records < COMPACT_MIN_RECORDS);
+            assert_eq!(
+                vec![
+                    format!("Msg # {}", COMPACT_MIN_RECORDS - 2).into_bytes(),
+                    format!("Msg # {}", COMPACT_MIN_RECORDS - 1).into_bytes()
+                ],
+                state.get_all(1.to_string()).unwrap()
+            );
+            state
+                .post_to(2.to_string(), "Msg # 2".as_bytes().to_vec())
+                .unwrap();
+            state
+                .post_to(2.to_string(), "Msg # 3".as_bytes().to_vec())
+                .unwrap();
+            state.post("Last".as_bytes().to_vec()).unwrap();
+            assert_eq!(
+                "Last".as_bytes().to_vec(),
+                state.get(1.to_string()).unwrap()
+            );
+            state.post("New".as_bytes().to_vec()).unwrap();
+            state.start(3.to_string()).unwrap();
+        }
+        {
+            let mut state = FileState::open(&path, retention).unwrap();
+            // an offset, two messages, two direct messages and two positions
+            assert_eq!(state.records, 7);
+            assert_eq!(
+                vec!["New".as_bytes().to_vec()],
+                state.get_all(1.to_string()).unwrap()
+            );
+            assert_eq!(
+                vec![
+                    "Msg # 2".as_bytes().to_vec(),
+                    "Msg # 3".as_bytes().to_vec(),
+                    "Last".as_bytes().to_vec(),
+                    "New".as_bytes().to_vec()
+                ],
+                state.get_all(2.to_string()).unwrap()
+            );
+            assert!(state.get_all(3.to_string()).unwrap().is_empty());
+        }
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn origin_test() {
+        let path = log_path("origin");
+        {
+            let mut state = FileState::open(&path, Retention::default()).unwrap();
+            state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+            state
+                .post_replica("Msg # 1".as_bytes().to_vec(), "relay1/0".to_string())
+                .unwrap();
+            state
+                .post_to_replica(
+                    1.to_string(),
+                    "Msg # 2".as_bytes().to_vec(),
+                    "relay1/0".to_string(),
+                )
+                .unwrap();
+        }
+        let state = FileState::open(&path, Retention::default());
+        let origins: Vec<_> = state
+            .unwrap()
+            .read(1.to_string(), Cursor::default())
+            .unwrap()
+            .into_iter()
+            .map(|message| message.origin)
+            .collect();
+        assert_eq!(
+            origins,
+            [
+                None,
+                Some("relay1/0".to_string()),
+                Some("relay1/0".to_string())
+            ]
+        );
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn forget_test() {
+        let path = log_path("forget");
+        let retention = Retention {
+            max_nodes: Some(1),
+            ..Default::default()
+        };
+        {
+            let mut state = FileState::open(&path, retention).unwrap();
+            state
+                .post_to(1.to_string(), "Msg # 0".as_bytes().to_vec())
+                .unwrap();
+            state
+                .post_to(2.to_string(), "Msg # 1".as_bytes().to_vec())
+                .unwrap();
+        }
+        {
+            // the forgotten node stays forgotten
+            let mut state = FileState::open(&path, retention).unwrap();
+            assert_eq!(0, state.direct_len("1"));
+            assert_eq!(
+                vec!["Msg # 1".as_bytes().to_vec()],
+                state.get_all(2.to_string()).unwrap()
+            );
+        }
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tmp_namespace_test() {
+        let path = log_path("tmp-namespace");
+        let mut storage = FileStorage::new(&path, Retention::default());
+        let mut tmp = storage.open("tmp").unwrap();
+        tmp.post("Msg # 0".as_bytes().to_vec()).unwrap();
+        // compacting the root log doesn't replace the log of the namespace `tmp`
+        storage.open("").unwrap();
+        tmp.post("Msg # 1".as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            2,
+            storage
+                .open("tmp")
+                .unwrap()
+                .get_all(1.to_string())
+                .unwrap()
+                .len()
+        );
+        assert!(storage
+            .open("")
+            .unwrap()
+            .get_all(1.to_string())
+            .unwrap()
+            .is_empty());
+        storage.remove("tmp").unwrap();
+        storage.remove("").unwrap();
+    }
+
+    #[test]
+    fn storage_test() {
+        let path = log_path("storage");
+        let mut storage = FileStorage::new(&path, Retention::default());
+        let round = PathBuf::from(format!("{}.rounds.5", path.display()));
+        {
+            let mut state = storage.open("rounds/5").unwrap();
+            state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+        }
+        assert!(round.exists());
+        assert!(!path.exists());
+        assert_eq!(
+            vec!["Msg # 0".as_bytes().to_vec()],
+            storage
+                .open("rounds/5")
+                .unwrap()
+                .get_all(1.to_string())
+                .unwrap()
+        );
+        storage.remove("rounds/5").unwrap();
+        assert!(!round.exists());
+        assert!(storage
+            .open("")
+            .unwrap()
+            .get_all(1.to_string())
+            .unwrap()
+            .is_empty());
+        assert!(path.exists());
+        storage.remove("").unwrap();
+        storage.remove("").unwrap();
+    }
+}
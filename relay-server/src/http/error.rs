@@ -0,0 +1,76 @@
+use std::{collections::HashMap, error, fmt, io::Error};
+
+use super::Response;
+
+/// An error which is sent to the client as a response.
+#[derive(Debug)]
+pub struct HttpError {
+    pub code: u16,
+    pub message: String,
+}
+
+impl HttpError {
+    pub fn new(code: u16, message: impl Into<String>) -> Self {
+        HttpError {
+            code,
+            message: message.into(),
+        }
+    }
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(400, message)
+    }
+}
+
+impl From<Error> for HttpError {
+    fn from(e: Error) -> Self {
+        Self::new(500, e.to_string())
+    }
+}
+
+impl From<HttpError> for Response {
+    fn from(e: HttpError) -> Self {
+        let mut headers = HashMap::default();
+        if e.code == 405 {
+            headers.insert("allow".to_string(), "GET, POST, DELETE".to_string());
+        }
+        let mut response = Response::with_code(e.code, e.message.into_bytes());
+        response.headers = headers;
+        response
+    }
+}
+
+/// The content of a message is larger than the limit.
+#[derive(Debug)]
+pub struct ContentTooLarge;
+
+impl fmt::Display for ContentTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("content too large")
+    }
+}
+
+impl error::Error for ContentTooLarge {}
+
+/// The first line and the headers of a message are larger than the limit.
+#[derive(Debug)]
+pub struct HeaderTooLarge;
+
+impl fmt::Display for HeaderTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("header too large")
+    }
+}
+
+impl error::Error for HeaderTooLarge {}
+
+/// A message wasn't read before its deadline.
+#[derive(Debug)]
+pub struct ReadTooSlow;
+
+impl fmt::Display for ReadTooSlow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("request not received in time")
+    }
+}
+
+impl error::Error for ReadTooSlow {}
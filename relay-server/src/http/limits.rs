@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+/// The default limit for the content of a request.
+pub const DEFAULT_MAX_CONTENT_LENGTH: usize = 8 * 1024 * 1024;
+/// The default limit for the first line and the headers of a request.
+pub const DEFAULT_MAX_HEADER_SIZE: usize = 8 * 1024;
+/// The default time to receive a request.
+pub const DEFAULT_MAX_READ_TIME: Duration = Duration::from_secs(30);
+
+/// The largest message which is read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Bytes of content, a larger request is answered with `413`.
+    pub max_content_length: usize,
+    /// Bytes of the first line and the headers, a larger request is answered with `431`.
+    pub max_header_size: usize,
+    /// Time to read a message after its first byte, a slower request is answered with `408`.
+    /// It is checked between reads, so a read can exceed it by the read timeout of the stream.
+    pub max_read_time: Duration,
+}
+
+impl Limits {
+    pub const UNLIMITED: Limits = Limits {
+        max_content_length: usize::MAX,
+        max_header_size: usize::MAX,
+        max_read_time: Duration::MAX,
+    };
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_content_length: DEFAULT_MAX_CONTENT_LENGTH,
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            max_read_time: DEFAULT_MAX_READ_TIME,
+        }
+    }
+}
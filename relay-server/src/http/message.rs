@@ -1,11 +1,15 @@
 use std::{
     collections::HashMap,
-    io::{Error, Read, Write},
+    io::{Error, ErrorKind, Read, Write},
+    time::Instant,
 };
 
-use super::{to_io_result::io_error, ToIoResult};
+use super::{
+    to_io_result::io_error, ContentTooLarge, HeaderTooLarge, Limits, ReadTooSlow, ToIoResult,
+};
 
 pub const PROTOCOL: &str = "HTTP/1.0";
+pub const PROTOCOL_1_1: &str = "HTTP/1.1";
 
 pub trait Message: Sized {
     fn new(
@@ -16,56 +20,94 @@ pub trait Message: Sized {
     fn first_line(&self) -> Vec<String>;
     fn headers(&self) -> &HashMap<String, String>;
     fn content(&self) -> &Vec<u8>;
+    /// An empty message without a length can only be read until the stream is closed.
+    fn needs_content_length(&self) -> bool {
+        false
+    }
 
+    /// Reads a message within the default `Limits`.
     fn read(i: &mut impl Read) -> Result<Self, Error> {
-        let mut read_byte = || -> Result<u8, Error> {
-            let mut buf = [0; 1];
-            i.read_exact(&mut buf)?;
-            Ok(buf[0])
-        };
-
-        let mut read_line = || -> Result<String, Error> {
-            let mut result = String::default();
-            loop {
-                let b = read_byte()?;
-                if b == 13 {
-                    break;
-                };
-                result.push(b as char);
-            }
-            if read_byte()? != 10 {
-                return Err(io_error("invalid HTTP line"));
+        Self::read_next(i)?.to_io_result("no message")
+    }
+    /// Reads a message within the default `Limits`, see `read_limited`.
+    fn read_next(i: &mut impl Read) -> Result<Option<Self>, Error> {
+        Self::read_limited(i, &Limits::default())
+    }
+    /// Reads a message, or returns `None` if the stream is closed or timed out before the
+    /// first byte of a message, for example between two messages on a persistent connection.
+    ///
+    /// A message over the `limits` is not read, the error contains `ContentTooLarge` or
+    /// `HeaderTooLarge`. Nothing is allocated for a content length above the limit.
+    /// A message which isn't read within `max_read_time` fails with `ReadTooSlow`.
+    fn read_limited(i: &mut impl Read, limits: &Limits) -> Result<Option<Self>, Error> {
+        let mut first = [0; 1];
+        loop {
+            match i.read(&mut first) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                // a TLS peer may close the connection without a `close_notify`
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::UnexpectedEof
+                    ) =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
             }
-            Ok(result)
+        }
+        let mut i = Deadline {
+            inner: first.as_slice().chain(i),
+            deadline: Instant::now().checked_add(limits.max_read_time),
         };
 
+        // the first byte is already read
+        let mut header_size = limits.max_header_size.saturating_sub(1);
+
         // read and parse the request line
-        let first_line = read_line()?.split(' ').map(str::to_string).collect();
+        let first_line = read_line(&mut i, &mut header_size)?
+            .split(' ')
+            .map(str::to_string)
+            .collect();
 
         // read and parse headers
-        let mut content_length = 0;
         let mut headers = HashMap::default();
-        loop {
-            let line = read_line()?;
-            if line.is_empty() {
-                break;
-            }
-            let (name, value) = {
-                let (name, value) = line.split_once(':').to_io_result("")?;
-                (name.to_lowercase(), value.trim())
-            };
-            if name == "content-length" {
-                content_length = value.parse().to_io_result("invalid content-length")?;
-            } else {
-                headers.insert(name, value.to_string());
-            }
-        }
+        let body = read_headers(&mut i, &mut headers, &mut header_size)?;
 
-        let mut content = vec![0; content_length];
-        i.read_exact(content.as_mut_slice())?;
+        let content = match body {
+            Body::Chunked => {
+                // every chunk is `<hex size>[;extensions]\r\n<data>\r\n`, the last chunk is empty.
+                let mut content = Vec::default();
+                loop {
+                    // a chunk size line is limited like a header line
+                    let mut line_size = limits.max_header_size;
+                    let line = read_line(&mut i, &mut line_size)?;
+                    let size = line.split(';').next().unwrap_or_default().trim();
+                    let size =
+                        usize::from_str_radix(size, 16).to_io_result("invalid chunk size")?;
+                    if size == 0 {
+                        break;
+                    }
+                    if size > limits.max_content_length - content.len() {
+                        return Err(too_large());
+                    }
+                    content.extend(read_content(&mut i, size)?);
+                    if read_content(&mut i, 2)? != b"\r\n" {
+                        return Err(io_error("invalid chunk"));
+                    }
+                }
+                // trailer headers
+                read_headers(&mut i, &mut headers, &mut header_size)?;
+                content
+            }
+            Body::Length(len) if len > limits.max_content_length => return Err(too_large()),
+            Body::Length(len) => read_content(&mut i, len)?,
+        };
 
         // return the message
-        Self::new(first_line, headers, content)
+        Self::new(first_line, headers, content).map(Some)
     }
     fn write(&self, o: &mut impl Write) -> Result<(), Error> {
         const EOL: &[u8] = "\r\n".as_bytes();
@@ -86,7 +128,7 @@ pub trait Message: Sized {
         }
         let content = self.content();
         let len = content.len();
-        if len > 0 {
+        if len > 0 || self.needs_content_length() {
             write_header(CONTENT_LENGTH, len.to_string().as_bytes())?;
         }
         //These could cause partial writes. Should we check the returned number of written bytes?
@@ -95,3 +137,105 @@ pub trait Message: Sized {
         Ok(())
     }
 }
+
+// fails once the deadline is over, so a client trickling bytes can't hold a connection.
+struct Deadline<R> {
+    inner: R,
+    deadline: Option<Instant>,
+}
+
+impl<R: Read> Read for Deadline<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(Error::new(ErrorKind::TimedOut, ReadTooSlow));
+        }
+        self.inner.read(buf)
+    }
+}
+
+enum Body {
+    Length(usize),
+    Chunked,
+}
+
+fn read_byte(i: &mut impl Read) -> Result<u8, Error> {
+    let mut buf = [0; 1];
+    i.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+// reads a line, `size` is the number of bytes which may still be read.
+fn read_line(i: &mut impl Read, size: &mut usize) -> Result<String, Error> {
+    let mut result = String::default();
+    loop {
+        if *size < 2 {
+            return Err(Error::new(ErrorKind::InvalidData, HeaderTooLarge));
+        }
+        *size -= 1;
+        let b = read_byte(i)?;
+        if b == 13 {
+            break;
+        };
+        result.push(b as char);
+    }
+    *size -= 1;
+    if read_byte(i)? != 10 {
+        return Err(io_error("invalid HTTP line"));
+    }
+    Ok(result)
+}
+
+// reads headers until an empty line, `content-length` and `transfer-encoding` define the body.
+//
+// `transfer-encoding` takes precedence over `content-length` (RFC 9112, 6.3), so a proxy and
+// the server can't disagree where the message ends.
+fn read_headers(
+    i: &mut impl Read,
+    headers: &mut HashMap<String, String>,
+    size: &mut usize,
+) -> Result<Body, Error> {
+    let mut length = None;
+    let mut chunked = false;
+    loop {
+        let line = read_line(i, size)?;
+        if line.is_empty() {
+            return Ok(if chunked {
+                Body::Chunked
+            } else {
+                Body::Length(length.unwrap_or_default())
+            });
+        }
+        let (name, value) = {
+            let (name, value) = line.split_once(':').to_io_result("")?;
+            (name.to_lowercase(), value.trim())
+        };
+        if name == "content-length" {
+            let value = value.parse().to_io_result("invalid content-length")?;
+            if length.is_some_and(|length| length != value) {
+                return Err(io_error("invalid content-length"));
+            }
+            length = Some(value);
+        } else if name == "transfer-encoding" {
+            // other codings can't be decoded
+            if !value.eq_ignore_ascii_case("chunked") {
+                return Err(io_error("unsupported transfer-encoding"));
+            }
+            chunked = true;
+        } else {
+            headers.insert(name, value.to_string());
+        }
+    }
+}
+
+fn read_content(i: &mut impl Read, len: usize) -> Result<Vec<u8>, Error> {
+    let mut content = vec![0; len];
+    i.read_exact(content.as_mut_slice())?;
+    Ok(content)
+}
+
+fn too_large() -> Error {
+    Error::new(ErrorKind::InvalidData, ContentTooLarge)
+}
@@ -1,9 +1,15 @@
+mod error;
+mod limits;
 mod message;
 mod request;
 mod response;
 mod to_io_result;
 
-pub use message::Message;
+pub use error::{ContentTooLarge, HeaderTooLarge, HttpError, ReadTooSlow};
+pub use limits::{
+    Limits, DEFAULT_MAX_CONTENT_LENGTH, DEFAULT_MAX_HEADER_SIZE, DEFAULT_MAX_READ_TIME,
+};
+pub use message::{Message, PROTOCOL_1_1};
 pub use request::Request;
 pub use response::Response;
-pub use to_io_result::ToIoResult;
+pub use to_io_result::{io_error, ToIoResult};
@@ -1,8 +1,11 @@
 use std::{collections::HashMap, io::Error};
 
-use super::{message::PROTOCOL, Message, ToIoResult};
+use super::{
+    message::{PROTOCOL, PROTOCOL_1_1},
+    Message, ToIoResult,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Request {
     pub method: String,
     pub url: String,
@@ -26,6 +29,16 @@ impl Request {
             content,
         }
     }
+    /// HTTP/1.1 connections are persistent unless the client asks to close them,
+    /// HTTP/1.0 connections only if the client asks to keep them alive.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.headers.get("connection").map(|v| v.to_lowercase());
+        if self.protocol == PROTOCOL_1_1 {
+            connection.as_deref() != Some("close")
+        } else {
+            connection.as_deref() == Some("keep-alive")
+        }
+    }
 }
 
 impl Message for Request {
@@ -69,7 +82,12 @@ impl Message for Request {
 mod tests {
     use std::{io::Cursor, str::from_utf8};
 
-    use super::{Message, Request};
+    use super::{
+        super::{
+            ContentTooLarge, HeaderTooLarge, DEFAULT_MAX_CONTENT_LENGTH, DEFAULT_MAX_HEADER_SIZE,
+        },
+        Message, Request,
+    };
 
     #[test]
     fn test() {
@@ -182,4 +200,152 @@ mod tests {
         let rm = Request::read(&mut read);
         assert!(rm.is_err());
     }
+
+    #[test]
+    fn chunked_test() {
+        const REQUEST: &str = "\
+            POST / HTTP/1.1\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            4\r\n\
+            Hell\r\n\
+            2;name=value\r\n\
+            o!\r\n\
+            0\r\n\
+            Trailer: x\r\n\
+            \r\n";
+        let mut read = Cursor::new(REQUEST);
+        let rm = Request::read(&mut read).unwrap();
+        assert_eq!(from_utf8(&rm.content), Ok("Hello!"));
+        assert_eq!(rm.headers.len(), 1);
+        assert_eq!(rm.headers["trailer"], "x");
+        assert_eq!(read.position(), REQUEST.len() as u64);
+    }
+
+    #[test]
+    fn invalid_chunk_test() {
+        const REQUEST: &str = "\
+            POST / HTTP/1.1\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            4\r\n\
+            Hello!\r\n\
+            0\r\n\
+            \r\n";
+        assert!(Request::read(&mut Cursor::new(REQUEST)).is_err());
+        const SIZE: &str = "\
+            POST / HTTP/1.1\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            x\r\n";
+        assert!(Request::read(&mut Cursor::new(SIZE)).is_err());
+    }
+
+    #[test]
+    fn chunked_and_length_test() {
+        // the chunks are the content, whatever the order of the headers
+        const REQUEST: &str = "\
+            POST / HTTP/1.1\r\n\
+            Transfer-Encoding: chunked\r\n\
+            Content-Length: 3\r\n\
+            \r\n\
+            6\r\n\
+            Hello!\r\n\
+            0\r\n\
+            \r\n";
+        let mut read = Cursor::new(REQUEST);
+        let rm = Request::read(&mut read).unwrap();
+        assert_eq!(from_utf8(&rm.content), Ok("Hello!"));
+        assert_eq!(read.position(), REQUEST.len() as u64);
+        const LENGTH_FIRST: &str = "\
+            POST / HTTP/1.1\r\n\
+            Content-Length: 3\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            6\r\n\
+            Hello!\r\n\
+            0\r\n\
+            \r\n";
+        let rm = Request::read(&mut Cursor::new(LENGTH_FIRST)).unwrap();
+        assert_eq!(from_utf8(&rm.content), Ok("Hello!"));
+    }
+
+    #[test]
+    fn ambiguous_length_test() {
+        const LENGTHS: &str = "\
+            POST / HTTP/1.1\r\n\
+            Content-Length: 6\r\n\
+            Content-Length: 3\r\n\
+            \r\n\
+            Hello!";
+        assert!(Request::read(&mut Cursor::new(LENGTHS)).is_err());
+        const ENCODING: &str = "\
+            POST / HTTP/1.1\r\n\
+            Transfer-Encoding: gzip, chunked\r\n\
+            \r\n\
+            0\r\n\
+            \r\n";
+        assert!(Request::read(&mut Cursor::new(ENCODING)).is_err());
+    }
+
+    #[test]
+    fn default_limits_test() {
+        let request = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            DEFAULT_MAX_CONTENT_LENGTH + 1
+        );
+        let e = Request::read(&mut Cursor::new(request)).unwrap_err();
+        assert!(e.get_ref().unwrap().is::<ContentTooLarge>());
+        let request = format!(
+            "GET /{} HTTP/1.1\r\n\r\n",
+            "a".repeat(DEFAULT_MAX_HEADER_SIZE)
+        );
+        let e = Request::read(&mut Cursor::new(request)).unwrap_err();
+        assert!(e.get_ref().unwrap().is::<HeaderTooLarge>());
+    }
+
+    #[test]
+    fn read_next_test() {
+        const REQUESTS: &str = "\
+            GET /?id=1 HTTP/1.1\r\n\
+            \r\n\
+            POST / HTTP/1.1\r\n\
+            Content-Length: 6\r\n\
+            \r\n\
+            Hello!";
+        let mut read = Cursor::new(REQUESTS);
+        assert_eq!(
+            Request::read_next(&mut read).unwrap().unwrap().method,
+            "GET"
+        );
+        assert_eq!(
+            Request::read_next(&mut read).unwrap().unwrap().method,
+            "POST"
+        );
+        assert!(Request::read_next(&mut read).unwrap().is_none());
+        assert!(Request::read(&mut read).is_err());
+    }
+
+    #[test]
+    fn keep_alive_test() {
+        let request = |protocol: &str, connection: Option<&str>| {
+            let mut request = Request::new(
+                "GET".to_string(),
+                "/".to_string(),
+                Default::default(),
+                Default::default(),
+            );
+            request.protocol = protocol.to_string();
+            if let Some(connection) = connection {
+                request
+                    .headers
+                    .insert("connection".to_string(), connection.to_string());
+            }
+            request
+        };
+        assert!(request("HTTP/1.1", None).keep_alive());
+        assert!(!request("HTTP/1.1", Some("Close")).keep_alive());
+        assert!(!request("HTTP/1.0", None).keep_alive());
+        assert!(request("HTTP/1.0", Some("Keep-Alive")).keep_alive());
+    }
 }
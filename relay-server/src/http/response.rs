@@ -1,7 +1,7 @@
-use std::{collections::HashMap, io::Read};
+use std::collections::HashMap;
 
 use super::{
-    message::{Message, PROTOCOL},
+    message::{Message, PROTOCOL, PROTOCOL_1_1},
     ToIoResult,
 };
 
@@ -29,6 +29,28 @@ impl Response {
             content,
         }
     }
+    /// A response with the standard reason phrase for the code.
+    pub fn with_code(code: u16, content: Vec<u8>) -> Self {
+        Self::new(code, phrase(code).to_string(), Default::default(), content)
+    }
+}
+
+fn phrase(code: u16) -> &'static str {
+    match code {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        507 => "Insufficient Storage",
+        _ => "",
+    }
 }
 
 impl Message for Response {
@@ -44,7 +66,7 @@ impl Message for Response {
             .to_io_result("expecting status code")?
             .parse()
             .to_io_result("invalid status code")?;
-        let phrase = i.next().unwrap_or(String::default());
+        let phrase = i.next().unwrap_or_default();
         Ok(Response {
             protocol,
             code,
@@ -70,6 +92,9 @@ impl Message for Response {
     fn content(&self) -> &Vec<u8> {
         &self.content
     }
-}
 
-pub trait ResponseEx: Read {}
+    // a response on a persistent connection ends after its content, `204` has no content.
+    fn needs_content_length(&self) -> bool {
+        self.protocol == PROTOCOL_1_1 && self.code != 204
+    }
+}
@@ -1,6 +1,7 @@
 use std::{
-    io::{Read, Write},
+    io::{Error, Read, Write},
     net::TcpStream,
+    time::Duration,
 };
 
 use crate::http::{Message, Request, Response};
@@ -13,14 +14,21 @@ pub trait IoStream: Sized {
     type Write: Write;
     fn istream(&mut self) -> &mut Self::Read;
     fn ostream(&mut self) -> &mut Self::Write;
-    fn call(mut self, request: Request) -> Response {
+    /// Limits the time a single read or write can block. Streams which never block ignore it.
+    fn set_timeout(&mut self, _timeout: Duration) -> Result<(), Error> {
+        Ok(())
+    }
+    fn call(self, request: Request) -> Response {
+        self.try_call(request).unwrap()
+    }
+    fn try_call(mut self, request: Request) -> Result<Response, Error> {
         let o = self.ostream();
         // send data to a callee.
-        request.write(o).unwrap();
+        request.write(o)?;
         // make sure we deliver all data to to the callee.
-        o.flush().unwrap();
+        o.flush()?;
         // read data from the callee.
-        Response::read(self.istream()).unwrap()
+        Response::read(self.istream())
     }
 }
 
@@ -33,4 +41,8 @@ impl IoStream for TcpStream {
     fn ostream(&mut self) -> &mut Self::Write {
         self
     }
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.set_read_timeout(Some(timeout))?;
+        self.set_write_timeout(Some(timeout))
+    }
 }
@@ -1,14 +1,41 @@
+mod auth;
+mod batch;
+mod config;
+mod cursor;
+mod file_state;
 mod http;
 mod io_stream;
 mod mem_io_stream;
 mod mem_state;
 mod remote_state;
+mod replica;
 mod server;
+mod shared_server;
 mod state;
+mod storage;
+mod thread_pool;
+mod tls;
 mod url;
 
-pub use http::{Request, Response};
+pub use auth::{Auth, Credentials, MAX_CLOCK_SKEW, NODE_HEADER, SIGNATURE_HEADER, TIME_HEADER};
+pub use batch::{decode_batch, encode_batch};
+pub use config::{
+    Config, DEFAULT_ADDRESS, DEFAULT_MAX_NAMESPACES, DEFAULT_MAX_NODES, DEFAULT_THREADS,
+    DEFAULT_TIMEOUT,
+};
+pub use cursor::{decode_indexed, encode_indexed, Cursor, Indexed};
+pub use file_state::{FileState, FileStorage};
+pub use http::{
+    Limits, Request, Response, DEFAULT_MAX_CONTENT_LENGTH, DEFAULT_MAX_HEADER_SIZE,
+    DEFAULT_MAX_READ_TIME,
+};
 pub use io_stream::IoStream;
+pub use mem_state::{MemState, MemStorage, Retention};
 pub use remote_state::RemoteState;
+pub use replica::{Replicator, MAX_QUEUED, ORIGIN_HEADER, REPLICA_HEADER, RETRY_DELAY};
 pub use server::Server;
+pub use shared_server::{SharedServer, MAX_WAIT};
 pub use state::State;
+pub use storage::Storage;
+pub use thread_pool::ThreadPool;
+pub use tls::{client_config, server_config, TlsStream};
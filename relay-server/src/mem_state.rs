@@ -1,54 +1,743 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::Error,
+    ops::Range,
+    time::{Duration, SystemTime},
+};
 
-use crate::state::State;
+use crate::{
+    cursor::{Cursor, Indexed},
+    state::State,
+    storage::Storage,
+};
+
+/// Limits for the messages a `MemState` keeps. The oldest broadcast messages are dropped
+/// once any limit is exceeded, nodes which haven't read them yet skip them. The messages to
+/// every node are limited the same way.
+///
+/// `max_nodes` limits the nodes a namespace keeps a read position or messages for. Once more
+/// nodes use it, the node which was used least recently is forgotten with its messages.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct Retention {
+    pub max_messages: Option<usize>,
+    pub max_age: Option<Duration>,
+    pub max_bytes: Option<usize>,
+    pub max_nodes: Option<usize>,
+}
+
+impl Retention {
+    // whether the oldest of `len` messages of `bytes` in total is dropped.
+    fn exceeded(&self, now: SystemTime, oldest: SystemTime, len: usize, bytes: usize) -> bool {
+        let expired = matches!(
+            (self.max_age, now.duration_since(oldest)),
+            (Some(max_age), Ok(age)) if age > max_age
+        );
+        let too_many = matches!(self.max_messages, Some(max) if len > max);
+        let too_large = matches!(self.max_bytes, Some(max) if bytes > max);
+        expired || too_many || too_large
+    }
+}
+
+/// A message posted to a single node.
+pub(crate) struct Direct {
+    /// The index of the first broadcast message posted after it, the node reads the
+    /// message before that broadcast message.
+    pub(crate) before: usize,
+    pub(crate) time: SystemTime,
+    pub(crate) msg: Vec<u8>,
+    /// The origin of a message which another relay forwarded.
+    pub(crate) origin: Option<String>,
+}
 
 #[derive(Default)]
 pub struct MemState {
-    /// The value for this map is an index for the last read message for this node.
-    highwaters: HashMap<String, usize>,
-    queue: Vec<Vec<u8>>,
+    /// The value for this map is an index of the first unread message for this node.
+    /// Indices count all messages ever posted, including dropped ones.
+    pub(crate) highwaters: HashMap<String, usize>,
+    /// The index of the first message in `queue`.
+    pub(crate) offset: usize,
+    pub(crate) queue: VecDeque<(SystemTime, Vec<u8>)>,
+    /// The origins of the kept broadcast messages which another relay forwarded, by index.
+    pub(crate) origins: HashMap<usize, String>,
+    bytes: usize,
+    /// Messages posted to a single node. They are removed once the node reads them.
+    pub(crate) direct: HashMap<String, VecDeque<Direct>>,
+    /// The index of the first kept message posted to a node. Indices count all messages
+    /// posted to the node, including read and dropped ones.
+    pub(crate) direct_offsets: HashMap<String, usize>,
+    /// The last use of every known node, by the number of uses so far.
+    used: HashMap<String, u64>,
+    uses: u64,
+    /// The nodes which were forgotten since the last `take_forgotten`.
+    forgotten: Vec<String>,
+    retention: Retention,
+}
+
+impl MemState {
+    pub fn with_retention(retention: Retention) -> Self {
+        MemState {
+            retention,
+            ..Default::default()
+        }
+    }
+    fn end(&self) -> usize {
+        self.offset + self.queue.len()
+    }
+    fn first_unread(&self, node_id: &str) -> usize {
+        self.highwaters
+            .get(node_id)
+            .map_or(self.offset, |first_unread| *first_unread.max(&self.offset))
+    }
+    fn prune(&mut self, now: SystemTime) {
+        while let Some((time, msg)) = self.queue.front() {
+            if !self
+                .retention
+                .exceeded(now, *time, self.queue.len(), self.bytes)
+            {
+                break;
+            }
+            self.bytes -= msg.len();
+            self.queue.pop_front();
+            self.origins.remove(&self.offset);
+            self.offset += 1;
+        }
+    }
+    fn prune_direct(&mut self, node_id: &str, now: SystemTime) {
+        let Some(direct) = self.direct.get_mut(node_id) else {
+            return;
+        };
+        let mut bytes: usize = direct.iter().map(|direct| direct.msg.len()).sum();
+        let mut dropped = 0;
+        while let Some(oldest) = direct.front() {
+            if !self
+                .retention
+                .exceeded(now, oldest.time, direct.len(), bytes)
+            {
+                break;
+            }
+            bytes -= oldest.msg.len();
+            direct.pop_front();
+            dropped += 1;
+        }
+        if dropped > 0 {
+            *self.direct_offsets.entry(node_id.to_string()).or_default() += dropped;
+        }
+    }
+    fn touch(&mut self, node_id: &str) {
+        self.uses += 1;
+        self.used.insert(node_id.to_string(), self.uses);
+    }
+    // marks the node as used now, and forgets the least recently used nodes over the limit.
+    fn use_node(&mut self, node_id: &str) {
+        self.touch(node_id);
+        let max_nodes = match self.retention.max_nodes {
+            Some(max_nodes) => max_nodes.max(1),
+            None => return,
+        };
+        while self.used.len() > max_nodes {
+            let oldest = self
+                .used
+                .iter()
+                .min_by_key(|(_, used)| **used)
+                .map(|(node_id, _)| node_id.clone());
+            match oldest {
+                Some(oldest) => self.forget(oldest),
+                None => break,
+            }
+        }
+    }
+    /// Drops the read position and the messages of a node.
+    pub(crate) fn forget(&mut self, node_id: String) {
+        self.highwaters.remove(&node_id);
+        self.direct.remove(&node_id);
+        self.direct_offsets.remove(&node_id);
+        self.used.remove(&node_id);
+        self.forgotten.push(node_id);
+    }
+    /// The nodes which were forgotten since the last call.
+    pub(crate) fn take_forgotten(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.forgotten)
+    }
+    pub(crate) fn post_at(&mut self, time: SystemTime, msg: Vec<u8>, origin: Option<String>) {
+        if let Some(origin) = origin {
+            self.origins.insert(self.end(), origin);
+        }
+        self.bytes += msg.len();
+        self.queue.push_back((time, msg));
+        self.prune(SystemTime::now());
+    }
+    /// Posts a message to a node at `time`, which was posted before the broadcast message
+    /// `before`. Used to restore a state.
+    pub(crate) fn post_to_at(
+        &mut self,
+        node_id: String,
+        before: usize,
+        time: SystemTime,
+        msg: Vec<u8>,
+        origin: Option<String>,
+    ) {
+        self.touch(&node_id);
+        self.direct
+            .entry(node_id.clone())
+            .or_default()
+            .push_back(Direct {
+                before,
+                time,
+                msg,
+                origin,
+            });
+        self.prune_direct(&node_id, SystemTime::now());
+    }
+    pub(crate) fn get_direct(&mut self, node_id: &str) -> Option<Vec<u8>> {
+        let direct = self.direct.get_mut(node_id).and_then(VecDeque::pop_front)?;
+        *self.direct_offsets.entry(node_id.to_string()).or_default() += 1;
+        Some(direct.msg)
+    }
+    // the broadcast messages from `first` and the messages to the node, in the order they
+    // were posted.
+    fn merged(&self, node_id: &str, first: usize) -> Vec<Indexed> {
+        let end = self.end();
+        let broadcast = |index: usize| Indexed {
+            index,
+            direct: false,
+            msg: self.queue[index - self.offset].1.clone(),
+            origin: self.origins.get(&index).cloned(),
+        };
+        let direct_offset = self.direct_offset(node_id);
+        let mut next = first;
+        let mut result = Vec::default();
+        for (i, direct) in self.direct.get(node_id).into_iter().flatten().enumerate() {
+            while next < direct.before.min(end) {
+                result.push(broadcast(next));
+                next += 1;
+            }
+            result.push(Indexed {
+                index: direct_offset + i,
+                direct: true,
+                msg: direct.msg.clone(),
+                origin: direct.origin.clone(),
+            });
+        }
+        result.extend((next..end).map(broadcast));
+        result
+    }
+    // prunes the messages and marks the node as used before it reads or starts.
+    fn prepare(&mut self, node_id: &str) {
+        let now = SystemTime::now();
+        self.use_node(node_id);
+        self.prune(now);
+        self.prune_direct(node_id, now);
+    }
+    /// The index of the first kept message to the node.
+    pub(crate) fn direct_offset(&self, node_id: &str) -> usize {
+        self.direct_offsets
+            .get(node_id)
+            .copied()
+            .unwrap_or_default()
+    }
+    /// Sets the read position. Used to restore a state.
+    pub(crate) fn set_position(&mut self, node_id: String, first_unread: usize) {
+        self.touch(&node_id);
+        self.highwaters.insert(node_id, first_unread);
+    }
+    /// Drops the messages to a node before the index `offset`, the next message to the node
+    /// gets this index if there is none. Used to restore a state.
+    pub(crate) fn set_direct_offset(&mut self, node_id: String, offset: usize) {
+        self.touch(&node_id);
+        while self.direct_offset(&node_id) < offset && self.get_direct(&node_id).is_some() {}
+        let direct_offset = self.direct_offsets.entry(node_id).or_default();
+        *direct_offset = offset.max(*direct_offset);
+    }
+    /// Sets the index of the first message. Used to restore a state before any message is posted.
+    pub(crate) fn set_offset(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+}
+
+/// Keeps every namespace in a `MemState` with the same retention.
+#[derive(Default)]
+pub struct MemStorage(pub Retention);
+
+impl Storage for MemStorage {
+    type State = MemState;
+    fn open(&mut self, _namespace: &str) -> Result<MemState, Error> {
+        Ok(MemState::with_retention(self.0))
+    }
+    // the server keeps the states
+    fn exists(&self, _namespace: &str) -> bool {
+        false
+    }
+    fn remove(&mut self, _namespace: &str) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 impl State for MemState {
-    fn get(&mut self, node_id: String) -> Vec<u8> {
-        let first_unread = self
-            .highwaters
-            .get(&node_id)
-            .map_or(0, |last_read| *last_read + 1);
-        let result = self.queue.get(first_unread);
-        if let Some(r) = result {
-            self.highwaters.insert(node_id, first_unread);
-            r.clone()
+    fn get(&mut self, node_id: String) -> Result<Vec<u8>, Error> {
+        self.prepare(&node_id);
+        let first_unread = self.first_unread(&node_id);
+        let direct_first = matches!(
+            self.direct.get(&node_id).and_then(VecDeque::front),
+            Some(direct) if direct.before <= first_unread
+        );
+        Ok(if first_unread < self.end() && !direct_first {
+            self.highwaters.insert(node_id, first_unread + 1);
+            self.queue[first_unread - self.offset].1.clone()
         } else {
-            Vec::default()
+            self.get_direct(&node_id).unwrap_or_default()
+        })
+    }
+    fn get_all(&mut self, node_id: String) -> Result<Vec<Vec<u8>>, Error> {
+        self.prepare(&node_id);
+        let first_unread = self.first_unread(&node_id);
+        let result = self.merged(&node_id, first_unread);
+        if first_unread < self.end() {
+            self.highwaters.insert(node_id.clone(), self.end());
+        }
+        if let Some(direct) = self.direct.remove(&node_id) {
+            *self.direct_offsets.entry(node_id).or_default() += direct.len();
+        }
+        Ok(result.into_iter().map(|indexed| indexed.msg).collect())
+    }
+    fn read(&mut self, node_id: String, cursor: Cursor) -> Result<Vec<Indexed>, Error> {
+        self.prepare(&node_id);
+        if let Some(direct_after) = cursor.direct_after {
+            // the node has processed these messages
+            for _ in self.direct_offset(&node_id)..=direct_after {
+                if self.get_direct(&node_id).is_none() {
+                    break;
+                }
+            }
         }
+        let first = cursor
+            .after
+            .map_or(self.offset, |after| (after + 1).max(self.offset));
+        Ok(self.merged(&node_id, first))
+    }
+    fn post(&mut self, msg: Vec<u8>) -> Result<(), Error> {
+        self.post_at(SystemTime::now(), msg, None);
+        Ok(())
+    }
+    fn post_to(&mut self, node_id: String, msg: Vec<u8>) -> Result<(), Error> {
+        self.use_node(&node_id);
+        self.post_to_at(node_id, self.end(), SystemTime::now(), msg, None);
+        Ok(())
+    }
+    fn post_replica(&mut self, msg: Vec<u8>, origin: String) -> Result<(), Error> {
+        self.post_at(SystemTime::now(), msg, Some(origin));
+        Ok(())
+    }
+    fn post_to_replica(
+        &mut self,
+        node_id: String,
+        msg: Vec<u8>,
+        origin: String,
+    ) -> Result<(), Error> {
+        self.use_node(&node_id);
+        self.post_to_at(node_id, self.end(), SystemTime::now(), msg, Some(origin));
+        Ok(())
+    }
+    fn start(&mut self, node_id: String) -> Result<(), Error> {
+        self.prepare(&node_id);
+        let end = self.end();
+        self.highwaters.entry(node_id).or_insert(end);
+        Ok(())
     }
-    fn post(&mut self, msg: Vec<u8>) {
-        self.queue.push(msg);
+    fn reset(&mut self, node_id: String) -> Result<(), Error> {
+        self.prepare(&node_id);
+        self.highwaters.insert(node_id, self.offset);
+        Ok(())
+    }
+    fn queue(&self) -> Range<usize> {
+        self.offset..self.end()
+    }
+    fn highwaters(&self) -> BTreeMap<String, usize> {
+        self.highwaters
+            .iter()
+            .map(|(node_id, first_unread)| (node_id.clone(), *first_unread.max(&self.offset)))
+            .collect()
+    }
+    fn direct_len(&self, node_id: &str) -> usize {
+        self.direct.get(node_id).map_or(0, VecDeque::len)
+    }
+    fn direct_end(&self, node_id: &str) -> usize {
+        self.direct_offset(node_id) + self.direct_len(node_id)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{MemState, State};
+    use std::{thread::sleep, time::Duration};
+
+    use super::{Cursor, Indexed, MemState, Retention, State};
     #[test]
     fn state_test() {
         let mut state = MemState::default();
-        assert!(state.get(1.to_string()).is_empty());
-        assert!(state.get(3.to_string()).is_empty());
+        assert!(state.get(1.to_string()).unwrap().is_empty());
+        assert!(state.get(3.to_string()).unwrap().is_empty());
         assert_eq!(0, state.highwaters.len());
-        state.post("Msg # 0".as_bytes().to_vec());
-        assert_eq!("Msg # 0".as_bytes().to_vec(), state.get(1.to_string()));
-        assert_eq!("Msg # 0".as_bytes().to_vec(), state.get(5.to_string()));
-        assert_eq!("Msg # 0".as_bytes().to_vec(), state.get(4.to_string()));
-        assert!(state.get(1.to_string()).is_empty());
-        state.post("Msg # 1".as_bytes().to_vec());
-        assert_eq!("Msg # 1".as_bytes().to_vec(), state.get(1.to_string()));
-        assert_eq!("Msg # 0".as_bytes().to_vec(), state.get(3.to_string()));
-        assert_eq!("Msg # 1".as_bytes().to_vec(), state.get(5.to_string()));
-        state.post("Msg # 2".as_bytes().to_vec());
-        assert_eq!("Msg # 2".as_bytes().to_vec(), state.get(1.to_string()));
-        assert_eq!("Msg # 1".as_bytes().to_vec(), state.get(4.to_string()));
-        assert_eq!("Msg # 2".as_bytes().to_vec(), state.get(4.to_string()));
+        state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(5.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(4.to_string()).unwrap()
+        );
+        assert!(state.get(1.to_string()).unwrap().is_empty());
+        state.post("Msg # 1".as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            "Msg # 1".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(3.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 1".as_bytes().to_vec(),
+            state.get(5.to_string()).unwrap()
+        );
+        state.post("Msg # 2".as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            "Msg # 2".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 1".as_bytes().to_vec(),
+            state.get(4.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 2".as_bytes().to_vec(),
+            state.get(4.to_string()).unwrap()
+        );
+    }
+    #[test]
+    fn read_test() {
+        let mut state = MemState::default();
+        state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+        state.post("Msg # 1".as_bytes().to_vec()).unwrap();
+        state
+            .post_to(1.to_string(), "Msg # 2".as_bytes().to_vec())
+            .unwrap();
+        state
+            .post_to(1.to_string(), "Msg # 3".as_bytes().to_vec())
+            .unwrap();
+        let indexed = |index, direct, msg: &str| Indexed {
+            index,
+            direct,
+            msg: msg.as_bytes().to_vec(),
+            origin: None,
+        };
+        let all = vec![
+            indexed(0, false, "Msg # 0"),
+            indexed(1, false, "Msg # 1"),
+            indexed(0, true, "Msg # 2"),
+            indexed(1, true, "Msg # 3"),
+        ];
+        // the same messages until the node moves its cursor
+        assert_eq!(all, state.read(1.to_string(), Cursor::default()).unwrap());
+        assert_eq!(all, state.read(1.to_string(), Cursor::default()).unwrap());
+        let cursor = Cursor {
+            after: Some(0),
+            direct_after: Some(0),
+        };
+        assert_eq!(all[1..2], state.read(1.to_string(), cursor).unwrap()[..1]);
+        assert_eq!(all[3..], state.read(1.to_string(), cursor).unwrap()[1..]);
+        // the processed message to the node is removed
+        assert_eq!(1, state.direct_len("1"));
+        // and the indices continue after a destructive read
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        state.get_all(1.to_string()).unwrap();
+        state
+            .post_to(1.to_string(), "Msg # 4".as_bytes().to_vec())
+            .unwrap();
+        assert_eq!(
+            vec![indexed(2, true, "Msg # 4")],
+            state
+                .read(
+                    1.to_string(),
+                    Cursor {
+                        after: Some(1),
+                        direct_after: Some(1),
+                    }
+                )
+                .unwrap()
+        );
+    }
+    #[test]
+    fn get_all_test() {
+        let mut state = MemState::default();
+        assert!(state.get_all(1.to_string()).unwrap().is_empty());
+        assert_eq!(0, state.highwaters.len());
+        state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+        state.post("Msg # 1".as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(2.to_string()).unwrap()
+        );
+        assert_eq!(
+            vec!["Msg # 0".as_bytes().to_vec(), "Msg # 1".as_bytes().to_vec()],
+            state.get_all(1.to_string()).unwrap()
+        );
+        assert!(state.get_all(1.to_string()).unwrap().is_empty());
+        state.post("Msg # 2".as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            vec!["Msg # 1".as_bytes().to_vec(), "Msg # 2".as_bytes().to_vec()],
+            state.get_all(2.to_string()).unwrap()
+        );
+        assert_eq!(
+            vec!["Msg # 2".as_bytes().to_vec()],
+            state.get_all(1.to_string()).unwrap()
+        );
+        assert!(state.get(2.to_string()).unwrap().is_empty());
+    }
+    #[test]
+    fn post_to_test() {
+        let mut state = MemState::default();
+        state
+            .post_to(1.to_string(), "Msg # 0".as_bytes().to_vec())
+            .unwrap();
+        assert!(state.get(2.to_string()).unwrap().is_empty());
+        state.post("Msg # 1".as_bytes().to_vec()).unwrap();
+        state
+            .post_to(1.to_string(), "Msg # 2".as_bytes().to_vec())
+            .unwrap();
+        // the messages come in the order they were posted
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 1".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 2".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        assert!(state.get(1.to_string()).unwrap().is_empty());
+        assert_eq!(
+            "Msg # 1".as_bytes().to_vec(),
+            state.get(2.to_string()).unwrap()
+        );
+        assert!(state.get(2.to_string()).unwrap().is_empty());
+        state
+            .post_to(2.to_string(), "Msg # 3".as_bytes().to_vec())
+            .unwrap();
+        state.post("Msg # 4".as_bytes().to_vec()).unwrap();
+        state
+            .post_to(2.to_string(), "Msg # 5".as_bytes().to_vec())
+            .unwrap();
+        assert_eq!(
+            vec![
+                "Msg # 3".as_bytes().to_vec(),
+                "Msg # 4".as_bytes().to_vec(),
+                "Msg # 5".as_bytes().to_vec()
+            ],
+            state.get_all(2.to_string()).unwrap()
+        );
+        assert!(state.get_all(2.to_string()).unwrap().is_empty());
+        assert_eq!(
+            vec!["Msg # 4".as_bytes().to_vec()],
+            state.get_all(1.to_string()).unwrap()
+        );
+        // a message to a node comes after the broadcast messages posted before it only
+        state.post("Msg # 6".as_bytes().to_vec()).unwrap();
+        state
+            .post_to(1.to_string(), "Msg # 7".as_bytes().to_vec())
+            .unwrap();
+        state.post("Msg # 8".as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            "Msg # 6".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 7".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 8".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+    }
+    #[test]
+    fn retention_test() {
+        let mut state = MemState::with_retention(Retention {
+            max_messages: Some(2),
+            ..Default::default()
+        });
+        state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+        state.post("Msg # 1".as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        state.post("Msg # 2".as_bytes().to_vec()).unwrap();
+        state.post("Msg # 3".as_bytes().to_vec()).unwrap();
+        assert_eq!(2, state.queue.len());
+        // the node skips the dropped message
+        assert_eq!(
+            vec!["Msg # 2".as_bytes().to_vec(), "Msg # 3".as_bytes().to_vec()],
+            state.get_all(1.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 2".as_bytes().to_vec(),
+            state.get(2.to_string()).unwrap()
+        );
+
+        let mut state = MemState::with_retention(Retention {
+            max_bytes: Some(14),
+            ..Default::default()
+        });
+        state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+        state.post("Msg # 1".as_bytes().to_vec()).unwrap();
+        state.post("Msg # 2".as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            vec!["Msg # 1".as_bytes().to_vec(), "Msg # 2".as_bytes().to_vec()],
+            state.get_all(1.to_string()).unwrap()
+        );
+
+        let mut state = MemState::with_retention(Retention {
+            max_age: Some(Duration::from_millis(50)),
+            ..Default::default()
+        });
+        state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+        sleep(Duration::from_millis(100));
+        state.post("Msg # 1".as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            vec!["Msg # 1".as_bytes().to_vec()],
+            state.get_all(1.to_string()).unwrap()
+        );
+        sleep(Duration::from_millis(100));
+        assert!(state.get_all(2.to_string()).unwrap().is_empty());
+        assert!(state.queue.is_empty());
+    }
+    #[test]
+    fn direct_retention_test() {
+        let mut state = MemState::with_retention(Retention {
+            max_messages: Some(2),
+            ..Default::default()
+        });
+        for i in 0..3 {
+            state
+                .post_to(1.to_string(), format!("Msg # {i}").into_bytes())
+                .unwrap();
+        }
+        assert_eq!(2, state.direct_len("1"));
+        // the indices count the dropped message
+        assert_eq!(
+            vec![
+                Indexed {
+                    index: 1,
+                    direct: true,
+                    msg: "Msg # 1".as_bytes().to_vec(),
+                    origin: None,
+                },
+                Indexed {
+                    index: 2,
+                    direct: true,
+                    msg: "Msg # 2".as_bytes().to_vec(),
+                    origin: None,
+                }
+            ],
+            state.read(1.to_string(), Cursor::default()).unwrap()
+        );
+    }
+    #[test]
+    fn max_nodes_test() {
+        let mut state = MemState::with_retention(Retention {
+            max_nodes: Some(2),
+            ..Default::default()
+        });
+        state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+        state
+            .post_to(1.to_string(), "Msg # 1".as_bytes().to_vec())
+            .unwrap();
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(2.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        // the node used least recently is forgotten
+        state.start(3.to_string()).unwrap();
+        assert_eq!(vec!["2".to_string()], state.take_forgotten());
+        assert_eq!(
+            vec![1.to_string(), 3.to_string()],
+            state.highwaters().into_keys().collect::<Vec<_>>()
+        );
+        state.start(4.to_string()).unwrap();
+        assert_eq!(vec!["1".to_string()], state.take_forgotten());
+        assert_eq!(0, state.direct_len("1"));
+        // a forgotten node starts again with the first kept message
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(2.to_string()).unwrap()
+        );
+    }
+    #[test]
+    fn start_test() {
+        let mut state = MemState::default();
+        state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+        state.post("Msg # 1".as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        // a new node skips all messages posted before
+        state.start(2.to_string()).unwrap();
+        assert!(state.get(2.to_string()).unwrap().is_empty());
+        // a known node keeps its position
+        state.start(1.to_string()).unwrap();
+        assert_eq!(
+            "Msg # 1".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        state.post("Msg # 2".as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            vec!["Msg # 2".as_bytes().to_vec()],
+            state.get_all(2.to_string()).unwrap()
+        );
+    }
+    #[test]
+    fn reset_test() {
+        let mut state = MemState::with_retention(Retention {
+            max_messages: Some(2),
+            ..Default::default()
+        });
+        state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+        state.post("Msg # 1".as_bytes().to_vec()).unwrap();
+        state.post("Msg # 2".as_bytes().to_vec()).unwrap();
+        state
+            .post_to(1.to_string(), "Msg # 3".as_bytes().to_vec())
+            .unwrap();
+        assert_eq!(
+            "Msg # 1".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        state.start(2.to_string()).unwrap();
+        assert_eq!(1..3, state.queue());
+        assert_eq!(
+            vec![(1.to_string(), 2), (2.to_string(), 3)],
+            state.highwaters().into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(1, state.direct_len("1"));
+        // the node reads the kept messages again
+        state.reset(2.to_string()).unwrap();
+        assert_eq!(
+            vec!["Msg # 1".as_bytes().to_vec(), "Msg # 2".as_bytes().to_vec()],
+            state.get_all(2.to_string()).unwrap()
+        );
     }
 }
@@ -1,4 +1,8 @@
+use std::io::Error;
+
 use crate::{
+    batch::decode_batch,
+    cursor::{decode_indexed, Cursor, Indexed},
     http::{Request, Response},
     state::State,
 };
@@ -6,19 +10,73 @@ use crate::{
 pub struct RemoteState<T: FnMut(Request) -> Response>(pub T);
 
 impl<T: FnMut(Request) -> Response> State for RemoteState<T> {
-    fn get(&mut self, node_id: String) -> Vec<u8> {
+    fn get(&mut self, node_id: String) -> Result<Vec<u8>, Error> {
         let request = Request::new(
             "GET".to_string(),
             format!("/?id={node_id}"),
             Default::default(),
             Default::default(),
         );
-        self.0(request).content
+        Ok(self.0(request).content)
     }
 
-    fn post(&mut self, msg: Vec<u8>) {
+    fn get_all(&mut self, node_id: String) -> Result<Vec<Vec<u8>>, Error> {
+        let request = Request::new(
+            "GET".to_string(),
+            format!("/?id={node_id}&batch"),
+            Default::default(),
+            Default::default(),
+        );
+        decode_batch(&self.0(request).content)
+    }
+
+    fn post(&mut self, msg: Vec<u8>) -> Result<(), Error> {
         let request = Request::new("POST".to_string(), "/".to_string(), Default::default(), msg);
         self.0(request);
+        Ok(())
+    }
+
+    fn post_to(&mut self, node_id: String, msg: Vec<u8>) -> Result<(), Error> {
+        let request = Request::new(
+            "POST".to_string(),
+            format!("/?to={node_id}"),
+            Default::default(),
+            msg,
+        );
+        self.0(request);
+        Ok(())
+    }
+
+    fn start(&mut self, node_id: String) -> Result<(), Error> {
+        let request = Request::new(
+            "GET".to_string(),
+            format!("/?id={node_id}&start=now"),
+            Default::default(),
+            Default::default(),
+        );
+        self.0(request);
+        Ok(())
+    }
+
+    fn read(&mut self, node_id: String, cursor: Cursor) -> Result<Vec<Indexed>, Error> {
+        let request = Request::new(
+            "GET".to_string(),
+            format!("/?id={node_id}&{cursor}"),
+            Default::default(),
+            Default::default(),
+        );
+        decode_indexed(&self.0(request).content)
+    }
+
+    fn reset(&mut self, node_id: String) -> Result<(), Error> {
+        let request = Request::new(
+            "POST".to_string(),
+            format!("/admin/reset?id={node_id}"),
+            Default::default(),
+            Default::default(),
+        );
+        self.0(request);
+        Ok(())
     }
 }
 
@@ -30,36 +88,104 @@ mod tests {
 
     #[test]
     fn test() {
-        use std::io::Cursor;
-
         let mut server = Server::default();
 
         let f = |r: Request| {
             let response_buf = {
-                let mut request_stream = Cursor::<Vec<u8>>::default();
+                let mut request_stream = std::io::Cursor::<Vec<u8>>::default();
                 r.write(&mut request_stream).unwrap();
                 server.call(request_stream.get_ref()).unwrap()
             };
-            let mut response_stream = Cursor::new(response_buf);
+            let mut response_stream = std::io::Cursor::new(response_buf);
             Response::read(&mut response_stream).unwrap()
         };
 
         let mut state = RemoteState(f);
-        assert!(state.get(1.to_string()).is_empty());
-        assert!(state.get(3.to_string()).is_empty());
+        assert!(state.get(1.to_string()).unwrap().is_empty());
+        assert!(state.get(3.to_string()).unwrap().is_empty());
         // assert_eq!(0, state.highwaters.len());
-        state.post("Msg # 0".as_bytes().to_vec());
-        assert_eq!("Msg # 0".as_bytes().to_vec(), state.get(1.to_string()));
-        assert_eq!("Msg # 0".as_bytes().to_vec(), state.get(5.to_string()));
-        assert_eq!("Msg # 0".as_bytes().to_vec(), state.get(4.to_string()));
-        assert!(state.get(1.to_string()).is_empty());
-        state.post("Msg # 1".as_bytes().to_vec());
-        assert_eq!("Msg # 1".as_bytes().to_vec(), state.get(1.to_string()));
-        assert_eq!("Msg # 0".as_bytes().to_vec(), state.get(3.to_string()));
-        assert_eq!("Msg # 1".as_bytes().to_vec(), state.get(5.to_string()));
-        state.post("Msg # 2".as_bytes().to_vec());
-        assert_eq!("Msg # 2".as_bytes().to_vec(), state.get(1.to_string()));
-        assert_eq!("Msg # 1".as_bytes().to_vec(), state.get(4.to_string()));
-        assert_eq!("Msg # 2".as_bytes().to_vec(), state.get(4.to_string()));
+        state.post("Msg # 0".as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(5.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(4.to_string()).unwrap()
+        );
+        assert!(state.get(1.to_string()).unwrap().is_empty());
+        state.post("Msg # 1".as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            "Msg # 1".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(3.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 1".as_bytes().to_vec(),
+            state.get(5.to_string()).unwrap()
+        );
+        state.post("Msg # 2".as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            "Msg # 2".as_bytes().to_vec(),
+            state.get(1.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 1".as_bytes().to_vec(),
+            state.get(4.to_string()).unwrap()
+        );
+        assert_eq!(
+            "Msg # 2".as_bytes().to_vec(),
+            state.get(4.to_string()).unwrap()
+        );
+        assert_eq!(
+            vec![
+                "Msg # 0".as_bytes().to_vec(),
+                "Msg # 1".as_bytes().to_vec(),
+                "Msg # 2".as_bytes().to_vec()
+            ],
+            state.get_all(6.to_string()).unwrap()
+        );
+        assert!(state.get_all(6.to_string()).unwrap().is_empty());
+        state
+            .post_to(6.to_string(), "Msg # 3".as_bytes().to_vec())
+            .unwrap();
+        assert!(state.get(1.to_string()).unwrap().is_empty());
+        assert_eq!(
+            "Msg # 3".as_bytes().to_vec(),
+            state.get(6.to_string()).unwrap()
+        );
+        assert!(state.get(6.to_string()).unwrap().is_empty());
+        state.start(7.to_string()).unwrap();
+        assert!(state.get_all(7.to_string()).unwrap().is_empty());
+        assert_eq!(
+            "Msg # 0".as_bytes().to_vec(),
+            state.get(8.to_string()).unwrap()
+        );
+        state
+            .post_to(6.to_string(), "Msg # 4".as_bytes().to_vec())
+            .unwrap();
+        let cursor = Cursor {
+            after: Some(2),
+            direct_after: None,
+        };
+        assert_eq!(
+            vec![Indexed {
+                index: 1,
+                direct: true,
+                msg: "Msg # 4".as_bytes().to_vec(),
+                origin: None,
+            }],
+            state.read(6.to_string(), cursor).unwrap()
+        );
+        state.reset(6.to_string()).unwrap();
+        // the kept messages and the direct message which the cursor read left
+        assert_eq!(4, state.get_all(6.to_string()).unwrap().len());
     }
 }
@@ -0,0 +1,194 @@
+use std::{
+    io::{Error, ErrorKind},
+    net::TcpStream,
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use rustls::ClientConfig;
+
+use crate::{
+    auth::{Credentials, NODE_HEADER, SIGNATURE_HEADER, TIME_HEADER},
+    http::{Request, Response},
+    io_stream::IoStream,
+    tls::TlsStream,
+};
+
+/// Marks a request which a relay forwards to its peers, so the peers don't forward it again.
+pub const REPLICA_HEADER: &str = "x-relay-replica";
+/// The origin of a forwarded message, see `Indexed`.
+pub const ORIGIN_HEADER: &str = "x-relay-origin";
+/// How long a relay waits before it forwards a request to a peer again.
+pub const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// How many requests wait for a peer, more requests are dropped while it is down.
+pub const MAX_QUEUED: usize = 10_000;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Forwards the messages which clients post to this relay, and the namespaces they delete,
+/// to the other relays of a cluster. Every relay of a cluster lists all the others as peers.
+///
+/// Every peer has a thread with a queue of up to `MAX_QUEUED` requests, which are sent in
+/// order. A request is sent again until the peer answers, so a peer which was down receives
+/// the messages posted meanwhile. The queues are lost when the relay stops.
+///
+/// A forwarded request is signed with the `credentials` of the relay instead of the client's
+/// signature, and signed again for every try. A peer with authentication accepts it if the
+/// relay is one of its `relays`.
+pub struct Replicator {
+    peers: Vec<(String, SyncSender<Request>)>,
+}
+
+impl Replicator {
+    /// `peers` are URLs like `http://127.0.0.1:9777`, an `https://` peer is trusted with
+    /// `tls`.
+    pub fn new(
+        peers: &[String],
+        tls: Option<Arc<ClientConfig>>,
+        credentials: Option<Credentials>,
+    ) -> Result<Self, Error> {
+        let peers = peers
+            .iter()
+            .map(|url| {
+                let peer = Peer::parse(url, tls.clone(), credentials.clone())?;
+                let (sender, receiver) = sync_channel(MAX_QUEUED);
+                thread::spawn(move || peer.run(receiver));
+                Ok((url.clone(), sender))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Replicator { peers })
+    }
+    /// Queues a request of a client for every peer, a message with the `origin` which this
+    /// relay assigned to it.
+    pub fn forward(&self, request: &Request, origin: Option<String>) {
+        let mut request = request.clone();
+        for header in [
+            "connection",
+            NODE_HEADER,
+            TIME_HEADER,
+            SIGNATURE_HEADER,
+            ORIGIN_HEADER,
+        ] {
+            request.headers.remove(header);
+        }
+        request
+            .headers
+            .insert(REPLICA_HEADER.to_string(), "1".to_string());
+        if let Some(origin) = origin {
+            request.headers.insert(ORIGIN_HEADER.to_string(), origin);
+        }
+        for (url, peer) in &self.peers {
+            // the thread only stops with the relay
+            if let Err(TrySendError::Full(_)) = peer.try_send(request.clone()) {
+                eprintln!(
+                    "peer {url} is too far behind, dropped {} {}",
+                    request.method, request.url
+                );
+            }
+        }
+    }
+}
+
+struct Peer {
+    url: String,
+    address: String,
+    // the server name for `https://` peers
+    tls: Option<(Arc<ClientConfig>, String)>,
+    credentials: Option<Credentials>,
+}
+
+impl Peer {
+    fn parse(
+        url: &str,
+        tls: Option<Arc<ClientConfig>>,
+        credentials: Option<Credentials>,
+    ) -> Result<Self, Error> {
+        let invalid =
+            |message: &str| Error::new(ErrorKind::InvalidInput, format!("{message} in peer {url}"));
+        let (host, port, tls) = match url.split_once("://") {
+            Some(("http", host)) => (host, 80, None),
+            Some(("https", host)) => {
+                let tls = tls.ok_or_else(|| invalid("no CA for TLS"))?;
+                (host, 443, Some(tls))
+            }
+            _ => return Err(invalid("unsupported scheme")),
+        };
+        let host = host.trim_end_matches('/');
+        if host.contains('/') {
+            return Err(invalid("a path"));
+        }
+        let (name, address) = match host.rsplit_once(':') {
+            Some((name, _)) => (name, host.to_string()),
+            None => (host, format!("{host}:{port}")),
+        };
+        Ok(Peer {
+            url: url.to_string(),
+            address,
+            tls: tls.map(|tls| (tls, name.to_string())),
+            credentials,
+        })
+    }
+    fn run(self, requests: Receiver<Request>) {
+        for request in requests {
+            loop {
+                match self.send(&request) {
+                    // a request the peer refuses would be refused again
+                    Ok(response) if response.code < 500 => {
+                        if response.code != 200 {
+                            eprintln!(
+                                "peer {} refused {} {}: {} {}",
+                                self.url,
+                                request.method,
+                                request.url,
+                                response.code,
+                                String::from_utf8_lossy(&response.content)
+                            );
+                        }
+                        break;
+                    }
+                    Ok(response) => eprintln!("peer {} failed: {}", self.url, response.code),
+                    Err(e) => eprintln!("peer {} failed: {e}", self.url),
+                }
+                thread::sleep(RETRY_DELAY);
+            }
+        }
+    }
+    fn send(&self, request: &Request) -> Result<Response, Error> {
+        let mut stream = TcpStream::connect(&self.address)?;
+        stream.set_timeout(TIMEOUT)?;
+        let mut request = request.clone();
+        // a signature is only accepted once and for a while
+        if let Some(credentials) = &self.credentials {
+            credentials.sign(&mut request);
+        }
+        match &self.tls {
+            Some((config, name)) => {
+                TlsStream::connect(config.clone(), name, stream)?.try_call(request)
+            }
+            None => stream.try_call(request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Peer;
+
+    #[test]
+    fn parse_test() {
+        let peer = Peer::parse("http://127.0.0.1:9777/", None, None).unwrap();
+        assert_eq!(peer.address, "127.0.0.1:9777");
+        assert!(peer.tls.is_none());
+        assert_eq!(
+            Peer::parse("http://relay", None, None).unwrap().address,
+            "relay:80"
+        );
+        assert!(Peer::parse("https://relay", None, None).is_err());
+        assert!(Peer::parse("http://relay/rounds", None, None).is_err());
+        assert!(Peer::parse("relay:9777", None, None).is_err());
+    }
+}
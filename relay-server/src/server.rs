@@ -1,17 +1,36 @@
-use std::io::{Error, ErrorKind, Write};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Error, ErrorKind, Write},
+    sync::Arc,
+};
 
 use crate::{
-    http::{Message, Request, Response, ToIoResult},
+    auth::Auth,
+    batch::encode_batch,
+    cursor::{encode_indexed, Cursor},
+    http::{
+        ContentTooLarge, HeaderTooLarge, HttpError, Limits, Message, ReadTooSlow, Request,
+        Response, PROTOCOL_1_1,
+    },
     io_stream::IoStream,
     mem_io_stream::MemIoStreamEx,
-    mem_state::MemState,
+    mem_state::MemStorage,
+    replica::{Replicator, ORIGIN_HEADER, REPLICA_HEADER},
     state::State,
+    storage::Storage,
     url::QueryEx,
 };
 
 /// The server keeps a state (messages) and can accept and respond to messages using the
 /// `update` function.
 ///
+/// Messages are grouped into namespaces by the URL path, for example `POST /rounds/5` posts
+/// a message which is only returned by `GET /rounds/5?id=alice`. `/` is a namespace too.
+/// `DELETE /rounds/5` removes a namespace with all its messages.
+///
+/// `GET /?id=alice&after=5&direct_after=2` reads the messages after a `Cursor` with their
+/// indices and keeps the read position, see `encode_indexed` for the response.
+///
 /// ## Example
 ///
 /// ```
@@ -30,29 +49,366 @@ use crate::{
 ///   assert_eq!(std::str::from_utf8(&response).unwrap(), RESPONSE);
 /// }
 /// ```
-#[derive(Default)]
-pub struct Server(MemState);
+///
+/// The messages are kept in memory by default, use `Server::new` for another `Storage`.
+///
+/// Every client can use the server by default. With `with_auth`, a request has to be signed
+/// by a known node, a node can only read its own messages, and only admin nodes remove a
+/// namespace.
+///
+/// The paths `/metrics` and `/admin/...` are not namespaces:
+/// - `GET /metrics` returns the metrics of the server in the Prometheus text format.
+/// - `GET /admin/namespaces` lists the namespaces used since the server started.
+/// - `GET /admin/nodes?namespace=rounds/5` lists the nodes of a namespace.
+/// - `POST /admin/reset?namespace=rounds/5&id=alice` moves a node back to the first kept message.
+/// - `POST /admin/purge?namespace=rounds/5` removes a namespace, like `DELETE /rounds/5`.
+///
+/// Metrics never require a signature, admin requests have to be signed by an admin node
+/// of `Auth`.
+pub struct Server<S: Storage = MemStorage> {
+    storage: S,
+    namespaces: HashMap<String, S::State>,
+    limits: Limits,
+    auth: Option<Arc<Auth>>,
+    replicator: Option<Replicator>,
+    id: Option<String>,
+    max_namespaces: Option<usize>,
+    // the number of responses by method and status code
+    requests: BTreeMap<(String, u16), u64>,
+}
 
-impl Server {
-    pub fn update(&mut self, io: &mut impl IoStream) -> Result<(), Error> {
-        let request = Request::read(io.istream())?;
-        let ostream = io.ostream();
+impl Default for Server {
+    fn default() -> Self {
+        Server::new(MemStorage::default())
+    }
+}
 
-        let content = match request.method.as_str() {
+impl<S: Storage> Server<S> {
+    pub fn new(storage: S) -> Self {
+        Server {
+            storage,
+            namespaces: Default::default(),
+            limits: Limits::default(),
+            auth: None,
+            replicator: None,
+            id: None,
+            max_namespaces: None,
+            requests: BTreeMap::default(),
+        }
+    }
+    /// Handles requests from the stream until the client closes a persistent connection,
+    /// or a single request otherwise.
+    pub fn update(&mut self, io: &mut impl IoStream) -> Result<(), Error> {
+        let limits = self.limits;
+        serve(io, &limits, |request| self.respond(request))
+    }
+    // the state of a namespace, `None` if it doesn't exist. A namespace is only created
+    // with `create` and when there are fewer than `max_namespaces`.
+    fn state(&mut self, namespace: &str, create: bool) -> Result<Option<&mut S::State>, HttpError> {
+        if !self.namespaces.contains_key(namespace) {
+            if !self.storage.exists(namespace) {
+                if !create {
+                    return Ok(None);
+                }
+                if matches!(self.max_namespaces, Some(max) if self.namespaces.len() >= max) {
+                    return Err(HttpError::new(507, "too many namespaces"));
+                }
+            }
+            let state = self.storage.open(namespace)?;
+            self.namespaces.insert(namespace.to_string(), state);
+        }
+        Ok(self.namespaces.get_mut(namespace))
+    }
+    // changes the state of a namespace, a namespace which doesn't exist returns the default
+    // unless `create`. A state which fails is dropped, so the next request opens it from the
+    // storage again.
+    fn change<T: Default>(
+        &mut self,
+        namespace: &str,
+        create: bool,
+        f: impl FnOnce(&mut S::State) -> Result<T, Error>,
+    ) -> Result<T, HttpError> {
+        let Some(state) = self.state(namespace, create)? else {
+            return Ok(T::default());
+        };
+        let result = f(state);
+        if result.is_err() {
+            self.namespaces.remove(namespace);
+        }
+        Ok(result?)
+    }
+    pub(crate) fn respond(&mut self, request: &Request) -> Response {
+        let response = authorize(self.auth.as_deref(), request)
+            .and_then(|_| self.try_respond(request))
+            .unwrap_or_else(Response::from);
+        self.count(&request.method, response.code);
+        response
+    }
+    /// Counts a response for the metrics.
+    pub(crate) fn count(&mut self, method: &str, code: u16) {
+        // any other method would add a metric
+        let method = match method {
+            "GET" | "POST" | "DELETE" => method,
+            _ => "other",
+        };
+        *self.requests.entry((method.to_string(), code)).or_default() += 1;
+    }
+    /// Responds to a request which is already authorized.
+    pub(crate) fn respond_authorized(&mut self, request: &Request) -> Response {
+        self.try_respond(request).unwrap_or_else(Response::from)
+    }
+    /// The nodes which may use the server, see `authorize`.
+    pub(crate) fn auth(&self) -> Option<Arc<Auth>> {
+        self.auth.clone()
+    }
+    fn try_respond(&mut self, request: &Request) -> Result<Response, HttpError> {
+        let path = request.url.url_path().trim_matches('/');
+        if path == METRICS || is_admin(path) {
+            return self.respond_reserved(path, request);
+        }
+        let namespace = namespace(&request.url)?;
+        let query = request.url.url_query();
+        match request.method.as_str() {
             "GET" => {
-                let query = *request.url.url_query().get("id").to_io_result("no id")?;
-                self.0.get(query.to_string())
+                let id = node_id(query.get("id"))?;
+                // a cursor read, the read position stays
+                let cursor = if query.contains_key("after") {
+                    let cursor = Cursor::from_query(&query)
+                        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+                    Some(cursor)
+                } else {
+                    None
+                };
+                let origin = self.id.clone();
+                let content = self.change(namespace, false, |state| {
+                    Ok(if let Some(cursor) = cursor {
+                        let mut messages = state.read(id, cursor)?;
+                        // the messages posted to this relay
+                        if let Some(origin) = origin {
+                            for message in messages.iter_mut().filter(|m| m.origin.is_none()) {
+                                message.origin = Some(format!("{origin}/{}", message.index));
+                            }
+                        }
+                        encode_indexed(&messages)
+                    } else if query.get("start") == Some(&"now") {
+                        // only sets the read position, a client reads with the next request.
+                        state.start(id)?;
+                        Vec::default()
+                    } else if query.contains_key("batch") {
+                        encode_batch(&state.get_all(id)?)
+                    } else {
+                        state.get(id)?
+                    })
+                })?;
+                let code = if content.is_empty() { 204 } else { 200 };
+                Ok(Response::with_code(code, content))
             }
             "POST" => {
-                self.0.post(request.content);
-                Vec::default()
+                let to = match query.get("to") {
+                    Some(to) => Some(node_id(Some(to))?),
+                    None => None,
+                };
+                let content = request.content.clone();
+                let replica = request.headers.contains_key(REPLICA_HEADER);
+                let origin = request.headers.get(ORIGIN_HEADER).cloned();
+                // the index of a message posted to this relay
+                let index = self.change(namespace, true, |state| {
+                    Ok(match (to, origin.filter(|_| replica)) {
+                        (Some(to), Some(origin)) => {
+                            state.post_to_replica(to, content, origin)?;
+                            None
+                        }
+                        (None, Some(origin)) => {
+                            state.post_replica(content, origin)?;
+                            None
+                        }
+                        (Some(to), None) => {
+                            let index = state.direct_end(&to);
+                            state.post_to(to, content)?;
+                            Some(index)
+                        }
+                        (None, None) => {
+                            let index = state.queue().end;
+                            state.post(content)?;
+                            Some(index)
+                        }
+                    })
+                })?;
+                let origin = index
+                    .zip(self.id.as_ref())
+                    .map(|(index, id)| format!("{id}/{index}"));
+                self.replicate(request, origin);
+                Ok(Response::with_code(200, Vec::default()))
+            }
+            "DELETE" => {
+                self.namespaces.remove(namespace);
+                self.storage.remove(namespace)?;
+                self.replicate(request, None);
+                Ok(Response::with_code(200, Vec::default()))
+            }
+            method => Err(HttpError::new(405, format!("unsupported method {method}"))),
+        }
+    }
+    fn respond_reserved(&mut self, path: &str, request: &Request) -> Result<Response, HttpError> {
+        let query = request.url.url_query();
+        let content = match (request.method.as_str(), path) {
+            ("GET", METRICS) => {
+                let mut response = Response::with_code(200, self.metrics().into_bytes());
+                response.headers.insert(
+                    "content-type".to_string(),
+                    "text/plain; version=0.0.4".to_string(),
+                );
+                return Ok(response);
+            }
+            ("GET", "admin/namespaces") => {
+                let namespaces: BTreeMap<_, _> = self.namespaces.iter().collect();
+                namespaces
+                    .into_iter()
+                    .map(|(namespace, state)| {
+                        let queue = state.queue();
+                        format!(
+                            "namespace=/{namespace} first={} end={} nodes={}\n",
+                            queue.start,
+                            queue.end,
+                            state.highwaters().len()
+                        )
+                    })
+                    .collect::<String>()
+            }
+            ("GET", "admin/nodes") => match self.state(namespace_param(&query)?, false)? {
+                Some(state) => {
+                    let end = state.queue().end;
+                    state
+                        .highwaters()
+                        .into_iter()
+                        .map(|(node_id, highwater)| {
+                            format!(
+                                "node={node_id} highwater={highwater} unread={} direct={}\n",
+                                end - highwater,
+                                state.direct_len(&node_id)
+                            )
+                        })
+                        .collect::<String>()
+                }
+                None => String::default(),
+            },
+            ("POST", "admin/reset") => {
+                let id = node_id(query.get("id"))?;
+                self.change(namespace_param(&query)?, false, |state| state.reset(id))?;
+                String::default()
+            }
+            ("POST", "admin/purge") => {
+                let namespace = namespace_param(&query)?;
+                self.namespaces.remove(namespace);
+                self.storage.remove(namespace)?;
+                String::default()
+            }
+            (method, _) => {
+                return Err(HttpError::new(
+                    404,
+                    format!("unknown request {method} /{path}"),
+                ))
             }
-            _ => return Err(Error::new(ErrorKind::InvalidData, "unknown HTTP method")),
         };
-        let response = Response::new(200, "OK".to_string(), Default::default(), content);
-        response.write(ostream)?;
-        ostream.flush()?;
-        Ok(())
+        Ok(Response::with_code(200, content.into_bytes()))
+    }
+    // The Prometheus text format.
+    fn metrics(&self) -> String {
+        let mut requests = String::default();
+        for ((method, code), count) in &self.requests {
+            requests +=
+                &format!("relay_requests_total{{method=\"{method}\",code=\"{code}\"}} {count}\n");
+        }
+        let mut messages = String::default();
+        let mut nodes = String::default();
+        let mut unread = String::default();
+        let namespaces: BTreeMap<_, _> = self.namespaces.iter().collect();
+        for (namespace, state) in namespaces {
+            let queue = state.queue();
+            let highwaters = state.highwaters();
+            messages += &format!(
+                "relay_queue_messages{{namespace=\"/{namespace}\"}} {}\n",
+                queue.len()
+            );
+            nodes += &format!(
+                "relay_nodes{{namespace=\"/{namespace}\"}} {}\n",
+                highwaters.len()
+            );
+            for (node_id, highwater) in highwaters {
+                let count = queue.end - highwater + state.direct_len(&node_id);
+                unread += &format!(
+                    "relay_node_unread_messages{{namespace=\"/{namespace}\",node=\"{node_id}\"}} {count}\n"
+                );
+            }
+        }
+        [
+            (
+                "relay_requests_total",
+                "counter",
+                "Responses by method and status code.",
+                requests,
+            ),
+            (
+                "relay_queue_messages",
+                "gauge",
+                "Broadcast messages kept in a namespace.",
+                messages,
+            ),
+            (
+                "relay_nodes",
+                "gauge",
+                "Nodes which read from a namespace.",
+                nodes,
+            ),
+            (
+                "relay_node_unread_messages",
+                "gauge",
+                "Broadcast and direct messages a node hasn't read yet.",
+                unread,
+            ),
+        ]
+        .into_iter()
+        .map(|(name, kind, help, samples)| {
+            format!("# HELP {name} {help}\n# TYPE {name} {kind}\n{samples}")
+        })
+        .collect()
+    }
+    // forwards a request of a client to the other relays of the cluster
+    fn replicate(&self, request: &Request, origin: Option<String>) {
+        if let Some(replicator) = &self.replicator {
+            if !request.headers.contains_key(REPLICA_HEADER) {
+                replicator.forward(request, origin);
+            }
+        }
+    }
+    /// The largest requests the server reads.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+    pub(crate) fn limits(&self) -> Limits {
+        self.limits
+    }
+    /// Only accepts requests signed by the nodes of `auth`.
+    pub fn with_auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(Arc::new(auth));
+        self
+    }
+    /// Refuses messages to a new namespace with `507` once there are `max` namespaces.
+    pub fn with_max_namespaces(mut self, max: usize) -> Self {
+        self.max_namespaces = Some(max);
+        self
+    }
+    /// Forwards the messages posted to this relay to the other relays of a cluster.
+    pub fn with_replicator(mut self, replicator: Replicator) -> Self {
+        self.replicator = Some(replicator);
+        self
+    }
+    /// The id of this relay in a cluster. A cursor read returns the origin of every message,
+    /// the id of the relay which it was posted to and its index there, see `Indexed`.
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
     }
     // TODO: move this function to a `test` mod.
     pub fn call(&mut self, msg: &[u8]) -> Result<Vec<u8>, Error> {
@@ -66,11 +422,140 @@ impl Server {
     }
 }
 
+/// Reads requests and writes their responses while the connection is persistent.
+/// Pipelined requests are answered in order.
+pub(crate) fn serve(
+    io: &mut impl IoStream,
+    limits: &Limits,
+    mut respond: impl FnMut(&Request) -> Response,
+) -> Result<(), Error> {
+    loop {
+        let request = match Request::read_limited(io.istream(), limits) {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                // the rest of the stream can't be read, answer and close the connection.
+                let code = match e.get_ref() {
+                    Some(inner) if inner.is::<ContentTooLarge>() => 413,
+                    Some(inner) if inner.is::<HeaderTooLarge>() => 431,
+                    Some(inner) if inner.is::<ReadTooSlow>() => 408,
+                    _ if e.kind() == ErrorKind::InvalidData => 400,
+                    _ => return Err(e),
+                };
+                let response = Response::from(HttpError::new(code, e.to_string()));
+                let ostream = io.ostream();
+                response.write(ostream)?;
+                ostream.flush()?;
+                return Err(e);
+            }
+        };
+        let mut response = respond(&request);
+        let keep_alive = request.keep_alive();
+        if request.protocol == PROTOCOL_1_1 {
+            response.protocol = PROTOCOL_1_1.to_string();
+            if !keep_alive {
+                response
+                    .headers
+                    .insert("connection".to_string(), "close".to_string());
+            }
+        } else if keep_alive {
+            response
+                .headers
+                .insert("connection".to_string(), "keep-alive".to_string());
+        }
+        let ostream = io.ostream();
+        response.write(ostream)?;
+        ostream.flush()?;
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+/// Checks the signature of the request, that the node reads its own messages, and that only
+/// admins use the admin requests and `DELETE`. Every request is authorized without `auth`.
+///
+/// A request forwarded by another relay of the cluster was authorized by that relay.
+pub(crate) fn authorize(auth: Option<&Auth>, request: &Request) -> Result<(), HttpError> {
+    let path = request.url.url_path().trim_matches('/');
+    let auth = match auth {
+        Some(auth) if path != METRICS => auth,
+        _ => return Ok(()),
+    };
+    let node_id = auth.authenticate(request)?;
+    if request.headers.contains_key(REPLICA_HEADER) {
+        return match auth.is_relay(&node_id) {
+            true => Ok(()),
+            false => Err(HttpError::new(
+                403,
+                format!("node {node_id} is not a relay"),
+            )),
+        };
+    }
+    if (is_admin(path) || request.method == "DELETE") && !auth.is_admin(&node_id) {
+        return Err(HttpError::new(
+            403,
+            format!("node {node_id} is not an admin"),
+        ));
+    }
+    match request.url.url_query().get("id") {
+        Some(&id) if request.method == "GET" && id != node_id => Err(HttpError::new(
+            403,
+            format!("node {node_id} can't read messages of {id}"),
+        )),
+        _ => Ok(()),
+    }
+}
+
+const METRICS: &str = "metrics";
+
+fn is_admin(path: &str) -> bool {
+    path == "admin" || path.starts_with("admin/")
+}
+
+// `/rounds/5/` is the namespace `rounds/5`, `/` is the namespace ``.
+fn namespace(url: &str) -> Result<&str, HttpError> {
+    valid_namespace(url.url_path())
+}
+
+// the `namespace` parameter of an admin request, the namespace `` by default.
+fn namespace_param<'a>(query: &HashMap<&str, &'a str>) -> Result<&'a str, HttpError> {
+    valid_namespace(query.get("namespace").copied().unwrap_or_default())
+}
+
+fn valid_namespace(path: &str) -> Result<&str, HttpError> {
+    let namespace = path.trim_matches('/');
+    let valid = namespace.is_empty()
+        || namespace.split('/').all(|segment| {
+            !segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        });
+    if !valid {
+        return Err(HttpError::bad_request("invalid namespace"));
+    }
+    Ok(namespace)
+}
+
+fn node_id(id: Option<&&str>) -> Result<String, HttpError> {
+    let id = id.ok_or_else(|| HttpError::bad_request("no id"))?;
+    if id.is_empty()
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(HttpError::bad_request(format!("invalid id {id}")));
+    }
+    Ok(id.to_string())
+}
+
 #[cfg(test)]
 mod test {
     use std::str::from_utf8;
 
     use super::*;
+    use crate::{auth::Credentials, cursor::Indexed, mem_state::MemState};
 
     #[test]
     fn test() {
@@ -104,10 +589,52 @@ mod test {
                 GET /?id=x HTTP/1.0\r\n\
                 \r\n";
             let response = server.call(REQUEST.as_bytes()).unwrap();
+            const RESPONSE: &str = "\
+                HTTP/1.0 204 No Content\r\n\
+                \r\n";
+            assert_eq!(from_utf8(&response).unwrap(), RESPONSE);
+        }
+        // batch request
+        {
+            const REQUEST: &str = "\
+                POST / HTTP/1.0\r\n\
+                Content-Length: 6\r\n\
+                \r\n\
+                World!";
+            server.call(REQUEST.as_bytes()).unwrap();
+        }
+        {
+            const REQUEST: &str = "\
+                GET /?id=y&batch HTTP/1.0\r\n\
+                \r\n";
+            let response = server.call(REQUEST.as_bytes()).unwrap();
             const RESPONSE: &str = "\
                 HTTP/1.0 200 OK\r\n\
+                content-length:20\r\n\
+                \r\n\
+                \0\0\0\x06Hello!\0\0\0\x06World!";
+            assert_eq!(from_utf8(&response).unwrap(), RESPONSE);
+        }
+        // directed message
+        {
+            const REQUEST: &str = "\
+                POST /?to=y HTTP/1.0\r\n\
+                Content-Length: 6\r\n\
+                \r\n\
+                To Y!!";
+            server.call(REQUEST.as_bytes()).unwrap();
+            const GET: &str = "\
+                GET /?id=y HTTP/1.0\r\n\
                 \r\n";
+            let response = server.call(GET.as_bytes()).unwrap();
+            const RESPONSE: &str = "\
+                HTTP/1.0 200 OK\r\n\
+                content-length:6\r\n\
+                \r\n\
+                To Y!!";
             assert_eq!(from_utf8(&response).unwrap(), RESPONSE);
+            let response = server.call(GET.as_bytes()).unwrap();
+            assert_eq!(from_utf8(&response).unwrap(), NO_CONTENT);
         }
         // invalid request
         {
@@ -120,4 +647,494 @@ mod test {
             assert!(response.is_err());
         }
     }
+
+    fn call(server: &mut Server, request: &str) -> String {
+        String::from_utf8(server.call(request.as_bytes()).unwrap()).unwrap()
+    }
+
+    const EMPTY: &str = "\
+        HTTP/1.0 200 OK\r\n\
+        \r\n";
+
+    const NO_CONTENT: &str = "\
+        HTTP/1.0 204 No Content\r\n\
+        \r\n";
+
+    #[test]
+    fn namespace_test() {
+        let mut server = Server::default();
+        const POST: &str = "\
+            POST /rounds/5 HTTP/1.0\r\n\
+            Content-Length: 6\r\n\
+            \r\n\
+            Hello!";
+        assert_eq!(call(&mut server, POST), EMPTY);
+        const MESSAGE: &str = "\
+            HTTP/1.0 200 OK\r\n\
+            content-length:6\r\n\
+            \r\n\
+            Hello!";
+        assert_eq!(call(&mut server, "GET /?id=x HTTP/1.0\r\n\r\n"), NO_CONTENT);
+        assert_eq!(
+            call(&mut server, "GET /rounds/6?id=x HTTP/1.0\r\n\r\n"),
+            NO_CONTENT
+        );
+        assert_eq!(
+            call(&mut server, "GET /rounds/5/?id=x HTTP/1.0\r\n\r\n"),
+            MESSAGE
+        );
+        assert_eq!(
+            call(&mut server, "DELETE /rounds/5 HTTP/1.0\r\n\r\n"),
+            EMPTY
+        );
+        assert_eq!(
+            call(&mut server, "GET /rounds/5?id=y HTTP/1.0\r\n\r\n"),
+            NO_CONTENT
+        );
+        // invalid namespaces
+        const INVALID: &str = "\
+            HTTP/1.0 400 Bad Request\r\n\
+            content-length:17\r\n\
+            \r\n\
+            invalid namespace";
+        assert_eq!(
+            call(&mut server, "GET /rounds//5?id=x HTTP/1.0\r\n\r\n"),
+            INVALID
+        );
+        assert_eq!(
+            call(&mut server, "GET /../5?id=x HTTP/1.0\r\n\r\n"),
+            INVALID
+        );
+    }
+
+    #[test]
+    fn start_test() {
+        let mut server = Server::default();
+        const POST: &str = "\
+            POST / HTTP/1.0\r\n\
+            Content-Length: 6\r\n\
+            \r\n\
+            Hello!";
+        assert_eq!(call(&mut server, POST), EMPTY);
+        assert_eq!(
+            call(&mut server, "GET /?id=x&start=now HTTP/1.0\r\n\r\n"),
+            NO_CONTENT
+        );
+        assert_eq!(call(&mut server, "GET /?id=x HTTP/1.0\r\n\r\n"), NO_CONTENT);
+        const POST2: &str = "\
+            POST / HTTP/1.0\r\n\
+            Content-Length: 6\r\n\
+            \r\n\
+            World!";
+        assert_eq!(call(&mut server, POST2), EMPTY);
+        const MESSAGE: &str = "\
+            HTTP/1.0 200 OK\r\n\
+            content-length:6\r\n\
+            \r\n\
+            World!";
+        assert_eq!(call(&mut server, "GET /?id=x HTTP/1.0\r\n\r\n"), MESSAGE);
+    }
+
+    #[test]
+    fn cursor_test() {
+        let mut server = Server::default();
+        const POST: &str = "\
+            POST / HTTP/1.0\r\n\
+            Content-Length: 6\r\n\
+            \r\n\
+            Hello!";
+        assert_eq!(call(&mut server, POST), EMPTY);
+        const MESSAGE: &str = "\
+            HTTP/1.0 200 OK\r\n\
+            content-length:19\r\n\
+            \r\n\
+            \0\0\0\x0f\0\0\0\0\0\0\0\0\0Hello!";
+        // the message is returned until the cursor is after it
+        for _ in 0..2 {
+            assert_eq!(
+                call(&mut server, "GET /?id=x&after= HTTP/1.0\r\n\r\n"),
+                MESSAGE
+            );
+        }
+        assert_eq!(
+            call(&mut server, "GET /?id=x&after=0 HTTP/1.0\r\n\r\n"),
+            NO_CONTENT
+        );
+        assert!(call(&mut server, "GET /?id=x&after=a HTTP/1.0\r\n\r\n")
+            .starts_with("HTTP/1.0 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn keep_alive_test() {
+        let mut server = Server::default();
+        const REQUESTS: &str = "\
+            POST / HTTP/1.1\r\n\
+            Content-Length: 6\r\n\
+            \r\n\
+            Hello!\
+            GET /?id=x HTTP/1.1\r\n\
+            \r\n\
+            GET /?id=x HTTP/1.1\r\n\
+            Connection: close\r\n\
+            \r\n";
+        const RESPONSES: &str = "\
+            HTTP/1.1 200 OK\r\n\
+            content-length:0\r\n\
+            \r\n\
+            HTTP/1.1 200 OK\r\n\
+            content-length:6\r\n\
+            \r\n\
+            Hello!\
+            HTTP/1.1 204 No Content\r\n\
+            connection:close\r\n\
+            \r\n";
+        assert_eq!(call(&mut server, REQUESTS), RESPONSES);
+        // an HTTP/1.0 connection is closed after the first request
+        const HTTP_1_0: &str = "\
+            GET /?id=x HTTP/1.0\r\n\
+            \r\n\
+            GET /?id=x HTTP/1.0\r\n\
+            \r\n";
+        assert!(server.call(HTTP_1_0.as_bytes()).is_err());
+        const KEEP_ALIVE: &str = "\
+            GET /?id=x HTTP/1.0\r\n\
+            Connection: keep-alive\r\n\
+            \r\n\
+            GET /?id=x HTTP/1.0\r\n\
+            \r\n";
+        const KEEP_ALIVE_RESPONSES: &str = "\
+            HTTP/1.0 204 No Content\r\n\
+            connection:keep-alive\r\n\
+            \r\n\
+            HTTP/1.0 204 No Content\r\n\
+            \r\n";
+        assert_eq!(call(&mut server, KEEP_ALIVE), KEEP_ALIVE_RESPONSES);
+    }
+
+    #[test]
+    fn error_test() {
+        let mut server = Server::default().with_limits(Limits {
+            max_content_length: 5,
+            max_header_size: 64,
+            ..Limits::default()
+        });
+        const NO_ID: &str = "\
+            HTTP/1.0 400 Bad Request\r\n\
+            content-length:5\r\n\
+            \r\n\
+            no id";
+        assert_eq!(call(&mut server, "GET / HTTP/1.0\r\n\r\n"), NO_ID);
+        const INVALID_ID: &str = "\
+            HTTP/1.0 400 Bad Request\r\n\
+            content-length:14\r\n\
+            \r\n\
+            invalid id a.b";
+        assert_eq!(
+            call(&mut server, "GET /?id=a.b HTTP/1.0\r\n\r\n"),
+            INVALID_ID
+        );
+        assert!(call(&mut server, "POST /?to= HTTP/1.0\r\n\r\n")
+            .starts_with("HTTP/1.0 400 Bad Request\r\n"));
+        const METHOD: &str = "\
+            HTTP/1.0 405 Method Not Allowed\r\n\
+            allow:GET, POST, DELETE\r\n\
+            content-length:22\r\n\
+            \r\n\
+            unsupported method PUT";
+        assert_eq!(call(&mut server, "PUT / HTTP/1.0\r\n\r\n"), METHOD);
+        // the content is not read, so the connection is closed
+        const POST: &str = "\
+            POST / HTTP/1.0\r\n\
+            Content-Length: 6\r\n\
+            \r\n\
+            Hello!";
+        let mut response = Vec::default();
+        assert!(server
+            .update(&mut POST.as_bytes().mem_io_stream(&mut response))
+            .is_err());
+        assert_eq!(
+            from_utf8(&response).unwrap(),
+            "\
+            HTTP/1.0 413 Payload Too Large\r\n\
+            content-length:17\r\n\
+            \r\n\
+            content too large"
+        );
+        // the headers are not read either
+        let mut response = Vec::default();
+        let header = format!("GET /?id={} HTTP/1.0\r\n\r\n", "x".repeat(64));
+        assert!(server
+            .update(&mut header.as_bytes().mem_io_stream(&mut response))
+            .is_err());
+        assert!(from_utf8(&response)
+            .unwrap()
+            .starts_with("HTTP/1.0 431 Request Header Fields Too Large\r\n"));
+        // a malformed request
+        let mut response = Vec::default();
+        assert!(server
+            .update(
+                &mut "GET / HTTP/1.0\r\nHost\r\n\r\n"
+                    .as_bytes()
+                    .mem_io_stream(&mut response)
+            )
+            .is_err());
+        assert!(from_utf8(&response)
+            .unwrap()
+            .starts_with("HTTP/1.0 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn auth_test() {
+        let alice = Credentials::new("alice", &"01".repeat(32)).unwrap();
+        let bob = Credentials::new("bob", &"02".repeat(32)).unwrap();
+        let keys = HashMap::from([
+            ("alice".to_string(), alice.public_key()),
+            ("bob".to_string(), bob.public_key()),
+        ]);
+        let mut server = Server::default().with_auth(Auth::new(&keys).unwrap());
+        let request = |method: &str, url: &str, content: &str| {
+            Request::new(
+                method.to_string(),
+                url.to_string(),
+                Default::default(),
+                content.as_bytes().to_vec(),
+            )
+        };
+
+        let mut post = request("POST", "/", "Hello!");
+        assert_eq!(server.respond(&post).code, 401);
+        alice.sign(&mut post);
+        assert_eq!(server.respond(&post).code, 200);
+
+        // bob can't take alice's messages
+        let mut get = request("GET", "/?id=alice", "");
+        bob.sign(&mut get);
+        assert_eq!(server.respond(&get).code, 403);
+        let mut get = request("GET", "/?id=alice", "");
+        alice.sign(&mut get);
+        assert_eq!(server.respond(&get).content, b"Hello!");
+        let mut get = request("GET", "/?id=bob", "");
+        bob.sign(&mut get);
+        assert_eq!(server.respond(&get).content, b"Hello!");
+
+        // only admins use the admin requests, everybody reads the metrics
+        let auth = Auth::new(&keys).unwrap().with_admins(["alice".to_string()]);
+        let mut server = Server::default().with_auth(auth);
+        let mut nodes = request("GET", "/admin/nodes", "");
+        bob.sign(&mut nodes);
+        assert_eq!(server.respond(&nodes).code, 403);
+        let mut nodes = request("GET", "/admin/nodes", "");
+        alice.sign(&mut nodes);
+        assert_eq!(server.respond(&nodes).code, 200);
+        let mut delete = request("DELETE", "/rounds/5", "");
+        bob.sign(&mut delete);
+        assert_eq!(server.respond(&delete).code, 403);
+        let mut delete = request("DELETE", "/rounds/5", "");
+        alice.sign(&mut delete);
+        assert_eq!(server.respond(&delete).code, 200);
+        assert_eq!(server.respond(&request("GET", "/metrics", "")).code, 200);
+
+        // only relays forward requests
+        let relay = Credentials::new("relay", &"03".repeat(32)).unwrap();
+        let keys = HashMap::from([
+            ("bob".to_string(), bob.public_key()),
+            ("relay".to_string(), relay.public_key()),
+        ]);
+        let auth = Auth::new(&keys).unwrap().with_relays(["relay".to_string()]);
+        let mut server = Server::default().with_auth(auth);
+        let mut forwarded = request("DELETE", "/rounds/5", "");
+        forwarded
+            .headers
+            .insert(REPLICA_HEADER.to_string(), "1".to_string());
+        let mut by_bob = forwarded.clone();
+        bob.sign(&mut by_bob);
+        assert_eq!(server.respond(&by_bob).code, 403);
+        relay.sign(&mut forwarded);
+        assert_eq!(server.respond(&forwarded).code, 200);
+    }
+
+    // fails to store the message `fail`
+    struct FailingState(MemState);
+
+    impl State for FailingState {
+        fn get(&mut self, node_id: String) -> Result<Vec<u8>, Error> {
+            self.0.get(node_id)
+        }
+        fn get_all(&mut self, node_id: String) -> Result<Vec<Vec<u8>>, Error> {
+            self.0.get_all(node_id)
+        }
+        fn post(&mut self, msg: Vec<u8>) -> Result<(), Error> {
+            self.0.post(msg.clone())?;
+            match msg.as_slice() {
+                b"fail" => Err(Error::other("can't store the message")),
+                _ => Ok(()),
+            }
+        }
+        fn post_to(&mut self, node_id: String, msg: Vec<u8>) -> Result<(), Error> {
+            self.0.post_to(node_id, msg)
+        }
+        fn start(&mut self, node_id: String) -> Result<(), Error> {
+            self.0.start(node_id)
+        }
+        fn read(&mut self, node_id: String, cursor: Cursor) -> Result<Vec<Indexed>, Error> {
+            self.0.read(node_id, cursor)
+        }
+        fn reset(&mut self, node_id: String) -> Result<(), Error> {
+            self.0.reset(node_id)
+        }
+    }
+
+    struct FailingStorage;
+
+    impl Storage for FailingStorage {
+        type State = FailingState;
+        fn open(&mut self, _namespace: &str) -> Result<FailingState, Error> {
+            Ok(FailingState(MemState::default()))
+        }
+        fn exists(&self, _namespace: &str) -> bool {
+            false
+        }
+        fn remove(&mut self, _namespace: &str) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn storage_error_test() {
+        let mut server = Server::new(FailingStorage);
+        let post = Request::new(
+            "POST".to_string(),
+            "/".to_string(),
+            Default::default(),
+            b"fail".to_vec(),
+        );
+        let get = Request::new(
+            "GET".to_string(),
+            "/?id=x".to_string(),
+            Default::default(),
+            Default::default(),
+        );
+        let response = server.respond(&post);
+        assert_eq!(response.code, 500);
+        assert_eq!(response.content, b"can't store the message");
+        // the namespace is opened again without the message which wasn't stored
+        assert_eq!(server.respond(&get).code, 204);
+    }
+
+    #[test]
+    fn max_namespaces_test() {
+        let mut server = Server::default().with_max_namespaces(1);
+        assert_eq!(
+            call(
+                &mut server,
+                "POST /rounds/5 HTTP/1.0\r\nContent-Length: 1\r\n\r\na"
+            ),
+            EMPTY
+        );
+        assert!(call(
+            &mut server,
+            "POST /rounds/6 HTTP/1.0\r\nContent-Length: 1\r\n\r\nb"
+        )
+        .starts_with("HTTP/1.0 507 Insufficient Storage\r\n"));
+        // a read of another namespace is empty
+        assert_eq!(
+            call(&mut server, "GET /rounds/6?id=x HTTP/1.0\r\n\r\n"),
+            NO_CONTENT
+        );
+        assert_eq!(
+            call(&mut server, "DELETE /rounds/5 HTTP/1.0\r\n\r\n"),
+            EMPTY
+        );
+        assert_eq!(
+            call(
+                &mut server,
+                "POST /rounds/6 HTTP/1.0\r\nContent-Length: 1\r\n\r\nb"
+            ),
+            EMPTY
+        );
+    }
+
+    #[test]
+    fn metrics_test() {
+        let mut server = Server::default();
+        call(
+            &mut server,
+            "POST /rounds/5 HTTP/1.0\r\nContent-Length: 1\r\n\r\na",
+        );
+        call(
+            &mut server,
+            "POST /rounds/5 HTTP/1.0\r\nContent-Length: 1\r\n\r\nb",
+        );
+        call(&mut server, "GET /rounds/5?id=x HTTP/1.0\r\n\r\n");
+        call(&mut server, "PUT / HTTP/1.0\r\n\r\n");
+        let response = call(&mut server, "GET /metrics HTTP/1.0\r\n\r\n");
+        let metrics = response.split("\r\n\r\n").nth(1).unwrap();
+        let samples: Vec<_> = metrics.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(
+            samples,
+            [
+                "relay_requests_total{method=\"GET\",code=\"200\"} 1",
+                "relay_requests_total{method=\"POST\",code=\"200\"} 2",
+                "relay_requests_total{method=\"other\",code=\"405\"} 1",
+                "relay_queue_messages{namespace=\"/rounds/5\"} 2",
+                "relay_nodes{namespace=\"/rounds/5\"} 1",
+                "relay_node_unread_messages{namespace=\"/rounds/5\",node=\"x\"} 1",
+            ]
+        );
+        assert!(metrics.contains("# TYPE relay_requests_total counter\n"));
+    }
+
+    #[test]
+    fn admin_test() {
+        let mut server = Server::default();
+        call(
+            &mut server,
+            "POST /rounds/5 HTTP/1.0\r\nContent-Length: 1\r\n\r\na",
+        );
+        call(
+            &mut server,
+            "POST /rounds/5?to=y HTTP/1.0\r\nContent-Length: 1\r\n\r\nb",
+        );
+        call(&mut server, "GET /rounds/5?id=x HTTP/1.0\r\n\r\n");
+        // a read doesn't create a namespace
+        call(&mut server, "GET /?id=x&start=now HTTP/1.0\r\n\r\n");
+        let content = |response: String| response.split("\r\n\r\n").nth(1).unwrap().to_string();
+        assert_eq!(
+            content(call(&mut server, "GET /admin/namespaces HTTP/1.0\r\n\r\n")),
+            "namespace=/rounds/5 first=0 end=1 nodes=1\n"
+        );
+        assert_eq!(
+            content(call(
+                &mut server,
+                "GET /admin/nodes?namespace=rounds/5 HTTP/1.0\r\n\r\n"
+            )),
+            "node=x highwater=1 unread=0 direct=0\n"
+        );
+        assert_eq!(
+            call(
+                &mut server,
+                "POST /admin/reset?namespace=rounds/5&id=x HTTP/1.0\r\n\r\n"
+            ),
+            EMPTY
+        );
+        assert_eq!(
+            call(&mut server, "GET /rounds/5?id=x HTTP/1.0\r\n\r\n"),
+            "HTTP/1.0 200 OK\r\ncontent-length:1\r\n\r\na"
+        );
+        assert_eq!(
+            call(
+                &mut server,
+                "POST /admin/purge?namespace=rounds/5 HTTP/1.0\r\n\r\n"
+            ),
+            EMPTY
+        );
+        assert_eq!(
+            call(&mut server, "GET /rounds/5?id=y HTTP/1.0\r\n\r\n"),
+            NO_CONTENT
+        );
+        assert!(call(&mut server, "GET /admin/queues HTTP/1.0\r\n\r\n")
+            .starts_with("HTTP/1.0 404 Not Found\r\n"));
+        assert!(call(&mut server, "POST /metrics HTTP/1.0\r\n\r\n")
+            .starts_with("HTTP/1.0 404 Not Found\r\n"));
+    }
 }
@@ -0,0 +1,229 @@
+use std::{
+    io::Error,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    http::{HttpError, Request, Response, ToIoResult},
+    io_stream::IoStream,
+    mem_state::MemStorage,
+    server::{authorize, serve, Server},
+    storage::Storage,
+    thread_pool::ThreadPool,
+    url::QueryEx,
+};
+
+/// The longest time a `GET` request can be held by the server.
+pub const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// A `Server` which can be shared between threads, one thread per connection.
+///
+/// In addition to the `Server` requests, it supports long polling. A `GET` request with
+/// a `wait=<milliseconds>` parameter, for example `GET /?id=alice&wait=10000`, is held
+/// until there is a message for the node or until the time is up.
+pub struct SharedServer<S: Storage = MemStorage>(Arc<(Mutex<Server<S>>, Condvar)>);
+
+impl Default for SharedServer {
+    fn default() -> Self {
+        SharedServer::new(Server::default())
+    }
+}
+
+// a derived `Clone` would require `S: Clone`
+impl<S: Storage> Clone for SharedServer<S> {
+    fn clone(&self) -> Self {
+        SharedServer(self.0.clone())
+    }
+}
+
+impl<S: Storage> SharedServer<S> {
+    pub fn new(server: Server<S>) -> Self {
+        SharedServer(Arc::new((Mutex::new(server), Condvar::new())))
+    }
+    pub fn update(&self, io: &mut impl IoStream) -> Result<(), Error> {
+        let (limits, auth) = {
+            let server = self.0 .0.lock().to_io_result("poisoned server")?;
+            (server.limits(), server.auth())
+        };
+        serve(io, &limits, |request| {
+            // a signature is checked without blocking other requests
+            let response = authorize(auth.as_deref(), request)
+                .and_then(|_| self.respond(request))
+                .unwrap_or_else(Response::from);
+            if let Ok(mut server) = self.0 .0.lock() {
+                server.count(&request.method, response.code);
+            }
+            response
+        })
+    }
+    // responds to an authorized request.
+    fn respond(&self, request: &Request) -> Result<Response, HttpError> {
+        let deadline = Instant::now() + wait(request)?;
+
+        let (server, posted) = &*self.0;
+        let mut server = server.lock().to_io_result("poisoned server")?;
+        let response = loop {
+            let response = server.respond_authorized(request);
+            let now = Instant::now();
+            // only an empty queue is waited for
+            if request.method != "GET" || response.code != 204 || now >= deadline {
+                break response;
+            }
+            server = posted
+                .wait_timeout(server, deadline - now)
+                .to_io_result("poisoned server")?
+                .0;
+        };
+        drop(server);
+        if request.method == "POST" {
+            posted.notify_all();
+        }
+        Ok(response)
+    }
+}
+
+impl<S: Storage + Send + 'static> SharedServer<S>
+where
+    S::State: Send,
+{
+    /// Handles every connection on a thread of the pool.
+    ///
+    /// `timeout` limits every read and write of a connection, so a client which stalls
+    /// only holds its own thread for a while. Waiting for a message is not limited by it.
+    /// While all threads are busy and as many connections wait for one, no more connections
+    /// are accepted.
+    pub fn run<T: IoStream + Send + 'static>(
+        &self,
+        pool: &ThreadPool,
+        timeout: Duration,
+        incoming: impl Iterator<Item = Result<T, Error>>,
+    ) {
+        for stream_or_error in incoming {
+            match stream_or_error {
+                Ok(mut stream) => {
+                    let server = self.clone();
+                    pool.execute(move || {
+                        if let Err(e) = stream
+                            .set_timeout(timeout)
+                            .and_then(|_| server.update(&mut stream))
+                        {
+                            eprintln!("IO error: {e}");
+                        }
+                    });
+                }
+                Err(e) => eprintln!("IO error: {e}"),
+            }
+        }
+    }
+}
+
+fn wait(request: &Request) -> Result<Duration, HttpError> {
+    let query = request.url.url_query();
+    // a `start=now` request never returns messages.
+    if query.contains_key("start") {
+        return Ok(Duration::ZERO);
+    }
+    Ok(match query.get("wait") {
+        Some(ms) => Duration::from_millis(
+            ms.parse()
+                .map_err(|_| HttpError::bad_request("invalid wait"))?,
+        )
+        .min(MAX_WAIT),
+        None => Duration::ZERO,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        thread::{sleep, spawn},
+        time::{Duration, Instant},
+    };
+
+    use crate::mem_io_stream::MemIoStreamEx;
+
+    use super::SharedServer;
+
+    fn call(server: &SharedServer, msg: &str) -> String {
+        let mut result = Vec::default();
+        server
+            .update(&mut msg.as_bytes().mem_io_stream(&mut result))
+            .unwrap();
+        String::from_utf8(result).unwrap()
+    }
+
+    const POST: &str = "\
+        POST / HTTP/1.0\r\n\
+        Content-Length: 6\r\n\
+        \r\n\
+        Hello!";
+
+    const GET: &str = "\
+        GET /?id=x&wait=10000 HTTP/1.0\r\n\
+        \r\n";
+
+    const MESSAGE: &str = "\
+        HTTP/1.0 200 OK\r\n\
+        content-length:6\r\n\
+        \r\n\
+        Hello!";
+
+    const EMPTY: &str = "\
+        HTTP/1.0 200 OK\r\n\
+        \r\n";
+
+    #[test]
+    fn available_message_test() {
+        let server = SharedServer::default();
+        assert_eq!(call(&server, POST), EMPTY);
+        let start = Instant::now();
+        assert_eq!(call(&server, GET), MESSAGE);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn wait_for_message_test() {
+        let server = SharedServer::default();
+        let poster = {
+            let server = server.clone();
+            spawn(move || {
+                sleep(Duration::from_millis(100));
+                assert_eq!(call(&server, POST), EMPTY);
+            })
+        };
+        let start = Instant::now();
+        assert_eq!(call(&server, GET), MESSAGE);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_secs(10));
+        poster.join().unwrap();
+    }
+
+    #[test]
+    fn timeout_test() {
+        let server = SharedServer::default();
+        const GET: &str = "\
+            GET /?id=x&wait=100 HTTP/1.0\r\n\
+            \r\n";
+        let start = Instant::now();
+        const NO_CONTENT: &str = "\
+            HTTP/1.0 204 No Content\r\n\
+            \r\n";
+        assert_eq!(call(&server, GET), NO_CONTENT);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn invalid_wait_test() {
+        let server = SharedServer::default();
+        const GET: &str = "\
+            GET /?id=x&wait=soon HTTP/1.0\r\n\
+            \r\n";
+        const INVALID: &str = "\
+            HTTP/1.0 400 Bad Request\r\n\
+            content-length:12\r\n\
+            \r\n\
+            invalid wait";
+        assert_eq!(call(&server, GET), INVALID);
+    }
+}
@@ -1,4 +1,63 @@
+use std::{collections::BTreeMap, io::Error, ops::Range};
+
+use crate::cursor::{Cursor, Indexed};
+
+/// A message store.
+///
+/// `get`, `get_all` and `read` return the broadcast messages and the messages posted to the
+/// node in the order they were posted, so a node reads every broadcast message which was
+/// posted before a message to it first.
+///
+/// A state which fails to store a change returns the error, and has to be opened from its
+/// `Storage` again to drop the changes which weren't stored.
 pub trait State {
-    fn get(&mut self, node_id: String) -> Vec<u8>;
-    fn post(&mut self, msg: Vec<u8>);
+    fn get(&mut self, node_id: String) -> Result<Vec<u8>, Error>;
+    /// Returns all unread messages for the node, in the order they were posted.
+    fn get_all(&mut self, node_id: String) -> Result<Vec<Vec<u8>>, Error>;
+    fn post(&mut self, msg: Vec<u8>) -> Result<(), Error>;
+    /// Posts a message which is delivered to the given node only.
+    fn post_to(&mut self, node_id: String, msg: Vec<u8>) -> Result<(), Error>;
+    /// Posts a message which another relay of a cluster forwarded, with the `origin` it
+    /// assigned, see `Indexed`.
+    fn post_replica(&mut self, msg: Vec<u8>, _origin: String) -> Result<(), Error> {
+        self.post(msg)
+    }
+    /// Posts a message to a node which another relay of a cluster forwarded.
+    fn post_to_replica(
+        &mut self,
+        node_id: String,
+        msg: Vec<u8>,
+        _origin: String,
+    ) -> Result<(), Error> {
+        self.post_to(node_id, msg)
+    }
+    /// Marks all messages posted so far as read for a node which hasn't read any message yet,
+    /// so the node starts with the next message. A node which read before keeps its position.
+    fn start(&mut self, node_id: String) -> Result<(), Error>;
+    /// Returns the broadcast messages and the messages to the node after the cursor with
+    /// their indices, without changing the read position. Messages to the node up to the
+    /// cursor are removed.
+    fn read(&mut self, node_id: String, cursor: Cursor) -> Result<Vec<Indexed>, Error>;
+    /// Moves the node back to the first kept broadcast message, so it reads them all again.
+    fn reset(&mut self, node_id: String) -> Result<(), Error>;
+
+    // A state which doesn't keep the messages itself, like `RemoteState`, reports nothing.
+
+    /// Indices of the kept broadcast messages. Indices count all messages ever posted,
+    /// including dropped ones.
+    fn queue(&self) -> Range<usize> {
+        0..0
+    }
+    /// The index of the first unread broadcast message of every node which read or started.
+    fn highwaters(&self) -> BTreeMap<String, usize> {
+        BTreeMap::new()
+    }
+    /// The number of unread messages posted to the node only.
+    fn direct_len(&self, _node_id: &str) -> usize {
+        0
+    }
+    /// The index of the next message posted to the node only.
+    fn direct_end(&self, _node_id: &str) -> usize {
+        0
+    }
 }
@@ -0,0 +1,14 @@
+use std::io::Error;
+
+use crate::state::State;
+
+/// Creates the `State` of every namespace of a `Server`.
+pub trait Storage {
+    type State: State;
+    /// Returns the state of the namespace, with the messages stored before if any.
+    fn open(&mut self, namespace: &str) -> Result<Self::State, Error>;
+    /// Whether messages of the namespace are stored, so `open` doesn't create it.
+    fn exists(&self, namespace: &str) -> bool;
+    /// Removes all messages stored for the namespace.
+    fn remove(&mut self, namespace: &str) -> Result<(), Error>;
+}
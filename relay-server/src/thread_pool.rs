@@ -0,0 +1,135 @@
+use std::{
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{spawn, JoinHandle},
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of threads which run jobs in the order they are given.
+///
+/// A job waits until a thread is free, so the number of threads is the number of requests
+/// which can be handled at the same time, including long polling requests. At most as many
+/// jobs as threads wait, then `execute` waits too.
+pub struct ThreadPool {
+    sender: Option<SyncSender<Job>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0);
+        let (sender, receiver) = sync_channel::<Job>(size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let threads = (0..size)
+            .map(|_| {
+                let receiver = receiver.clone();
+                spawn(move || worker(&receiver))
+            })
+            .collect();
+        ThreadPool {
+            sender: Some(sender),
+            threads,
+        }
+    }
+    /// Waits while the queue is full.
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        if let Some(sender) = &self.sender {
+            // the threads only stop when the pool is dropped.
+            sender.send(Box::new(job)).unwrap();
+        }
+    }
+}
+
+fn worker(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        // the lock is released before the job runs.
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        match job {
+            Ok(job) => job(),
+            Err(_) => return,
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    /// Waits for all given jobs.
+    fn drop(&mut self) {
+        self.sender = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Barrier,
+        },
+        thread::{scope, sleep},
+        time::Duration,
+    };
+
+    use super::ThreadPool;
+
+    #[test]
+    fn test() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        {
+            let pool = ThreadPool::new(4);
+            for _ in 0..100 {
+                let counter = counter.clone();
+                pool.execute(move || {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                });
+            }
+        }
+        assert_eq!(counter.load(Ordering::Relaxed), 100);
+    }
+
+    #[test]
+    fn full_test() {
+        let pool = ThreadPool::new(1);
+        let barrier = Arc::new(Barrier::new(2));
+        {
+            let barrier = barrier.clone();
+            pool.execute(move || {
+                barrier.wait();
+            });
+        }
+        // the first job runs, the second one waits in the queue
+        pool.execute(|| {});
+        let queued = AtomicBool::new(false);
+        scope(|s| {
+            s.spawn(|| {
+                pool.execute(|| {});
+                queued.store(true, Ordering::Relaxed);
+            });
+            sleep(Duration::from_millis(100));
+            assert!(!queued.load(Ordering::Relaxed));
+            barrier.wait();
+        });
+        assert!(queued.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn concurrent_test() {
+        // every job waits for all others, so they must run at the same time.
+        let pool = ThreadPool::new(8);
+        let barrier = Arc::new(Barrier::new(8));
+        for _ in 0..8 {
+            let barrier = barrier.clone();
+            pool.execute(move || {
+                barrier.wait();
+            });
+        }
+    }
+}
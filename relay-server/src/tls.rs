@@ -0,0 +1,129 @@
+use std::{
+    io::{BufReader, Error, ErrorKind, Read, Write},
+    net::TcpStream,
+    sync::Arc,
+    time::Duration,
+};
+
+use rustls::{
+    crypto::ring::default_provider,
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+    server::WebPkiClientVerifier,
+    ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned,
+};
+
+use crate::io_stream::IoStream;
+
+/// A TLS connection over TCP, `TlsStream<ServerConnection>` on the server side and
+/// `TlsStream<ClientConnection>` on the client side.
+///
+/// The handshake happens with the first read or write, so accepting a connection never blocks.
+pub struct TlsStream<C>(pub StreamOwned<C, TcpStream>);
+
+impl TlsStream<ServerConnection> {
+    pub fn accept(config: Arc<ServerConfig>, stream: TcpStream) -> Result<Self, Error> {
+        let connection = ServerConnection::new(config).map_err(invalid)?;
+        Ok(TlsStream(StreamOwned::new(connection, stream)))
+    }
+}
+
+impl TlsStream<ClientConnection> {
+    /// `server_name` is the name in the server certificate, for example `localhost`.
+    pub fn connect(
+        config: Arc<ClientConfig>,
+        server_name: &str,
+        stream: TcpStream,
+    ) -> Result<Self, Error> {
+        let server_name = ServerName::try_from(server_name.to_string()).map_err(invalid)?;
+        let connection = ClientConnection::new(config, server_name).map_err(invalid)?;
+        Ok(TlsStream(StreamOwned::new(connection, stream)))
+    }
+}
+
+impl<C> IoStream for TlsStream<C>
+where
+    StreamOwned<C, TcpStream>: Read + Write,
+{
+    type Read = StreamOwned<C, TcpStream>;
+    type Write = StreamOwned<C, TcpStream>;
+    fn istream(&mut self) -> &mut Self::Read {
+        &mut self.0
+    }
+    fn ostream(&mut self) -> &mut Self::Write {
+        &mut self.0
+    }
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.0.sock.set_timeout(timeout)
+    }
+}
+
+/// A server configuration with a certificate chain and its private key in PEM.
+///
+/// With `client_ca`, a client has to present a certificate signed by one of these CAs.
+pub fn server_config(
+    cert: &[u8],
+    key: &[u8],
+    client_ca: Option<&[u8]>,
+) -> Result<Arc<ServerConfig>, Error> {
+    let provider = Arc::new(default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid)?;
+    let builder = match client_ca {
+        Some(client_ca) => builder.with_client_cert_verifier(
+            WebPkiClientVerifier::builder_with_provider(root_store(client_ca)?, provider)
+                .build()
+                .map_err(invalid)?,
+        ),
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(certs(cert)?, private_key(key)?)
+        .map_err(invalid)?;
+    Ok(Arc::new(config))
+}
+
+/// A client configuration which only trusts servers with a certificate signed by `ca`.
+///
+/// `identity` is a certificate chain and its private key, for a server which verifies clients.
+pub fn client_config(
+    ca: &[u8],
+    identity: Option<(&[u8], &[u8])>,
+) -> Result<Arc<ClientConfig>, Error> {
+    let builder = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(invalid)?
+        .with_root_certificates(root_store(ca)?);
+    let config = match identity {
+        Some((cert, key)) => builder
+            .with_client_auth_cert(certs(cert)?, private_key(key)?)
+            .map_err(invalid)?,
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+fn certs(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(pem)).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "no certificate"));
+    }
+    Ok(certs)
+}
+
+fn private_key(pem: &[u8]) -> Result<PrivateKeyDer<'static>, Error> {
+    rustls_pemfile::private_key(&mut BufReader::new(pem))?
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no private key"))
+}
+
+fn root_store(pem: &[u8]) -> Result<Arc<RootCertStore>, Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in certs(pem)? {
+        roots.add(cert).map_err(invalid)?;
+    }
+    Ok(Arc::new(roots))
+}
+
+fn invalid(e: impl std::error::Error + Send + Sync + 'static) -> Error {
+    Error::new(ErrorKind::InvalidInput, e)
+}
@@ -2,9 +2,13 @@ use std::collections::HashMap;
 
 pub trait QueryEx {
     fn url_query(&self) -> HashMap<&str, &str>;
+    fn url_path(&self) -> &str;
 }
 
 impl QueryEx for str {
+    fn url_path(&self) -> &str {
+        self.split_once('?').map_or(self, |(left, _)| left)
+    }
     fn url_query(&self) -> HashMap<&str, &str> {
         match self.split_once('?') {
             Some((_, right)) if !right.is_empty() => right
@@ -53,4 +57,11 @@ mod tests {
         assert!(x.get("azx").unwrap().is_empty());
         assert_eq!(x.get("id").unwrap().to_owned(), "hello");
     }
+
+    #[test]
+    fn path_test() {
+        assert_eq!("/".url_path(), "/");
+        assert_eq!("/rounds/5?id=1&batch".url_path(), "/rounds/5");
+        assert_eq!("?id=1".url_path(), "");
+    }
 }
@@ -15,6 +15,18 @@ curl 'http://127.0.0.1:9776' -X POST -d 'Msg # 2'
 curl 'http://127.0.0.1:9776/?id=1'
 curl 'http://127.0.0.1:9776/?id=4'
 curl 'http://127.0.0.1:9776/?id=4'
+# read all unread messages at once
+curl 'http://127.0.0.1:9776/?id=3&batch'
+# send a message to one client only
+curl 'http://127.0.0.1:9776/?to=5' -X POST -d 'Msg # 5 only'
+curl 'http://127.0.0.1:9776/?id=5&batch'
+# skip all messages posted so far
+curl 'http://127.0.0.1:9776/?id=6&start=now'
+curl 'http://127.0.0.1:9776/?id=6'
+# use a namespace
+curl 'http://127.0.0.1:9776/rounds/5' -X POST -d 'Round # 5'
+curl 'http://127.0.0.1:9776/rounds/5?id=1'
+curl 'http://127.0.0.1:9776/rounds/5' -X DELETE
 # try an empty message
 curl 'http://127.0.0.1:9776' -X POST
 curl 'http://127.0.0.1:9776/?id=1'
@@ -0,0 +1,173 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread::{sleep, spawn},
+    time::Duration,
+};
+
+use relay_server::{
+    decode_indexed, Auth, Credentials, IoStream, Replicator, Request, Server, SharedServer,
+    ThreadPool, REPLICA_HEADER,
+};
+
+fn start_relay(listener: TcpListener, peers: &[SocketAddr]) {
+    let peers: Vec<_> = peers.iter().map(|addr| format!("http://{addr}")).collect();
+    let server = Server::default().with_replicator(Replicator::new(&peers, None, None).unwrap());
+    start(listener, server);
+}
+
+fn start(listener: TcpListener, server: Server) {
+    spawn(move || {
+        let pool = ThreadPool::new(4);
+        SharedServer::new(server).run(&pool, Duration::from_secs(10), listener.incoming());
+    });
+}
+
+fn request(method: &str, url: &str, content: &str) -> Request {
+    Request::new(
+        method.to_string(),
+        url.to_string(),
+        Default::default(),
+        content.as_bytes().to_vec(),
+    )
+}
+
+fn call(addr: SocketAddr, method: &str, url: &str, content: &str) -> Vec<u8> {
+    TcpStream::connect(addr)
+        .unwrap()
+        .call(request(method, url, content))
+        .content
+}
+
+#[test]
+fn replication_test() {
+    let listeners: Vec<_> = (0..3)
+        .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+        .collect();
+    let addrs: Vec<_> = listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+    // the third relay is down at first
+    let mut listeners = listeners.into_iter();
+    let first = listeners.next().unwrap();
+    let second = listeners.next().unwrap();
+    drop(listeners);
+    start_relay(first, &[addrs[1], addrs[2]]);
+    start_relay(second, &[addrs[0], addrs[2]]);
+
+    call(addrs[0], "POST", "/", "Hello!");
+    call(addrs[1], "POST", "/?to=x", "To X!");
+    // every relay has every message once
+    for addr in &addrs[..2] {
+        assert_eq!(
+            call(*addr, "GET", "/?id=x&wait=5000", ""),
+            b"Hello!",
+            "{addr}"
+        );
+        assert_eq!(call(*addr, "GET", "/?id=x&wait=5000", ""), b"To X!");
+        assert!(call(*addr, "GET", "/?id=x", "").is_empty());
+    }
+
+    // the messages are forwarded once the third relay is up
+    start_relay(TcpListener::bind(addrs[2]).unwrap(), &[addrs[0], addrs[1]]);
+    assert_eq!(call(addrs[2], "GET", "/?id=y&wait=5000", ""), b"Hello!");
+    assert!(call(addrs[2], "GET", "/?id=y", "").is_empty());
+    // the relays which forward the messages retry independently, so they come in any order
+    let mut messages = [
+        call(addrs[2], "GET", "/?id=x&wait=5000", ""),
+        call(addrs[2], "GET", "/?id=x&wait=5000", ""),
+    ];
+    messages.sort();
+    assert_eq!(messages, [b"Hello!".to_vec(), b"To X!".to_vec()]);
+}
+
+#[test]
+fn authenticated_replication_test() {
+    let alice = Credentials::new("alice", &"01".repeat(32)).unwrap();
+    let relays: Vec<_> = (0..2)
+        .map(|i| {
+            Credentials::new(format!("relay{i}"), &format!("{:02}", i + 2).repeat(32)).unwrap()
+        })
+        .collect();
+    let keys: HashMap<_, _> = [&alice, &relays[0], &relays[1]]
+        .iter()
+        .map(|credentials| (credentials.node_id().to_string(), credentials.public_key()))
+        .collect();
+    let listeners: Vec<_> = (0..2)
+        .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+        .collect();
+    let addrs: Vec<_> = listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+    for (i, listener) in listeners.into_iter().enumerate() {
+        let peer = format!("http://{}", addrs[1 - i]);
+        let auth = Auth::new(&keys)
+            .unwrap()
+            .with_relays([relays[1 - i].node_id().to_string()]);
+        let replicator = Replicator::new(&[peer], None, Some(relays[i].clone())).unwrap();
+        start(
+            listener,
+            Server::default()
+                .with_auth(auth)
+                .with_replicator(replicator),
+        );
+    }
+    let signed = |addr, method: &str, url: &str, content: &str| {
+        let mut request = request(method, url, content);
+        alice.sign(&mut request);
+        TcpStream::connect(addr).unwrap().call(request)
+    };
+
+    assert_eq!(signed(addrs[0], "POST", "/", "Hello!").code, 200);
+    // the peer accepts the message from the relay, not the client
+    assert_eq!(
+        signed(addrs[1], "GET", "/?id=alice&wait=5000", "").content,
+        b"Hello!"
+    );
+    // the client's request can't be replayed to the peer as a forwarded request
+    let mut forged = request("POST", "/", "Forged!");
+    alice.sign(&mut forged);
+    forged
+        .headers
+        .insert(REPLICA_HEADER.to_string(), "1".to_string());
+    assert_eq!(TcpStream::connect(addrs[1]).unwrap().call(forged).code, 403);
+}
+
+#[test]
+fn origin_test() {
+    let listeners: Vec<_> = (0..2)
+        .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+        .collect();
+    let addrs: Vec<_> = listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+    for (i, listener) in listeners.into_iter().enumerate() {
+        let peers = [format!("http://{}", addrs[1 - i])];
+        let server = Server::default()
+            .with_id(format!("relay{i}"))
+            .with_replicator(Replicator::new(&peers, None, None).unwrap());
+        start(listener, server);
+    }
+    call(addrs[1], "POST", "/", "Msg # 0");
+    // the first relay counts the forwarded message before its own
+    assert_eq!(call(addrs[0], "GET", "/?id=y&wait=5000", ""), b"Msg # 0");
+    call(addrs[0], "POST", "/", "Msg # 1");
+    call(addrs[0], "POST", "/?to=x", "To X!");
+    let origins = |addr| {
+        // waits until the messages are forwarded
+        for _ in 0..50 {
+            let messages =
+                decode_indexed(&call(addr, "GET", "/?id=x&after=&direct_after=", "")).unwrap();
+            if messages.len() == 3 {
+                return messages
+                    .into_iter()
+                    .map(|message| (message.msg, message.origin.unwrap()))
+                    .collect::<HashSet<_>>();
+            }
+            sleep(Duration::from_millis(100));
+        }
+        panic!("{addr} misses messages");
+    };
+    // the origins are the same on every relay
+    let expected = HashSet::from([
+        (b"Msg # 0".to_vec(), "relay1/0".to_string()),
+        (b"Msg # 1".to_vec(), "relay0/1".to_string()),
+        (b"To X!".to_vec(), "relay0/0".to_string()),
+    ]);
+    assert_eq!(origins(addrs[0]), expected);
+    assert_eq!(origins(addrs[1]), expected);
+}
@@ -0,0 +1,115 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread::{sleep, spawn},
+    time::{Duration, Instant},
+};
+
+use relay_server::{IoStream, Limits, Request, Response, Server, SharedServer, ThreadPool};
+
+const POLLERS: usize = 200;
+
+fn start_server(threads: usize, timeout: Duration) -> SocketAddr {
+    start_limited_server(threads, timeout, Limits::default())
+}
+
+fn start_limited_server(threads: usize, timeout: Duration, limits: Limits) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    spawn(move || {
+        let pool = ThreadPool::new(threads);
+        SharedServer::new(Server::default().with_limits(limits)).run(
+            &pool,
+            timeout,
+            listener.incoming(),
+        );
+    });
+    addr
+}
+
+fn call(addr: SocketAddr, method: &str, url: String, content: &str) -> Response {
+    TcpStream::connect(addr).unwrap().call(Request::new(
+        method.to_string(),
+        url,
+        Default::default(),
+        content.as_bytes().to_vec(),
+    ))
+}
+
+#[test]
+fn many_pollers_test() {
+    let addr = start_server(POLLERS + 16, Duration::from_secs(10));
+    let pollers = (0..POLLERS)
+        .map(|id| spawn(move || call(addr, "GET", format!("/?id={id}&wait=30000"), "").content))
+        .collect::<Vec<_>>();
+    // give the pollers time to connect, they are all waiting now.
+    sleep(Duration::from_millis(500));
+    let start = Instant::now();
+    call(addr, "POST", "/".to_string(), "Hello!");
+    for poller in pollers {
+        assert_eq!(poller.join().unwrap(), "Hello!".as_bytes());
+    }
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn stalled_client_test() {
+    let addr = start_server(4, Duration::from_millis(200));
+    // clients which send an incomplete request, or nothing at all.
+    let mut stalled = (0..4)
+        .map(|_| TcpStream::connect(addr).unwrap())
+        .collect::<Vec<_>>();
+    stalled[0].write_all(b"POST / HTTP/1.0\r\n").unwrap();
+    let start = Instant::now();
+    call(addr, "POST", "/".to_string(), "Hello!");
+    assert_eq!(
+        call(addr, "GET", "/?id=x".to_string(), "").content,
+        "Hello!".as_bytes()
+    );
+    // the stalled clients hold the threads until the timeout only.
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn slow_client_test() {
+    let limits = Limits {
+        max_read_time: Duration::from_millis(500),
+        ..Limits::default()
+    };
+    let addr = start_limited_server(4, Duration::from_millis(200), limits);
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let start = Instant::now();
+    // every byte comes before the read timeout, but the request takes too long.
+    let result = b"POST / HTTP/1.0\r\nContent-Length: 6\r\n\r\nHello!"
+        .iter()
+        .try_for_each(|b| {
+            sleep(Duration::from_millis(50));
+            stream.write_all(&[*b])
+        });
+    let mut response = String::default();
+    let _ = stream.read_to_string(&mut response);
+    assert!(response.starts_with("HTTP/1.0 408 Request Timeout\r\n"));
+    assert!(result.is_err() || start.elapsed() < Duration::from_secs(3));
+}
+
+#[test]
+fn persistent_connection_test() {
+    let addr = start_server(4, Duration::from_secs(10));
+    let mut stream = TcpStream::connect(addr).unwrap();
+    for i in 0..3 {
+        let msg = format!("Msg # {i}");
+        let request = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n{msg}GET /?id=x HTTP/1.1\r\n\r\n",
+            msg.len()
+        );
+        stream.write_all(request.as_bytes()).unwrap();
+        let expected = format!(
+            "HTTP/1.1 200 OK\r\ncontent-length:0\r\n\r\n\
+            HTTP/1.1 200 OK\r\ncontent-length:{}\r\n\r\n{msg}",
+            msg.len()
+        );
+        let mut response = vec![0; expected.len()];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!(String::from_utf8(response).unwrap(), expected);
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+    thread::spawn,
+    time::Duration,
+};
+
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+};
+use relay_server::{
+    client_config, server_config, IoStream, Request, SharedServer, ThreadPool, TlsStream,
+};
+use rustls::{ClientConfig, ServerConfig};
+
+struct Ca {
+    cert: Certificate,
+    key: KeyPair,
+}
+
+impl Ca {
+    fn new() -> Self {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        Ca { cert, key }
+    }
+    // returns a certificate and its private key in PEM
+    fn issue(&self, name: &str, usage: ExtendedKeyUsagePurpose) -> (String, String) {
+        let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+        params.extended_key_usages = vec![usage];
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+    fn pem(&self) -> String {
+        self.cert.pem()
+    }
+}
+
+fn start_server(config: Arc<ServerConfig>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    spawn(move || {
+        let pool = ThreadPool::new(4);
+        let incoming = listener
+            .incoming()
+            .map(move |stream| TlsStream::accept(config.clone(), stream?));
+        SharedServer::default().run(&pool, Duration::from_secs(10), incoming);
+    });
+    addr
+}
+
+fn request(method: &str, url: &str, content: &str) -> Request {
+    Request::new(
+        method.to_string(),
+        url.to_string(),
+        Default::default(),
+        content.as_bytes().to_vec(),
+    )
+}
+
+fn connect(addr: SocketAddr, config: &Arc<ClientConfig>) -> impl IoStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    TlsStream::connect(config.clone(), "localhost", stream).unwrap()
+}
+
+fn call(addr: SocketAddr, config: &Arc<ClientConfig>, request: Request) -> Vec<u8> {
+    connect(addr, config).call(request).content
+}
+
+// sends a request on a connection which the server or the client should refuse
+fn refused(addr: SocketAddr, config: &Arc<ClientConfig>) -> bool {
+    let mut tls = connect(addr, config);
+    let mut response = Vec::default();
+    let result = tls
+        .ostream()
+        .write_all(b"GET /?id=x HTTP/1.0\r\n\r\n")
+        .and_then(|_| tls.ostream().flush())
+        .and_then(|_| tls.istream().read_to_end(&mut response));
+    matches!(result, Err(_) | Ok(0))
+}
+
+#[test]
+fn tls_test() {
+    let ca = Ca::new();
+    let (cert, key) = ca.issue("localhost", ExtendedKeyUsagePurpose::ServerAuth);
+    let addr = start_server(server_config(cert.as_bytes(), key.as_bytes(), None).unwrap());
+
+    let client = client_config(ca.pem().as_bytes(), None).unwrap();
+    call(addr, &client, request("POST", "/", "Hello!"));
+    assert_eq!(call(addr, &client, request("GET", "/?id=x", "")), b"Hello!");
+
+    // a client which doesn't trust the server's CA
+    let other = client_config(Ca::new().pem().as_bytes(), None).unwrap();
+    assert!(refused(addr, &other));
+}
+
+#[test]
+fn client_certificate_test() {
+    let ca = Ca::new();
+    let (cert, key) = ca.issue("localhost", ExtendedKeyUsagePurpose::ServerAuth);
+    let addr = start_server(
+        server_config(cert.as_bytes(), key.as_bytes(), Some(ca.pem().as_bytes())).unwrap(),
+    );
+
+    let (client_cert, client_key) = ca.issue("signer-1", ExtendedKeyUsagePurpose::ClientAuth);
+    let client = client_config(
+        ca.pem().as_bytes(),
+        Some((client_cert.as_bytes(), client_key.as_bytes())),
+    )
+    .unwrap();
+    call(addr, &client, request("POST", "/", "Hello!"));
+    assert_eq!(call(addr, &client, request("GET", "/?id=x", "")), b"Hello!");
+
+    // a client without a certificate is rejected
+    let anonymous = client_config(ca.pem().as_bytes(), None).unwrap();
+    assert!(refused(addr, &anonymous));
+}
@@ -0,0 +1,69 @@
+use bitcoin::{Address, Script, Txid};
+use blockstack_lib::types::chainstate::BurnchainHeaderHash;
+
+pub mod client;
+
+#[cfg_attr(test, mockall::automock)]
+pub trait BitcoinNode {
+    fn broadcast_transaction(&self, tx: &BitcoinTransaction) -> Result<Txid, Error>;
+    /// The confirmations of a transaction, 0 while it is in the mempool, `None` if it is unknown
+    fn confirmations(&self, txid: &Txid) -> Result<Option<u32>, Error>;
+    /// The unspent outputs which pay an address
+    fn list_unspent(&self, address: &Address) -> Result<Vec<Utxo>, Error>;
+    /// The fee rate in sats per virtual byte for a transaction to confirm within `target_blocks`
+    fn estimate_fee_rate(&self, target_blocks: u16) -> Result<u64, Error>;
+    /// The hash of the block at the height in the best chain of the node
+    fn burn_header_hash(&self, block_height: u64) -> Result<BurnchainHeaderHash, Error>;
+}
+
+pub type BitcoinTransaction = bitcoin::Transaction;
+
+/// An unspent transaction output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Utxo {
+    pub txid: Txid,
+    pub vout: u32,
+    /// The amount in sats
+    pub amount: u64,
+    pub script_pubkey: Script,
+    pub confirmations: u32,
+}
+
+/// Kinds of errors of a bitcoin node
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The node could not be reached
+    #[error("Failed to reach the bitcoin node: {0}")]
+    NetworkError(String),
+    /// The node answered with an error status and no RPC error
+    #[error("The bitcoin node answered {0}: {1}")]
+    StatusError(u16, String),
+    /// The node refused the call
+    #[error("Bitcoin RPC error {0}: {1}")]
+    RpcError(i64, String),
+    /// The node answered with something else than expected
+    #[error("Invalid response from the bitcoin node: {0}")]
+    InvalidResponse(String),
+    /// The transaction can't be broadcast
+    #[error("Invalid transaction: {0}")]
+    InvalidTransaction(String),
+    /// An input of the transaction is spent by another transaction, in a block or the mempool
+    #[error("The inputs of the transaction are already spent: {0}")]
+    InputsSpent(String),
+    /// The node has not seen enough transactions to estimate the fee
+    #[error("The bitcoin node can't estimate the fee")]
+    NoFeeEstimate,
+}
+
+impl Error {
+    /// Errors which may go away when the call is repeated
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::NetworkError(_) => true,
+            Self::StatusError(code, _) => *code >= 500,
+            // the node is still loading
+            Self::RpcError(code, _) => *code == client::RPC_IN_WARMUP,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,518 @@
+use bitcoin::hashes::Hash;
+use bitcoin::schnorr::{TapTweak, TweakedPublicKey};
+use bitcoin::secp256k1::{schnorr, Message, Secp256k1, XOnlyPublicKey};
+use bitcoin::util::address::WitnessVersion;
+use bitcoin::util::sighash::{Prevouts, SighashCache};
+use bitcoin::{
+    Address, Network, OutPoint, PackedLockTime, PubkeyHash, SchnorrSighashType, Script, ScriptHash,
+    Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use blockstack_lib::chainstate::stacks::address::{PoxAddress, PoxAddressType32};
+use wtfrost::Point;
+
+use crate::bitcoin_node::{BitcoinTransaction, Utxo};
+use crate::error::Result;
+use crate::peg_wallet::BitcoinWallet as BitcoinWalletTrait;
+use crate::stacks_node::PegOutRequestOp;
+
+// the c32 versions of single signature addresses on mainnet and testnet
+const SINGLESIG_VERSIONS: [u8; 2] = [22, 26];
+
+/// Outputs below this amount in sats are not relayed
+pub const DUST_LIMIT: u64 = 546;
+
+/// The peg wallet, a taproot address which the FROST aggregate key spends with a key path.
+///
+/// The aggregate key is the internal key, the output key is tweaked with it as BIP-341 describes
+/// for an output without a script tree. A signature of an input has to be a BIP-340 signature of
+/// the tweaked key, with an even `R`, the tagged challenge and the even output key. Every
+/// signature is verified before it is used, a signature which isn't one fails the peg-out.
+pub struct BitcoinWallet {
+    network: Network,
+    public_key: Option<XOnlyPublicKey>,
+}
+
+impl BitcoinWallet {
+    pub fn new(network: Network) -> Self {
+        Self {
+            network,
+            public_key: None,
+        }
+    }
+
+    /// Sets the aggregate key of a DKG round, which holds the peg funds.
+    pub fn set_aggregate_public_key(&mut self, key: &Point) -> Result<()> {
+        let compressed = key.compress();
+        let public_key = XOnlyPublicKey::from_slice(&compressed.as_bytes()[1..])
+            .map_err(|e| Error::InvalidPublicKey(e.to_string()))?;
+        self.public_key = Some(public_key);
+        Ok(())
+    }
+
+    fn public_key(&self) -> Result<XOnlyPublicKey> {
+        Ok(self.public_key.ok_or(Error::NoPublicKey)?)
+    }
+
+    /// The aggregate key tweaked without a script tree
+    fn output_key(&self) -> Result<TweakedPublicKey> {
+        let (output_key, _) = self
+            .public_key()?
+            .tap_tweak(&Secp256k1::verification_only(), None);
+        Ok(output_key)
+    }
+}
+
+impl BitcoinWalletTrait for BitcoinWallet {
+    fn address(&self) -> Result<Address> {
+        Ok(Address::p2tr_tweaked(self.output_key()?, self.network))
+    }
+
+    /// Pays the recipient the amount of the request minus the fee, which is paid for
+    /// `fee_rate` sats per virtual byte. The largest confirmed UTXOs are spent, the change
+    /// goes back to the peg wallet.
+    fn fulfill_peg_out(
+        &self,
+        op: &PegOutRequestOp,
+        utxos: &[Utxo],
+        fee_rate: u64,
+    ) -> Result<PegOutTransaction> {
+        let output_key = self.output_key()?.to_inner();
+        let peg_wallet = self.address()?.script_pubkey();
+
+        let mut utxos: Vec<_> = utxos.iter().filter(|u| u.confirmations > 0).collect();
+        utxos.sort_by(|a, b| b.amount.cmp(&a.amount));
+        let mut selected = Vec::new();
+        let mut total = 0;
+        for utxo in utxos {
+            if total >= op.amount {
+                break;
+            }
+            total += utxo.amount;
+            selected.push(utxo);
+        }
+        if total < op.amount {
+            return Err(Error::InsufficientFunds(op.amount, total).into());
+        }
+
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: selected
+                .iter()
+                .map(|utxo| TxIn {
+                    previous_output: OutPoint::new(utxo.txid, utxo.vout),
+                    script_sig: Script::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    // the size of a signature
+                    witness: Witness::from_vec(vec![vec![0; 64]]),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: op.amount,
+                script_pubkey: script_pubkey(&op.recipient),
+            }],
+        };
+        let change = total - op.amount;
+        if change >= DUST_LIMIT {
+            tx.output.push(TxOut {
+                value: change,
+                script_pubkey: peg_wallet,
+            });
+        }
+        let fee = tx.vsize() as u64 * fee_rate;
+        tx.output[0].value = op
+            .amount
+            .checked_sub(fee)
+            .filter(|value| *value >= DUST_LIMIT)
+            .ok_or(Error::AmountTooSmall(op.amount, fee))?;
+        for input in &mut tx.input {
+            input.witness = Witness::new();
+        }
+
+        Ok(PegOutTransaction {
+            tx,
+            prevouts: selected
+                .iter()
+                .map(|utxo| TxOut {
+                    value: utxo.amount,
+                    script_pubkey: utxo.script_pubkey.clone(),
+                })
+                .collect(),
+            output_key,
+        })
+    }
+}
+
+/// A peg-out fulfillment, which is broadcast once every input is signed
+#[derive(Debug, Clone)]
+pub struct PegOutTransaction {
+    pub tx: BitcoinTransaction,
+    // the outputs which the inputs spend
+    prevouts: Vec<TxOut>,
+    // the key which the signatures are verified with
+    output_key: XOnlyPublicKey,
+}
+
+impl PegOutTransaction {
+    /// The taproot key path sighash of every input, which the aggregate key signs
+    pub fn sighashes(&self) -> Result<Vec<[u8; 32]>> {
+        let mut cache = SighashCache::new(&self.tx);
+        (0..self.tx.input.len())
+            .map(|input| {
+                let sighash = cache
+                    .taproot_key_spend_signature_hash(
+                        input,
+                        &Prevouts::All(&self.prevouts),
+                        SchnorrSighashType::Default,
+                    )
+                    .map_err(|e| Error::SighashError(e.to_string()))?;
+                Ok(sighash.into_inner())
+            })
+            .collect()
+    }
+
+    /// Adds a BIP-340 signature of the sighash of an input as its witness, when it verifies
+    /// with the output key.
+    pub fn set_signature(&mut self, input: usize, bytes: &[u8; 64]) -> Result<()> {
+        let sighash = self
+            .sighashes()?
+            .get(input)
+            .copied()
+            .ok_or(Error::InvalidSignature(input))?;
+        let message =
+            Message::from_slice(&sighash).map_err(|e| Error::SighashError(e.to_string()))?;
+        let valid = schnorr::Signature::from_slice(bytes)
+            .map(|signature| {
+                Secp256k1::verification_only()
+                    .verify_schnorr(&signature, &message, &self.output_key)
+                    .is_ok()
+            })
+            .unwrap_or(false);
+        if !valid {
+            return Err(Error::InvalidSignature(input).into());
+        }
+        self.tx.input[input].witness = Witness::from_vec(vec![bytes.to_vec()]);
+        Ok(())
+    }
+}
+
+/// The output script which pays a PoX address.
+pub fn script_pubkey(address: &PoxAddress) -> Script {
+    match address {
+        PoxAddress::Standard(address, _) if SINGLESIG_VERSIONS.contains(&address.version) => {
+            Script::new_p2pkh(&PubkeyHash::from_inner(address.bytes.0))
+        }
+        PoxAddress::Standard(address, _) => {
+            Script::new_p2sh(&ScriptHash::from_inner(address.bytes.0))
+        }
+        PoxAddress::Addr20(_, _, bytes) => Script::new_witness_program(WitnessVersion::V0, bytes),
+        PoxAddress::Addr32(_, PoxAddressType32::P2WSH, bytes) => {
+            Script::new_witness_program(WitnessVersion::V0, bytes)
+        }
+        PoxAddress::Addr32(_, PoxAddressType32::P2TR, bytes) => {
+            Script::new_witness_program(WitnessVersion::V1, bytes)
+        }
+    }
+}
+
+/// Kinds of errors of the peg wallet
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// There is no aggregate key before a DKG round
+    #[error("The peg wallet has no aggregate public key")]
+    NoPublicKey,
+    #[error("Invalid aggregate public key: {0}")]
+    InvalidPublicKey(String),
+    /// The amount needed and the amount of the confirmed UTXOs
+    #[error("Insufficient funds, {0} sats needed and {1} sats available")]
+    InsufficientFunds(u64, u64),
+    /// The amount of the request and the fee
+    #[error("The amount of {0} sats doesn't cover the fee of {1} sats")]
+    AmountTooSmall(u64, u64),
+    #[error("Failed to compute the sighash: {0}")]
+    SighashError(String),
+    /// The signature of an input isn't a BIP-340 signature of the tweaked aggregate key
+    #[error("Invalid signature for input {0}, it isn't a BIP-340 signature of the peg wallet")]
+    InvalidSignature(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+    use std::thread::spawn;
+
+    use bitcoin::secp256k1::{KeyPair, SecretKey};
+    use bitcoin::{Network, Script, Txid};
+    use blockstack_lib::{
+        burnchains::Txid as BurnchainTxid,
+        chainstate::stacks::address::PoxAddress,
+        types::chainstate::{BurnchainHeaderHash, StacksAddress},
+        util::{hash::Hash160, secp256k1::MessageSignature},
+    };
+    use frost_coordinator::coordinator::Coordinator as FrostCoordinator;
+    use frost_signer::config::Config as SignerConfig;
+    use frost_signer::mem_net::{MemNet, MemNetListen};
+    use frost_signer::net::{Message as NetMessage, NetListen};
+    use frost_signer::signing_round::{party_ids_for_signer, SigningRound};
+    use frost_signer::taproot;
+
+    use super::*;
+    use crate::error::Error as CoordinatorError;
+    use crate::stacks_node::PegInOp;
+
+    fn key_pair() -> KeyPair {
+        KeyPair::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[1; 32]).unwrap())
+    }
+
+    fn wallet() -> BitcoinWallet {
+        let (public_key, _) = key_pair().x_only_public_key();
+        let mut wallet = BitcoinWallet::new(Network::Regtest);
+        wallet.public_key = Some(public_key);
+        wallet
+    }
+
+    fn utxo(vout: u32, amount: u64, confirmations: u32) -> Utxo {
+        Utxo {
+            txid: Txid::from_str(
+                "6bd5e7dc66f1cc1e3e5c1c0d2b4ac5d25c38d5c4b2f2ef9b6b0fb3c6a4bd1d3e",
+            )
+            .unwrap(),
+            vout,
+            amount,
+            script_pubkey: wallet().address().unwrap().script_pubkey(),
+            confirmations,
+        }
+    }
+
+    fn peg_out_request_op(amount: u64) -> PegOutRequestOp {
+        let pox_address = PoxAddress::Standard(StacksAddress::new(26, Hash160([2; 20])), None);
+        PegOutRequestOp {
+            recipient: pox_address.clone(),
+            peg_wallet_address: pox_address,
+            amount,
+            fulfillment_fee: 0,
+            signature: MessageSignature([0; 65]),
+            memo: vec![],
+            txid: BurnchainTxid([0; 32]),
+            burn_header_hash: BurnchainHeaderHash([0; 32]),
+            block_height: 1,
+            vtxindex: 0,
+        }
+    }
+
+    #[test]
+    fn fulfill_peg_out_test() {
+        let wallet = wallet();
+        let utxos = [utxo(0, 5_000, 1), utxo(1, 20_000, 1), utxo(2, 100_000, 0)];
+        let fulfill = wallet
+            .fulfill_peg_out(&peg_out_request_op(10_000), &utxos, 2)
+            .unwrap();
+        let tx = &fulfill.tx;
+        // the largest confirmed UTXO is enough
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output.vout, 1);
+        assert!(tx.input[0].witness.is_empty());
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(
+            tx.output[0].script_pubkey,
+            Script::new_p2pkh(&PubkeyHash::from_inner([2; 20]))
+        );
+        assert_eq!(tx.output[1].value, 10_000);
+        assert_eq!(
+            tx.output[1].script_pubkey,
+            wallet.address().unwrap().script_pubkey()
+        );
+        // the recipient pays the fee
+        let fee = 10_000 - tx.output[0].value;
+        assert!((2 * 100..=2 * 200).contains(&fee), "fee {}", fee);
+        assert_eq!(fulfill.sighashes().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn fulfill_peg_out_errors_test() {
+        let wallet = wallet();
+        let utxos = [utxo(0, 5_000, 1), utxo(1, 20_000, 0)];
+        assert!(matches!(
+            wallet.fulfill_peg_out(&peg_out_request_op(10_000), &utxos, 1),
+            Err(CoordinatorError::BitcoinWalletError(
+                Error::InsufficientFunds(10_000, 5_000)
+            ))
+        ));
+        assert!(matches!(
+            wallet.fulfill_peg_out(&peg_out_request_op(600), &utxos, 1),
+            Err(CoordinatorError::BitcoinWalletError(Error::AmountTooSmall(
+                600,
+                _
+            )))
+        ));
+        assert!(matches!(
+            BitcoinWallet::new(Network::Regtest).address(),
+            Err(CoordinatorError::BitcoinWalletError(Error::NoPublicKey))
+        ));
+    }
+
+    /// Checks the witness of an input like a node, with the key of the output it spends
+    fn verify_witness(fulfill: &PegOutTransaction, input: usize) {
+        let witness = fulfill.tx.input[input].witness.to_vec();
+        assert_eq!(witness.len(), 1);
+        let output_key =
+            XOnlyPublicKey::from_slice(&fulfill.prevouts[input].script_pubkey[2..]).unwrap();
+        let sighash = fulfill.sighashes().unwrap()[input];
+        Secp256k1::verification_only()
+            .verify_schnorr(
+                &schnorr::Signature::from_slice(&witness[0]).unwrap(),
+                &Message::from_slice(&sighash).unwrap(),
+                &output_key,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn set_signature_test() {
+        let secp = Secp256k1::new();
+        let wallet = wallet();
+        let mut fulfill = wallet
+            .fulfill_peg_out(&peg_out_request_op(10_000), &[utxo(0, 20_000, 1)], 1)
+            .unwrap();
+        let message = Message::from_slice(&fulfill.sighashes().unwrap()[0]).unwrap();
+        let sign = |key_pair: &KeyPair| {
+            let mut bytes = [0; 64];
+            bytes.copy_from_slice(&secp.sign_schnorr_no_aux_rand(&message, key_pair)[..]);
+            bytes
+        };
+
+        // the untweaked aggregate key doesn't spend the peg wallet
+        assert!(matches!(
+            fulfill.set_signature(0, &sign(&key_pair())),
+            Err(CoordinatorError::BitcoinWalletError(
+                Error::InvalidSignature(0)
+            ))
+        ));
+        assert!(fulfill.tx.input[0].witness.is_empty());
+
+        let tweaked = key_pair().tap_tweak(&secp, None).to_inner();
+        fulfill.set_signature(0, &sign(&tweaked)).unwrap();
+        verify_witness(&fulfill, 0);
+    }
+
+    /// A FROST coordinator of signers on a memory network after a DKG round, its aggregate key
+    /// and a function which stops the signers
+    fn frost_signers() -> (FrostCoordinator<MemNetListen>, Point, impl FnOnce()) {
+        const TOTAL_SIGNERS: usize = 3;
+        let config = SignerConfig {
+            total_signers: TOTAL_SIGNERS,
+            total_keys: TOTAL_SIGNERS * 2,
+            keys_threshold: TOTAL_SIGNERS * 2 * 2 / 3,
+            max_party_id: TOTAL_SIGNERS,
+            ..Default::default()
+        };
+        let net = MemNet::new();
+        let stop = Arc::new(AtomicBool::new(false));
+        let signers = (1..=TOTAL_SIGNERS as u32)
+            .map(|signer_id| {
+                let mut net = MemNetListen::new(net.clone());
+                let mut round = SigningRound::new(
+                    config.keys_threshold,
+                    config.total_keys,
+                    signer_id,
+                    party_ids_for_signer(signer_id),
+                );
+                let stop = stop.clone();
+                spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        net.poll(signer_id).unwrap();
+                        while let Some(message) = net.next_message() {
+                            for msg in round.process(message.msg).unwrap() {
+                                net.send_message(NetMessage { msg, sig: [0; 32] }).unwrap();
+                            }
+                            net.ack().unwrap();
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut coordinator = FrostCoordinator::new(0, 0, &config, MemNetListen::new(net));
+        let key = coordinator.run_distributed_key_generation().unwrap();
+        let stop = move || {
+            stop.store(true, Ordering::Relaxed);
+            for signer in signers {
+                signer.join().unwrap();
+            }
+        };
+        (coordinator, key, stop)
+    }
+
+    #[test]
+    fn frost_signature_test() {
+        let (mut coordinator, key, stop) = frost_signers();
+        let mut wallet = BitcoinWallet::new(Network::Regtest);
+        wallet.set_aggregate_public_key(&key).unwrap();
+        let utxos = [(0, 20_000), (1, 15_000)].map(|(vout, amount)| Utxo {
+            script_pubkey: wallet.address().unwrap().script_pubkey(),
+            ..utxo(vout, amount, 1)
+        });
+        let mut fulfill = wallet
+            .fulfill_peg_out(&peg_out_request_op(30_000), &utxos, 1)
+            .unwrap();
+        // the signers sign every input with a new nonce
+        let signatures = fulfill
+            .sighashes()
+            .unwrap()
+            .iter()
+            .map(|sighash| {
+                let signature = coordinator.sign_message(sighash).unwrap();
+                assert!(taproot::verify(&key, sighash, &signature));
+                signature
+            })
+            .collect::<Vec<_>>();
+        stop();
+
+        assert_eq!(signatures.len(), 2);
+        for (input, signature) in signatures.iter().enumerate() {
+            assert!(fulfill.set_signature(input, signature).is_ok());
+            verify_witness(&fulfill, input);
+        }
+    }
+    #[test]
+    fn peg_in_spend_test() {
+        let (mut coordinator, key, stop) = frost_signers();
+        let mut wallet = BitcoinWallet::new(Network::Regtest);
+        wallet.set_aggregate_public_key(&key).unwrap();
+        let op = PegInOp {
+            recipient: StacksAddress::new(26, Hash160([0; 20])).into(),
+            peg_wallet_address: PoxAddress::Addr32(
+                false,
+                PoxAddressType32::P2TR,
+                taproot::output_key(&key),
+            ),
+            amount: 20_000,
+            memo: vec![],
+            txid: BurnchainTxid([0; 32]),
+            vtxindex: 0,
+            block_height: 1,
+            burn_header_hash: BurnchainHeaderHash([0; 32]),
+        };
+        // the coordinator only accepts a peg-in which pays the peg wallet
+        let peg_in_script = script_pubkey(&op.peg_wallet_address);
+        assert_eq!(peg_in_script, wallet.address().unwrap().script_pubkey());
+
+        // and a peg-out spends its output with a signature of the signers
+        let utxo = Utxo {
+            script_pubkey: peg_in_script,
+            ..utxo(0, op.amount, 1)
+        };
+        let mut fulfill = wallet
+            .fulfill_peg_out(&peg_out_request_op(10_000), &[utxo], 1)
+            .unwrap();
+        let sighash = fulfill.sighashes().unwrap()[0];
+        let signature = coordinator.sign_message(&sighash).unwrap();
+        stop();
+        assert!(fulfill.set_signature(0, &signature).is_ok());
+        verify_witness(&fulfill, 0);
+    }
+}
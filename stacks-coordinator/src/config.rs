@@ -14,6 +14,44 @@ pub struct Config {
     pub bitcoin_node_rpc_url: Url,
     pub frost_dkg_round_id: u64,
     pub signer_config_path: String,
+    /// The state of the last DKG round, which is loaded on start so the peg wallet keeps its key.
+    /// A new round only runs with the `dkg` command or when there is no saved state.
+    #[serde(default = "default_dkg_state_path")]
+    pub dkg_state_path: String,
+    /// The fee in microSTX of every contract call
+    #[serde(default = "default_stacks_fee")]
+    pub stacks_fee: u64,
+    /// The peg queue database, the queue is kept in memory without it
+    pub rusqlite_path: Option<String>,
+    /// The peg queue starts with the burn block after this height
+    #[serde(default)]
+    pub start_block_height: u64,
+    /// The burn blocks on top of the block of a peg-in before sBTC is minted for it
+    #[serde(default = "default_peg_in_depth")]
+    pub peg_in_depth: u64,
+    /// The burn blocks on top of the block of a peg-out request before it is fulfilled
+    #[serde(default)]
+    pub peg_out_depth: u64,
+    /// The smallest peg-in in sats which sBTC is minted for
+    #[serde(default)]
+    pub min_peg_in_amount: u64,
+    /// The bitcoin network, `bitcoin`, `testnet`, `signet` or `regtest`, `testnet` by default.
+    /// The contract calls are made on stacks mainnet with `bitcoin`, on testnet otherwise.
+    pub bitcoin_network: Option<String>,
+    /// The directory which contains `yarpc/js`, `..` by default
+    pub yarpc_path: Option<String>,
+}
+
+fn default_dkg_state_path() -> String {
+    "dkg_state.json".to_string()
+}
+
+fn default_peg_in_depth() -> u64 {
+    6
+}
+
+fn default_stacks_fee() -> u64 {
+    10_000
 }
 
 impl Config {
@@ -1,25 +1,49 @@
+use bitcoin::{Network, OutPoint};
+use blockstack_lib::burnchains::Txid;
+use blockstack_lib::types::chainstate::{BurnchainHeaderHash, StacksAddress};
+use frost_coordinator::coordinator::DkgState;
 use frost_coordinator::create_coordinator;
 use frost_signer::net::HttpNetListen;
+use frost_signer::taproot::SchnorrSignature;
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::mpsc;
-use wtfrost::{common::Signature, Point};
+use std::time::Duration;
+use tracing::{info, warn};
+use wtfrost::Point;
 
+use crate::bitcoin_node::client::BitcoinClient;
+use crate::bitcoin_node::Error as BitcoinNodeError;
+use crate::bitcoin_wallet::{script_pubkey, BitcoinWallet as BitcoinWalletImpl};
 use crate::config::Config;
+use crate::peg_queue::{BroadcastTransaction, SqlitePegQueue};
 use crate::peg_wallet::StacksWallet;
-use crate::peg_wallet::{BitcoinWallet, PegWallet};
+use crate::peg_wallet::{BitcoinWallet, PegWallet, WrapPegWallet};
 use crate::stacks_node;
+use crate::stacks_node::client::NodeClient;
+use crate::stacks_node::Error as StacksNodeError;
+use crate::stacks_wallet_js::StacksWalletJs;
 
 // Traits in scope
 use crate::bitcoin_node::BitcoinNode;
 use crate::peg_queue::{PegQueue, SbtcOp};
 use crate::stacks_node::StacksNode;
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 
 type FrostCoordinator = frost_coordinator::coordinator::Coordinator<HttpNetListen>;
 
 // TODO: Define these types
 pub type PublicKey = Point;
 
+/// How long the coordinator waits before it polls the stacks node again when there are no ops
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// The number of blocks a peg-out fulfillment should confirm within, which sets its fee
+pub const FEE_TARGET_BLOCKS: u16 = 6;
+/// The fee rate in sats per virtual byte when the bitcoin node has no estimate, like on regtest
+pub const MIN_FEE_RATE: u64 = 1;
+
 pub trait Coordinator: Sized {
     type PegQueue: PegQueue;
     type FeeWallet: PegWallet;
@@ -27,6 +51,7 @@ pub trait Coordinator: Sized {
     type BitcoinNode: BitcoinNode;
 
     // Required methods
+    fn config(&self) -> &Config;
     fn peg_queue(&self) -> &Self::PegQueue;
     fn fee_wallet(&mut self) -> &mut Self::FeeWallet;
     fn frost_coordinator(&self) -> &FrostCoordinator;
@@ -35,18 +60,27 @@ pub trait Coordinator: Sized {
     fn bitcoin_node(&self) -> &Self::BitcoinNode;
 
     // Provided methods
+    /// Processes the ops of the peg queue until it is stopped. A failing op or poll is
+    /// logged and retried later, only an error of the peg queue itself stops the coordinator.
     fn run(mut self, commands: mpsc::Receiver<Command>) -> Result<()> {
         loop {
-            match self.peg_queue().sbtc_op()? {
-                Some(SbtcOp::PegIn(op)) => self.peg_in(op)?,
-                Some(SbtcOp::PegOutRequest(op)) => self.peg_out(op)?,
-                None => self.peg_queue().poll(self.stacks_node())?,
-            }
+            let wait = match self.peg_queue().sbtc_op()? {
+                Some(op) => {
+                    self.process(op)?;
+                    Duration::ZERO
+                }
+                None => {
+                    if let Err(e) = self.poll() {
+                        warn!("Failed to poll: {}", e);
+                    }
+                    POLL_INTERVAL
+                }
+            };
 
-            match commands.try_recv() {
+            match commands.recv_timeout(wait) {
                 Ok(Command::Stop) => break,
-                Err(mpsc::TryRecvError::Disconnected) => break,
-                Err(mpsc::TryRecvError::Empty) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
             }
         }
         Ok(())
@@ -55,44 +89,316 @@ pub trait Coordinator: Sized {
 
 // Private helper functions
 trait CoordinatorHelpers: Coordinator {
+    /// Processes an op, a failure is recorded in the peg queue
+    fn process(&mut self, op: SbtcOp) -> Result<()> {
+        let (txid, burn_header_hash) = (op.txid(), op.burn_header_hash());
+        let result = match op {
+            SbtcOp::PegIn(op) => self.peg_in(op),
+            SbtcOp::PegOutRequest(op) => self.peg_out(op),
+        };
+        if let Err(e) = result {
+            warn!("Failed to process op {}: {}", txid, e);
+            self.peg_queue()
+                .fail(&txid, &burn_header_hash, &e.to_string(), e.is_transient())?;
+        }
+        Ok(())
+    }
+
+    /// Polls the stacks node for new ops, broadcasts the signed ops again and acknowledges the
+    /// broadcast ops whose transactions are confirmed
+    fn poll(&self) -> Result<()> {
+        self.peg_queue()
+            .poll(self.stacks_node(), self.bitcoin_node())?;
+        // an op whose broadcast failed is only ever retried with the transactions it was
+        // signed with, new ones could pay it twice. It fails once they can't be broadcast.
+        for (op, transactions) in self.peg_queue().signed_ops()? {
+            let (txid, burn_header_hash) = (op.txid(), op.burn_header_hash());
+            if let Err(e) = self.broadcast(&txid, &burn_header_hash, &transactions) {
+                warn!("Failed to broadcast op {} again: {}", txid, e);
+                self.peg_queue().fail(
+                    &txid,
+                    &burn_header_hash,
+                    &e.to_string(),
+                    e.is_transient(),
+                )?;
+            }
+        }
+        for (op, transactions) in self.peg_queue().broadcast_ops()? {
+            let mut confirmed = true;
+            for transaction in &transactions {
+                if !self.is_confirmed(transaction)? {
+                    confirmed = false;
+                    break;
+                }
+            }
+            if confirmed {
+                info!("The transactions of op {} are confirmed", op.txid());
+                self.peg_queue()
+                    .acknowledge(&op.txid(), &op.burn_header_hash())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_confirmed(&self, transaction: &BroadcastTransaction) -> Result<bool> {
+        match transaction {
+            BroadcastTransaction::Stacks { sender, nonce, .. } => {
+                let sender = StacksAddress::from_string(sender).ok_or_else(|| {
+                    StacksNodeError::InvalidTransaction(format!("invalid sender {}", sender))
+                })?;
+                Ok(self.stacks_node().next_nonce(sender)? > *nonce)
+            }
+            BroadcastTransaction::Bitcoin { txid, .. } => {
+                let txid = bitcoin::Txid::from_str(txid)
+                    .map_err(|e| BitcoinNodeError::InvalidResponse(e.to_string()))?;
+                // a transaction in the mempool has 0 confirmations
+                let confirmations = self.bitcoin_node().confirmations(&txid)?;
+                Ok(confirmations.unwrap_or(0) > 0)
+            }
+        }
+    }
+
+    /// Records the signed transactions of an op and broadcasts them, an op is never broadcast
+    /// with other transactions than the recorded ones
+    fn sign_and_broadcast(
+        &self,
+        txid: &Txid,
+        burn_header_hash: &BurnchainHeaderHash,
+        transactions: &[BroadcastTransaction],
+    ) -> Result<()> {
+        self.peg_queue()
+            .sign(txid, burn_header_hash, transactions)?;
+        self.broadcast(txid, burn_header_hash, transactions)
+    }
+
+    /// Broadcasts the transactions of a signed op which aren't confirmed yet
+    fn broadcast(
+        &self,
+        txid: &Txid,
+        burn_header_hash: &BurnchainHeaderHash,
+        transactions: &[BroadcastTransaction],
+    ) -> Result<()> {
+        for transaction in transactions {
+            if !self.is_confirmed(transaction)? {
+                self.broadcast_transaction(transaction)?;
+            }
+        }
+        self.peg_queue().broadcast(txid, burn_header_hash)?;
+        Ok(())
+    }
+
+    fn broadcast_transaction(&self, transaction: &BroadcastTransaction) -> Result<()> {
+        match transaction {
+            BroadcastTransaction::Stacks { serialized, .. } => {
+                let tx = hex::decode(serialized)
+                    .map_err(|e| StacksNodeError::InvalidTransaction(e.to_string()))?;
+                self.stacks_node().broadcast_transaction(&tx)?;
+            }
+            BroadcastTransaction::Bitcoin { serialized, .. } => {
+                self.bitcoin_node()
+                    .broadcast_transaction(&bitcoin_transaction(serialized)?)?;
+            }
+        }
+        Ok(())
+    }
+
     fn peg_in(&mut self, op: stacks_node::PegInOp) -> Result<()> {
-        let tx = self.fee_wallet().stacks_mut().mint(&op)?;
-        self.stacks_node().broadcast_transaction(&tx);
+        self.validate_peg_in(&op)?;
+        let nonce = self.next_nonce()?;
+        let tx = self.fee_wallet().stacks_mut().mint(&op, nonce)?;
+        let transactions = [BroadcastTransaction::try_from(&tx)?];
+        if self.was_orphaned(&op.txid, &op.burn_header_hash)? {
+            warn!(
+                "Not minting sBTC for peg-in {}: its burn block was orphaned",
+                op.txid
+            );
+            return Ok(());
+        }
+        self.sign_and_broadcast(&op.txid, &op.burn_header_hash, &transactions)
+    }
+
+    /// Checks that a peg-in pays the peg wallet, is large enough and wasn't minted before, an
+    /// `Error::InvalidPegIn` tells why it isn't. An invalid peg-in fails without retries. The
+    /// peg queue only hands out a peg-in once it is `peg_in_depth` blocks deep.
+    fn validate_peg_in(&mut self, op: &stacks_node::PegInOp) -> Result<()> {
+        let address = self.fee_wallet().bitcoin_mut().address()?;
+        if script_pubkey(&op.peg_wallet_address) != address.script_pubkey() {
+            return Err(Error::InvalidPegIn(format!(
+                "it pays {:?} instead of the peg wallet {}",
+                op.peg_wallet_address, address
+            )));
+        }
+
+        let minimum = self.config().min_peg_in_amount;
+        if op.amount < minimum {
+            return Err(Error::InvalidPegIn(format!(
+                "its amount {} is below the minimum {minimum}",
+                op.amount
+            )));
+        }
+
+        if self.peg_queue().is_acknowledged(&op.txid)? {
+            return Err(Error::InvalidPegIn("it was minted before".to_string()));
+        }
         Ok(())
     }
 
     fn peg_out(&mut self, op: stacks_node::PegOutRequestOp) -> Result<()> {
-        let _stacks = self.fee_wallet().stacks_mut();
-        let burn_tx = self.fee_wallet().stacks_mut().burn(&op)?;
-        let fulfill_tx = self.fee_wallet().bitcoin_mut().fulfill_peg_out(&op);
+        let nonce = self.next_nonce()?;
+        let burn_tx = self.fee_wallet().stacks_mut().burn(&op, nonce)?;
 
-        //TODO: what do we do with the returned signature?
-        self.frost_coordinator_mut()
-            .sign_message(fulfill_tx.as_bytes())?;
+        let address = self.fee_wallet().bitcoin_mut().address()?;
+        // the node lists the outputs of the blocks, which the recorded fulfillments may spend
+        let spent = self.spent_outpoints()?;
+        let utxos: Vec<_> = self
+            .bitcoin_node()
+            .list_unspent(&address)?
+            .into_iter()
+            .filter(|utxo| !spent.contains(&OutPoint::new(utxo.txid, utxo.vout)))
+            .collect();
+        let fee_rate = match self.bitcoin_node().estimate_fee_rate(FEE_TARGET_BLOCKS) {
+            Err(BitcoinNodeError::NoFeeEstimate) => MIN_FEE_RATE,
+            fee_rate => fee_rate?,
+        };
+        let mut fulfill_tx = self
+            .fee_wallet()
+            .bitcoin_mut()
+            .fulfill_peg_out(&op, &utxos, fee_rate)?;
 
-        self.stacks_node().broadcast_transaction(&burn_tx);
-        self.bitcoin_node().broadcast_transaction(&fulfill_tx);
-        Ok(())
+        // every input is signed by the signers
+        for (input, sighash) in fulfill_tx.sighashes()?.iter().enumerate() {
+            let signature = self.frost_coordinator_mut().sign_message(sighash)?;
+            fulfill_tx.set_signature(input, &signature)?;
+        }
+
+        let transactions = [
+            BroadcastTransaction::try_from(&burn_tx)?,
+            BroadcastTransaction::from(&fulfill_tx.tx),
+        ];
+        if self.was_orphaned(&op.txid, &op.burn_header_hash)? {
+            warn!(
+                "Not fulfilling peg-out {}: its burn block was orphaned",
+                op.txid
+            );
+            return Ok(());
+        }
+        self.sign_and_broadcast(&op.txid, &op.burn_header_hash, &transactions)
+    }
+
+    /// The outputs which the bitcoin transactions of the signed and broadcast ops spend
+    fn spent_outpoints(&self) -> Result<HashSet<OutPoint>> {
+        let mut ops = self.peg_queue().signed_ops()?;
+        ops.extend(self.peg_queue().broadcast_ops()?);
+        let mut spent = HashSet::new();
+        for (_, transactions) in ops {
+            for transaction in transactions {
+                // the transactions recorded before the wire format was kept have none
+                if let BroadcastTransaction::Bitcoin { serialized, .. } = transaction {
+                    if !serialized.is_empty() {
+                        let tx = bitcoin_transaction(&serialized)?;
+                        spent.extend(tx.input.iter().map(|input| input.previous_output));
+                    }
+                }
+            }
+        }
+        Ok(spent)
+    }
+
+    /// The lowest nonce of the next transaction of the fee wallet. The stacks node only counts
+    /// confirmed transactions, so the transactions of the signed and broadcast ops are counted
+    /// too.
+    fn next_nonce(&mut self) -> Result<u64> {
+        let address = self.fee_wallet().stacks_mut().address();
+        let mut nonce = self.stacks_node().next_nonce(address.clone())?;
+        let address = address.to_string();
+        let mut ops = self.peg_queue().signed_ops()?;
+        ops.extend(self.peg_queue().broadcast_ops()?);
+        for (_, transactions) in ops {
+            for transaction in transactions {
+                if let BroadcastTransaction::Stacks {
+                    sender, nonce: n, ..
+                } = transaction
+                {
+                    if sender == address {
+                        nonce = nonce.max(n + 1);
+                    }
+                }
+            }
+        }
+        Ok(nonce)
+    }
+
+    /// Polls the peg queue again before an op is broadcast, so an op whose burn block left the
+    /// canonical chain meanwhile is aborted.
+    fn was_orphaned(&self, txid: &Txid, burn_header_hash: &BurnchainHeaderHash) -> Result<bool> {
+        self.peg_queue()
+            .poll(self.stacks_node(), self.bitcoin_node())?;
+        self.peg_queue().is_orphaned(txid, burn_header_hash)
     }
 }
 
 impl<T: Coordinator> CoordinatorHelpers for T {}
 
+/// A bitcoin transaction from its wire format in hex
+fn bitcoin_transaction(serialized: &str) -> Result<bitcoin::Transaction> {
+    let tx =
+        hex::decode(serialized).map_err(|e| BitcoinNodeError::InvalidTransaction(e.to_string()))?;
+    Ok(bitcoin::consensus::encode::deserialize(&tx)
+        .map_err(|e| BitcoinNodeError::InvalidTransaction(e.to_string()))?)
+}
+
 pub enum Command {
     Stop,
 }
 
 pub struct StacksCoordinator {
-    _config: Config,
+    config: Config,
     frost_coordinator: FrostCoordinator,
+    local_peg_queue: SqlitePegQueue,
+    local_stacks_node: NodeClient,
+    local_bitcoin_node: BitcoinClient,
+    pub local_fee_wallet: WrapPegWallet,
 }
 
 impl StacksCoordinator {
+    /// Runs a DKG round, the aggregate key holds the peg funds from now on. The round is saved
+    /// and the address of the new peg wallet is set in the contract.
     pub fn run_dkg_round(&mut self) -> Result<PublicKey> {
-        Ok(self.frost_coordinator.run_distributed_key_generation()?)
+        let key = self.frost_coordinator.run_distributed_key_generation()?;
+        std::fs::write(
+            &self.config.dkg_state_path,
+            serde_json::to_string(&self.frost_coordinator.dkg_state())?,
+        )?;
+        self.local_fee_wallet
+            .bitcoin_wallet
+            .set_aggregate_public_key(&key)?;
+
+        let address = self.local_fee_wallet.bitcoin_wallet.address()?;
+        let nonce = self.next_nonce()?;
+        let tx = self
+            .local_fee_wallet
+            .stacks_wallet
+            .set_wallet_address(&address, nonce)?;
+        self.broadcast_transaction(&BroadcastTransaction::try_from(&tx)?)?;
+        info!("Set the peg wallet address {} in the contract", address);
+        Ok(key)
     }
 
-    pub fn sign_message(&mut self, message: &str) -> Result<Signature> {
+    /// Loads the DKG round saved by `run_dkg_round`, `None` when no round was saved.
+    pub fn load_dkg_round(&mut self) -> Result<Option<PublicKey>> {
+        let path = Path::new(&self.config.dkg_state_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let state: DkgState = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let key = self.frost_coordinator.set_dkg_state(state)?;
+        self.local_fee_wallet
+            .bitcoin_wallet
+            .set_aggregate_public_key(&key)?;
+        Ok(Some(key))
+    }
+
+    pub fn sign_message(&mut self, message: &str) -> Result<SchnorrSignature> {
         Ok(self.frost_coordinator.sign_message(message.as_bytes())?)
     }
 }
@@ -100,12 +406,509 @@ impl StacksCoordinator {
 impl TryFrom<Config> for StacksCoordinator {
     type Error = String;
     fn try_from(config: Config) -> std::result::Result<Self, String> {
+        let local_peg_queue = match &config.rusqlite_path {
+            Some(path) => SqlitePegQueue::new(path, config.start_block_height),
+            None => SqlitePegQueue::in_memory(config.start_block_height),
+        }
+        .map_err(|e| e.to_string())?
+        .with_depths(config.peg_in_depth, config.peg_out_depth);
+        let network = match &config.bitcoin_network {
+            Some(network) => Network::from_str(network).map_err(|e| e.to_string())?,
+            None => Network::Testnet,
+        };
+        // the contract is `<address>.<name>`
+        let (contract_address, contract_name) = config
+            .sbtc_contract
+            .split_once('.')
+            .unwrap_or((config.sbtc_contract.as_str(), ""));
+        let stacks_wallet = StacksWalletJs::new(
+            config.yarpc_path.as_deref().unwrap_or(".."),
+            contract_address.to_string(),
+            contract_name.to_string(),
+            config.stacks_private_key.clone(),
+            network == Network::Bitcoin,
+            config.stacks_fee,
+        )
+        .map_err(|e| e.to_string())?;
         Ok(Self {
-            frost_coordinator: create_coordinator(config.signer_config_path.clone())?,
-            _config: config,
+            frost_coordinator: create_coordinator(&config.signer_config_path)?,
+            local_peg_queue,
+            local_stacks_node: NodeClient::new(&config.stacks_node_rpc_url),
+            local_bitcoin_node: BitcoinClient::new(&config.bitcoin_node_rpc_url),
+            local_fee_wallet: WrapPegWallet {
+                stacks_wallet,
+                bitcoin_wallet: BitcoinWalletImpl::new(network),
+            },
+            config,
         })
     }
 }
 
+impl Coordinator for StacksCoordinator {
+    type PegQueue = SqlitePegQueue;
+    type FeeWallet = WrapPegWallet;
+    type StacksNode = NodeClient;
+    type BitcoinNode = BitcoinClient;
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn peg_queue(&self) -> &Self::PegQueue {
+        &self.local_peg_queue
+    }
+
+    fn fee_wallet(&mut self) -> &mut Self::FeeWallet {
+        &mut self.local_fee_wallet
+    }
+
+    fn frost_coordinator(&self) -> &FrostCoordinator {
+        &self.frost_coordinator
+    }
+
+    fn frost_coordinator_mut(&mut self) -> &mut FrostCoordinator {
+        &mut self.frost_coordinator
+    }
+
+    fn stacks_node(&self) -> &Self::StacksNode {
+        &self.local_stacks_node
+    }
+
+    fn bitcoin_node(&self) -> &Self::BitcoinNode {
+        &self.local_bitcoin_node
+    }
+}
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use bitcoin::util::address::{Payload, WitnessVersion};
+    use blockstack_lib::chainstate::stacks::address::{PoxAddress, PoxAddressType32};
+    use blockstack_lib::util::hash::Hash160;
+    use blockstack_lib::util::secp256k1::MessageSignature;
+    use frost_signer::config::Config as SignerConfig;
+    use frost_signer::net::HttpNet;
+    use serde_json::json;
+
+    use super::*;
+    use crate::bitcoin_node::{MockBitcoinNode, Utxo};
+    use crate::bitcoin_wallet::Error as BitcoinWalletError;
+    use crate::peg_wallet::{MockBitcoinWallet, MockStacksWallet};
+    use crate::stacks_node::MockStacksNode;
+    use crate::stacks_transaction::StacksTransaction;
+
+    const PEG_WALLET: [u8; 32] = [1; 32];
+    const BURN_HEADER_HASH: BurnchainHeaderHash = BurnchainHeaderHash([2; 32]);
+
+    struct TestWallet {
+        stacks: MockStacksWallet,
+        bitcoin: MockBitcoinWallet,
+    }
+
+    impl PegWallet for TestWallet {
+        type StacksWallet = MockStacksWallet;
+        type BitcoinWallet = MockBitcoinWallet;
+        fn stacks_mut(&mut self) -> &mut MockStacksWallet {
+            &mut self.stacks
+        }
+        fn bitcoin_mut(&mut self) -> &mut MockBitcoinWallet {
+            &mut self.bitcoin
+        }
+    }
+
+    struct TestCoordinator {
+        config: Config,
+        peg_queue: SqlitePegQueue,
+        fee_wallet: TestWallet,
+        frost_coordinator: FrostCoordinator,
+        stacks_node: MockStacksNode,
+        bitcoin_node: MockBitcoinNode,
+    }
+
+    impl Coordinator for TestCoordinator {
+        type PegQueue = SqlitePegQueue;
+        type FeeWallet = TestWallet;
+        type StacksNode = MockStacksNode;
+        type BitcoinNode = MockBitcoinNode;
+
+        fn config(&self) -> &Config {
+            &self.config
+        }
+        fn peg_queue(&self) -> &SqlitePegQueue {
+            &self.peg_queue
+        }
+        fn fee_wallet(&mut self) -> &mut TestWallet {
+            &mut self.fee_wallet
+        }
+        fn frost_coordinator(&self) -> &FrostCoordinator {
+            &self.frost_coordinator
+        }
+        fn frost_coordinator_mut(&mut self) -> &mut FrostCoordinator {
+            &mut self.frost_coordinator
+        }
+        fn stacks_node(&self) -> &MockStacksNode {
+            &self.stacks_node
+        }
+        fn bitcoin_node(&self) -> &MockBitcoinNode {
+            &self.bitcoin_node
+        }
+    }
+
+    fn sender() -> StacksAddress {
+        StacksAddress::new(26, Hash160([3; 20]))
+    }
+
+    fn peg_in_op(peg_wallet: [u8; 32], amount: u64) -> stacks_node::PegInOp {
+        stacks_node::PegInOp {
+            recipient: StacksAddress::new(26, Hash160([0; 20])).into(),
+            peg_wallet_address: PoxAddress::Addr32(false, PoxAddressType32::P2TR, peg_wallet),
+            amount,
+            memo: vec![],
+            txid: Txid([4; 32]),
+            vtxindex: 0,
+            block_height: 1,
+            burn_header_hash: BURN_HEADER_HASH,
+        }
+    }
+
+    fn peg_out_request_op() -> stacks_node::PegOutRequestOp {
+        let recipient = PoxAddress::Standard(StacksAddress::new(26, Hash160([0; 20])), None);
+        stacks_node::PegOutRequestOp {
+            recipient,
+            peg_wallet_address: PoxAddress::Addr32(false, PoxAddressType32::P2TR, PEG_WALLET),
+            amount: 10_000,
+            fulfillment_fee: 0,
+            signature: MessageSignature([0; 65]),
+            memo: vec![],
+            txid: Txid([5; 32]),
+            vtxindex: 0,
+            block_height: 1,
+            burn_header_hash: BURN_HEADER_HASH,
+        }
+    }
+
+    fn utxo(vout: u32) -> Utxo {
+        Utxo {
+            txid: bitcoin::Txid::from_str(&"06".repeat(32)).unwrap(),
+            vout,
+            amount: 20_000,
+            script_pubkey: bitcoin::Script::new(),
+            confirmations: 1,
+        }
+    }
+
+    /// A bitcoin transaction which spends the output `vout` of `utxo`
+    fn spending_transaction(vout: u32) -> bitcoin::Transaction {
+        let utxo = utxo(vout);
+        bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::PackedLockTime::ZERO,
+            input: vec![bitcoin::TxIn {
+                previous_output: OutPoint::new(utxo.txid, utxo.vout),
+                ..Default::default()
+            }],
+            output: vec![],
+        }
+    }
+
+    fn stacks_transaction(nonce: u64) -> StacksTransaction {
+        serde_json::from_value(json!({
+            "version": 128,
+            "chainId": 2147483648u32,
+            "auth": {"spendingCondition": {
+                "hashMode": 0,
+                "nonce": nonce.to_string(),
+                "signer": "03".repeat(20),
+            }},
+            "anchorMode": 3,
+            "payload": {},
+            "postConditionMode": 2,
+            "postConditions": {},
+            "serialized": "00",
+        }))
+        .unwrap()
+    }
+
+    /// A coordinator whose stacks node has a single peg-in at burn height 1, and whose stacks
+    /// node reports `nonce` as the next nonce of the fee wallet
+    fn test_coordinator(op: stacks_node::PegInOp, nonce: Arc<AtomicU64>) -> TestCoordinator {
+        let config = toml::from_str(
+            r#"
+            sbtc_contract = "ST000000000000000000002AMW42H.sbtc"
+            stacks_private_key = ""
+            bitcoin_private_key = ""
+            stacks_node_rpc_url = ""
+            bitcoin_node_rpc_url = ""
+            frost_dkg_round_id = 0
+            signer_config_path = ""
+            min_peg_in_amount = 1000
+            "#,
+        )
+        .unwrap();
+
+        let mut stacks_node = MockStacksNode::new();
+        stacks_node.expect_burn_block_height().returning(|| Ok(1));
+        stacks_node
+            .expect_get_peg_in_ops()
+            .returning(move |_| Ok(vec![op.clone()]));
+        stacks_node
+            .expect_get_peg_out_request_ops()
+            .returning(|_| Ok(vec![]));
+        stacks_node
+            .expect_next_nonce()
+            .returning(move |_| Ok(nonce.load(Ordering::Relaxed)));
+
+        let mut bitcoin_node = MockBitcoinNode::new();
+        bitcoin_node
+            .expect_burn_header_hash()
+            .returning(|_| Ok(BURN_HEADER_HASH));
+
+        let mut stacks = MockStacksWallet::new();
+        stacks.expect_address().returning(sender);
+        let mut bitcoin = MockBitcoinWallet::new();
+        bitcoin.expect_address().returning(|| {
+            Ok(bitcoin::Address {
+                network: Network::Regtest,
+                payload: Payload::WitnessProgram {
+                    version: WitnessVersion::V1,
+                    program: PEG_WALLET.to_vec(),
+                },
+            })
+        });
+
+        let net = HttpNetListen::new(HttpNet::new("http://127.0.0.1:0".to_string()), vec![]);
+        let coordinator = TestCoordinator {
+            config,
+            peg_queue: SqlitePegQueue::in_memory(0)
+                .unwrap()
+                .with_depths(0, 0)
+                .with_retry_delay(Duration::ZERO),
+            fee_wallet: TestWallet { stacks, bitcoin },
+            frost_coordinator: FrostCoordinator::new(0, 0, &SignerConfig::default(), net),
+            stacks_node,
+            bitcoin_node,
+        };
+        coordinator.poll().unwrap();
+        coordinator
+    }
+
+    #[test]
+    fn validate_peg_in_test() {
+        let nonce = Arc::new(AtomicU64::new(0));
+        let mut coordinator = test_coordinator(peg_in_op(PEG_WALLET, 1000), nonce);
+        assert!(coordinator
+            .validate_peg_in(&peg_in_op(PEG_WALLET, 1000))
+            .is_ok());
+        // another wallet
+        assert!(matches!(
+            coordinator.validate_peg_in(&peg_in_op([5; 32], 1000)),
+            Err(Error::InvalidPegIn(_))
+        ));
+        // below the minimum
+        assert!(matches!(
+            coordinator.validate_peg_in(&peg_in_op(PEG_WALLET, 999)),
+            Err(Error::InvalidPegIn(_))
+        ));
+
+        let op = peg_in_op(PEG_WALLET, 1000);
+        coordinator
+            .peg_queue()
+            .acknowledge(&op.txid, &op.burn_header_hash)
+            .unwrap();
+        assert!(matches!(
+            coordinator.validate_peg_in(&op),
+            Err(Error::InvalidPegIn(_))
+        ));
+    }
+
+    #[test]
+    fn process_and_acknowledge_peg_in_test() {
+        let nonce = Arc::new(AtomicU64::new(7));
+        let mut coordinator = test_coordinator(peg_in_op(PEG_WALLET, 1000), nonce.clone());
+        coordinator
+            .fee_wallet
+            .stacks
+            .expect_mint()
+            .times(1)
+            .returning(|_, nonce| Ok(stacks_transaction(nonce)));
+        coordinator
+            .stacks_node
+            .expect_broadcast_transaction()
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let op = coordinator.peg_queue().sbtc_op().unwrap().unwrap();
+        coordinator.process(op).unwrap();
+        let broadcast = coordinator.peg_queue().broadcast_ops().unwrap();
+        assert_eq!(broadcast.len(), 1);
+        assert_eq!(
+            broadcast[0].1,
+            [BroadcastTransaction::Stacks {
+                sender: sender().to_string(),
+                nonce: 7,
+                serialized: "00".to_string(),
+            }]
+        );
+        // the next transaction doesn't reuse the nonce before the mint is confirmed
+        assert_eq!(coordinator.next_nonce().unwrap(), 8);
+
+        // the mint isn't confirmed while the nonce of the sender didn't pass it
+        coordinator.poll().unwrap();
+        assert_eq!(coordinator.peg_queue().broadcast_ops().unwrap().len(), 1);
+        nonce.store(8, Ordering::Relaxed);
+        coordinator.poll().unwrap();
+        assert!(coordinator.peg_queue().broadcast_ops().unwrap().is_empty());
+        assert!(coordinator
+            .peg_queue()
+            .is_acknowledged(&Txid([4; 32]))
+            .unwrap());
+    }
+
+    #[test]
+    fn process_peg_in_with_failed_broadcast_test() {
+        let nonce = Arc::new(AtomicU64::new(7));
+        let mut coordinator = test_coordinator(peg_in_op(PEG_WALLET, 1000), nonce);
+        // the peg-in is minted once, its broadcast is retried with the same transaction
+        coordinator
+            .fee_wallet
+            .stacks
+            .expect_mint()
+            .times(1)
+            .returning(|_, nonce| Ok(stacks_transaction(nonce)));
+        let mut broadcasts = 0;
+        coordinator
+            .stacks_node
+            .expect_broadcast_transaction()
+            .times(2)
+            .returning(move |tx| {
+                assert_eq!(tx, [0]);
+                broadcasts += 1;
+                if broadcasts == 1 {
+                    Err(StacksNodeError::NetworkError("node down".to_string()))
+                } else {
+                    Ok(())
+                }
+            });
+
+        let op = coordinator.peg_queue().sbtc_op().unwrap().unwrap();
+        coordinator.process(op).unwrap();
+        assert_eq!(coordinator.peg_queue().signed_ops().unwrap().len(), 1);
+        assert!(coordinator.peg_queue().sbtc_op().unwrap().is_none());
+        assert_eq!(coordinator.next_nonce().unwrap(), 8);
+
+        coordinator.poll().unwrap();
+        assert!(coordinator.peg_queue().signed_ops().unwrap().is_empty());
+        assert_eq!(coordinator.peg_queue().broadcast_ops().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn process_failed_peg_in_test() {
+        // an invalid peg-in fails for good
+        let nonce = Arc::new(AtomicU64::new(0));
+        let mut coordinator = test_coordinator(peg_in_op([5; 32], 1000), nonce.clone());
+        coordinator.fee_wallet.stacks.expect_mint().never();
+        let op = coordinator.peg_queue().sbtc_op().unwrap().unwrap();
+        coordinator.process(op).unwrap();
+        assert!(coordinator.peg_queue().sbtc_op().unwrap().is_none());
+        assert!(coordinator.peg_queue().broadcast_ops().unwrap().is_empty());
+
+        // a transient failure is retried
+        let mut coordinator = test_coordinator(peg_in_op(PEG_WALLET, 1000), nonce);
+        coordinator
+            .fee_wallet
+            .stacks
+            .expect_mint()
+            .times(1)
+            .returning(|_, _| Err(Error::ContractError));
+        let op = coordinator.peg_queue().sbtc_op().unwrap().unwrap();
+        coordinator.process(op).unwrap();
+        let op = coordinator.peg_queue().sbtc_op().unwrap().unwrap();
+        assert_eq!(op.txid(), Txid([4; 32]));
+        assert!(coordinator.peg_queue().broadcast_ops().unwrap().is_empty());
+    }
+
+    #[test]
+    fn peg_out_spends_no_output_of_a_recorded_fulfillment_test() {
+        let nonce = Arc::new(AtomicU64::new(7));
+        let mut coordinator = test_coordinator(peg_in_op(PEG_WALLET, 1000), nonce);
+        let op = coordinator.peg_queue().sbtc_op().unwrap().unwrap();
+        coordinator
+            .peg_queue()
+            .sign(
+                &op.txid(),
+                &op.burn_header_hash(),
+                &[BroadcastTransaction::from(&spending_transaction(0))],
+            )
+            .unwrap();
+
+        coordinator
+            .fee_wallet
+            .stacks
+            .expect_burn()
+            .times(1)
+            .returning(|_, nonce| Ok(stacks_transaction(nonce)));
+        coordinator
+            .bitcoin_node
+            .expect_list_unspent()
+            .returning(|_| Ok(vec![utxo(0), utxo(1)]));
+        coordinator
+            .bitcoin_node
+            .expect_estimate_fee_rate()
+            .returning(|_| Ok(1));
+        // the output which the signed fulfillment spends isn't offered
+        coordinator
+            .fee_wallet
+            .bitcoin
+            .expect_fulfill_peg_out()
+            .times(1)
+            .withf(|_, utxos, _| utxos.to_vec() == vec![utxo(1)])
+            .returning(|op, _, _| Err(BitcoinWalletError::InsufficientFunds(op.amount, 0).into()));
+        assert!(coordinator.peg_out(peg_out_request_op()).is_err());
+    }
+
+    #[test]
+    fn rebroadcast_with_spent_inputs_test() {
+        let nonce = Arc::new(AtomicU64::new(7));
+        let mut coordinator = test_coordinator(peg_in_op(PEG_WALLET, 1000), nonce);
+        let op = coordinator.peg_queue().sbtc_op().unwrap().unwrap();
+        coordinator
+            .peg_queue()
+            .sign(
+                &op.txid(),
+                &op.burn_header_hash(),
+                &[BroadcastTransaction::from(&spending_transaction(0))],
+            )
+            .unwrap();
+
+        coordinator
+            .bitcoin_node
+            .expect_confirmations()
+            .returning(|_| Ok(None));
+        let mut broadcasts = 0;
+        coordinator
+            .bitcoin_node
+            .expect_broadcast_transaction()
+            .times(2)
+            .returning(move |tx| {
+                assert_eq!(*tx, spending_transaction(0));
+                broadcasts += 1;
+                Err(if broadcasts == 1 {
+                    BitcoinNodeError::NetworkError("node down".to_string())
+                } else {
+                    BitcoinNodeError::InputsSpent("bad-txns-inputs-missingorspent".to_string())
+                })
+            });
+
+        // the op stays signed while the node can't be reached
+        coordinator.poll().unwrap();
+        assert_eq!(coordinator.peg_queue().signed_ops().unwrap().len(), 1);
+
+        // and fails once another transaction spends its inputs
+        coordinator.poll().unwrap();
+        assert!(coordinator.peg_queue().signed_ops().unwrap().is_empty());
+        assert!(coordinator.peg_queue().broadcast_ops().unwrap().is_empty());
+        assert!(!coordinator.peg_queue().is_acknowledged(&op.txid()).unwrap());
+    }
+}
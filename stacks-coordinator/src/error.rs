@@ -1,7 +1,10 @@
 use frost_coordinator::coordinator::Error as FrostCoordinatorError;
 use frost_signer::net::HttpNetError;
 
+use crate::bitcoin_node::Error as BitcoinNodeError;
+use crate::bitcoin_wallet::Error as BitcoinWalletError;
 use crate::peg_queue::Error as PegQueueError;
+use crate::stacks_node::Error as StacksNodeError;
 
 /// Helper that uses this module's error type
 pub type Result<T> = std::result::Result<T, Error>;
@@ -16,16 +19,54 @@ pub enum Error {
     /// Error occurred with the sBTC Contract
     #[error("sBTC Contract Error")]
     ContractError,
+    /// The stacks private key of the config
+    #[error("Invalid stacks private key: {0}")]
+    InvalidStacksPrivateKey(String),
+    /// A peg-in which sBTC must not be minted for
+    #[error("Invalid peg-in: {0}")]
+    InvalidPegIn(String),
     /// Error occurred with the Frost Coordinator
     #[error("Frost Coordinator encountered an error: {0}")]
     FrostCoordinatorError(#[from] FrostCoordinatorError),
     /// Error occurred with peg queue
     #[error("Error occurred in the Peg Queue: {0}")]
     PegQueueError(#[from] PegQueueError),
+    /// Error occurred with the stacks node
+    #[error("Stacks Node Error: {0}")]
+    StacksNodeError(#[from] StacksNodeError),
+    /// Error occurred with the bitcoin node
+    #[error("Bitcoin Node Error: {0}")]
+    BitcoinNodeError(#[from] BitcoinNodeError),
+    /// Error occurred with the peg wallet
+    #[error("Bitcoin Wallet Error: {0}")]
+    BitcoinWalletError(#[from] BitcoinWalletError),
     /// Error occurred reading a file
     #[error("Failed to read file: {0}")]
     FileReadingError(#[from] std::io::Error),
+    /// The saved state of a DKG round can't be read or written
+    #[error("Invalid DKG state: {0}")]
+    DkgStateError(#[from] serde_json::Error),
     /// Config parse error
     #[error("Failed to parse config file: {0}")]
     ConfigError(#[from] toml::de::Error),
 }
+
+impl Error {
+    /// Errors which may go away when the failed operation is repeated later
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::HttpNetError(_) | Self::ContractError => true,
+            Self::FrostCoordinatorError(e) => matches!(
+                e,
+                FrostCoordinatorError::NetworkError(_) | FrostCoordinatorError::Timeout
+            ),
+            Self::StacksNodeError(e) => e.is_transient(),
+            Self::BitcoinNodeError(e) => e.is_transient(),
+            // the peg wallet may receive more funds
+            Self::BitcoinWalletError(e) => {
+                matches!(e, BitcoinWalletError::InsufficientFunds(_, _))
+            }
+            _ => false,
+        }
+    }
+}
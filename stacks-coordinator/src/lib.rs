@@ -1,4 +1,5 @@
 pub mod bitcoin_node;
+pub mod bitcoin_wallet;
 pub mod cli;
 pub mod config;
 pub mod coordinator;
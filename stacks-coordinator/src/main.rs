@@ -1,8 +1,12 @@
+use std::sync::mpsc;
+
 use clap::Parser;
 use frost_signer::logging;
 use stacks_coordinator::cli::{Cli, Command};
 use stacks_coordinator::config::Config;
-use stacks_coordinator::coordinator::StacksCoordinator;
+use stacks_coordinator::coordinator::{
+    Command as CoordinatorCommand, Coordinator, StacksCoordinator,
+};
 use tracing::{info, warn};
 
 fn main() {
@@ -26,8 +30,34 @@ fn main() {
                     match cli.command {
                         Command::Run => {
                             info!("Running coordinator");
-                            //TODO: set up coordination with the stacks node
-                            //stacks_coordinator.run();
+                            let (sender, receiver) = mpsc::channel();
+                            if let Err(e) = ctrlc::set_handler(move || {
+                                info!("Stopping coordinator");
+                                let _ = sender.send(CoordinatorCommand::Stop);
+                            }) {
+                                warn!("An error occurred setting the Ctrl-C handler: {}", e);
+                                return;
+                            }
+                            // peg-outs are signed with the key of the last round
+                            match coordinator.load_dkg_round() {
+                                Ok(Some(key)) => {
+                                    info!("Loaded the DKG round of aggregate key {}", key)
+                                }
+                                Ok(None) => {
+                                    info!("No saved DKG round, running DKG Round");
+                                    if let Err(e) = coordinator.run_dkg_round() {
+                                        warn!("An error occurred during DKG round: {}", e);
+                                        return;
+                                    }
+                                }
+                                Err(e) => {
+                                    warn!("An error occurred loading the DKG round: {}", e);
+                                    return;
+                                }
+                            }
+                            if let Err(e) = coordinator.run(receiver) {
+                                warn!("An error occurred running coordinator: {}", e);
+                            }
                         }
                         Command::Dkg => {
                             info!("Running DKG Round");
@@ -92,6 +92,15 @@ impl SignedContractCallOptions {
         self.fee = Some(fee.to_string());
         self
     }
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = Some(nonce.to_string());
+        self
+    }
+    /// `network` is a network name of stacks.js, like `mainnet` or `testnet`
+    pub fn with_network(mut self, network: &str) -> Self {
+        self.network = Some(network.into());
+        self
+    }
 }
 
 pub type TransactionVersion = serde_json::Number;
@@ -116,9 +125,23 @@ pub struct MakeContractCall(Js);
 
 impl MakeContractCall {
     pub fn call(&mut self, input: &SignedContractCallOptions) -> Result<StacksTransaction> {
+        self.dispatch("makeContractCall", input)
+    }
+    /// Like `call`, with the `serialized` transaction which can be broadcast.
+    pub fn call_serialized(
+        &mut self,
+        input: &SignedContractCallOptions,
+    ) -> Result<StacksTransaction> {
+        self.dispatch("makeSerializedContractCall", input)
+    }
+    fn dispatch(
+        &mut self,
+        command: &str,
+        input: &SignedContractCallOptions,
+    ) -> Result<StacksTransaction> {
         let tx = self
             .0
-            .call(&DispatchCommand("makeContractCall".to_string(), input))
+            .call(&DispatchCommand(command.to_string(), input))
             .map_err(|_| Error::ContractError)?;
         Ok(tx)
     }
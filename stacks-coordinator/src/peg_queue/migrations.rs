@@ -0,0 +1,190 @@
+use rusqlite::Connection as RusqliteConnection;
+use rusqlite::OptionalExtension;
+
+use crate::bitcoin_wallet::script_pubkey;
+use crate::peg_queue::Error;
+use crate::peg_queue::SbtcOp;
+
+type Migration = fn(&RusqliteConnection) -> Result<(), Error>;
+
+/// The migrations in order, a database at version `n` has the first `n` applied
+const MIGRATIONS: &[Migration] = &[
+    create_sbtc_ops,
+    create_burn_blocks,
+    add_lifecycle_columns,
+    add_indexes,
+    add_structured_columns,
+];
+
+/// The schema version of a database with all migrations applied
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Brings the schema of a database up to `SCHEMA_VERSION`, every migration runs in its own
+/// transaction. A database from before the `schema_version` table gets the version its
+/// tables have. A database of a newer coordinator is refused, its data may not be understood.
+pub fn migrate(conn: &mut RusqliteConnection) -> Result<(), Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)",
+        rusqlite::params![],
+    )?;
+    let version = match schema_version(conn)? {
+        Some(version) => version,
+        None => {
+            let version = unversioned_schema_version(conn)?;
+            conn.execute(
+                "INSERT INTO schema_version (version) VALUES (?1)",
+                rusqlite::params![version],
+            )?;
+            version
+        }
+    };
+    if version > SCHEMA_VERSION {
+        return Err(Error::UnsupportedSchemaVersion(version, SCHEMA_VERSION));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let tx = conn.transaction()?;
+        migration(&tx)?;
+        tx.execute(
+            "UPDATE schema_version SET version=?1",
+            rusqlite::params![index as u32 + 1],
+        )?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+pub fn schema_version(conn: &RusqliteConnection) -> Result<Option<u32>, Error> {
+    Ok(conn
+        .query_row(
+            "SELECT version FROM schema_version",
+            rusqlite::params![],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+/// The type, amount and recipient of an op for its structured columns. The recipient of a
+/// peg-in is its principal, the one of a peg-out request its bitcoin script in hex.
+pub fn structured_columns(op: &SbtcOp) -> (&'static str, i64, String) {
+    match op {
+        SbtcOp::PegIn(op) => ("peg_in", op.amount as i64, op.recipient.to_string()),
+        SbtcOp::PegOutRequest(op) => (
+            "peg_out_request",
+            op.amount as i64,
+            hex::encode(script_pubkey(&op.recipient).as_bytes()),
+        ),
+    }
+}
+
+fn unversioned_schema_version(conn: &RusqliteConnection) -> Result<u32, Error> {
+    Ok(if !table_exists(conn, "sbtc_ops")? {
+        0
+    } else if column_exists(conn, "sbtc_ops", "attempts")? {
+        3
+    } else if table_exists(conn, "burn_blocks")? {
+        2
+    } else {
+        1
+    })
+}
+
+fn table_exists(conn: &RusqliteConnection, table: &str) -> Result<bool, Error> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name=?1",
+        rusqlite::params![table],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+fn column_exists(conn: &RusqliteConnection, table: &str, column: &str) -> Result<bool, Error> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name=?2",
+        rusqlite::params![table, column],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+fn create_sbtc_ops(conn: &RusqliteConnection) -> Result<(), Error> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS sbtc_ops (
+            txid TEXT NOT NULL,
+            burn_header_hash TEXT NOT NULL,
+            block_height INTEGER NOT NULL,
+            op TEXT NOT NULL,
+            status TEXT NOT NULL,
+
+            PRIMARY KEY(txid, burn_header_hash)
+        );
+        "#,
+    )?;
+    Ok(())
+}
+
+fn create_burn_blocks(conn: &RusqliteConnection) -> Result<(), Error> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS burn_blocks (
+            block_height INTEGER PRIMARY KEY,
+            burn_header_hash TEXT NOT NULL
+        );
+        "#,
+    )?;
+    Ok(())
+}
+
+fn add_lifecycle_columns(conn: &RusqliteConnection) -> Result<(), Error> {
+    conn.execute_batch(
+        r#"
+        ALTER TABLE sbtc_ops ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE sbtc_ops ADD COLUMN last_error TEXT;
+        ALTER TABLE sbtc_ops ADD COLUMN transactions TEXT NOT NULL DEFAULT '[]';
+        ALTER TABLE sbtc_ops ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE sbtc_ops ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE sbtc_ops ADD COLUMN retry_at INTEGER NOT NULL DEFAULT 0;
+        "#,
+    )?;
+    Ok(())
+}
+
+fn add_indexes(conn: &RusqliteConnection) -> Result<(), Error> {
+    conn.execute_batch(
+        r#"
+        CREATE INDEX IF NOT EXISTS sbtc_ops_status ON sbtc_ops (status, block_height);
+        CREATE INDEX IF NOT EXISTS sbtc_ops_block_height ON sbtc_ops (block_height);
+        CREATE INDEX IF NOT EXISTS sbtc_ops_txid ON sbtc_ops (txid);
+        "#,
+    )?;
+    Ok(())
+}
+
+/// The existing ops get the columns from their JSON
+fn add_structured_columns(conn: &RusqliteConnection) -> Result<(), Error> {
+    conn.execute_batch(
+        r#"
+        ALTER TABLE sbtc_ops ADD COLUMN op_type TEXT;
+        ALTER TABLE sbtc_ops ADD COLUMN amount INTEGER;
+        ALTER TABLE sbtc_ops ADD COLUMN recipient TEXT;
+        "#,
+    )?;
+
+    let ops = conn
+        .prepare("SELECT txid, burn_header_hash, op FROM sbtc_ops")?
+        .query_map(rusqlite::params![], |row| {
+            Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?))
+        })?
+        .collect::<Result<Vec<(String, String, String)>, _>>()?;
+    for (txid, burn_header_hash, op) in ops {
+        let (op_type, amount, recipient) = structured_columns(&serde_json::from_str(&op)?);
+        conn.execute(
+            "UPDATE sbtc_ops SET op_type=?1, amount=?2, recipient=?3 WHERE txid=?4 AND burn_header_hash=?5",
+            rusqlite::params![op_type, amount, recipient, txid, burn_header_hash],
+        )?;
+    }
+
+    Ok(())
+}
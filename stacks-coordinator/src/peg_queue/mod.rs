@@ -1,18 +1,110 @@
 use blockstack_lib::burnchains::Txid;
 use blockstack_lib::types::chainstate::BurnchainHeaderHash;
 
+use crate::bitcoin_node;
 use crate::error::Result;
 use crate::stacks_node;
+use crate::stacks_transaction::StacksTransaction;
+mod migrations;
 mod sqlite_peg_queue;
 
+pub use migrations::SCHEMA_VERSION;
 pub use sqlite_peg_queue::Error;
 pub use sqlite_peg_queue::SqlitePegQueue;
+pub use sqlite_peg_queue::MAX_ATTEMPTS;
+pub use sqlite_peg_queue::RETRY_DELAY;
 
 pub trait PegQueue {
+    /// The next new op which is deep enough in the burn chain
     fn sbtc_op(&self) -> Result<Option<SbtcOp>>;
-    fn poll<N: stacks_node::StacksNode>(&self, stacks_node: &N) -> Result<()>;
+    /// The new ops which aren't deep enough in the burn chain yet
+    fn unconfirmed_ops(&self) -> Result<Vec<SbtcOp>>;
+    /// Scans the burn blocks after the last observed one. When an observed block isn't
+    /// canonical anymore, the blocks back to the fork are orphaned and the new branch is
+    /// scanned.
+    fn poll<N: stacks_node::StacksNode, B: bitcoin_node::BitcoinNode>(
+        &self,
+        stacks_node: &N,
+        bitcoin_node: &B,
+    ) -> Result<()>;
 
+    /// Records the signed transactions of a processed op before any of them is broadcast. The op
+    /// is only ever broadcast with these transactions from now on.
+    fn sign(
+        &self,
+        txid: &Txid,
+        burn_header_hash: &BurnchainHeaderHash,
+        transactions: &[BroadcastTransaction],
+    ) -> Result<()>;
+    /// The signed ops with their transactions, which weren't all broadcast yet
+    fn signed_ops(&self) -> Result<Vec<(SbtcOp, Vec<BroadcastTransaction>)>>;
+    /// Records that the transactions of a signed op were broadcast, the op is acknowledged once
+    /// they are confirmed.
+    fn broadcast(&self, txid: &Txid, burn_header_hash: &BurnchainHeaderHash) -> Result<()>;
+    /// The broadcast ops with their transactions
+    fn broadcast_ops(&self) -> Result<Vec<(SbtcOp, Vec<BroadcastTransaction>)>>;
+    /// Records that processing an op failed. A transient failure is retried after a backoff
+    /// until the attempts run out, then the op has failed. A signed op stays signed after a
+    /// transient failure, its transactions are broadcast again.
+    fn fail(
+        &self,
+        txid: &Txid,
+        burn_header_hash: &BurnchainHeaderHash,
+        error: &str,
+        transient: bool,
+    ) -> Result<()>;
     fn acknowledge(&self, txid: &Txid, burn_header_hash: &BurnchainHeaderHash) -> Result<()>;
+    /// Whether an op with the txid was signed, broadcast or acknowledged in any block
+    fn is_acknowledged(&self, txid: &Txid) -> Result<bool>;
+    /// Whether the block of the op left the canonical chain before the op was acknowledged
+    fn is_orphaned(&self, txid: &Txid, burn_header_hash: &BurnchainHeaderHash) -> Result<bool>;
+}
+
+/// A signed transaction of an op with its wire format in hex, which is what is broadcast. The
+/// transactions recorded before the wire format was kept have none.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum BroadcastTransaction {
+    /// A stacks transaction, it is confirmed once the nonce of the sender passed its nonce
+    Stacks {
+        sender: String,
+        nonce: u64,
+        #[serde(default)]
+        serialized: String,
+    },
+    /// A bitcoin transaction by its txid in hex
+    Bitcoin {
+        txid: String,
+        #[serde(default)]
+        serialized: String,
+    },
+}
+
+impl TryFrom<&StacksTransaction> for BroadcastTransaction {
+    type Error = stacks_node::Error;
+
+    fn try_from(tx: &StacksTransaction) -> std::result::Result<Self, Self::Error> {
+        let (sender, nonce) = tx.sender_nonce().ok_or_else(|| {
+            stacks_node::Error::InvalidTransaction("no sender or nonce".to_string())
+        })?;
+        let serialized = tx
+            .serialized
+            .clone()
+            .ok_or_else(|| stacks_node::Error::InvalidTransaction("not serialized".to_string()))?;
+        Ok(Self::Stacks {
+            sender: sender.to_string(),
+            nonce,
+            serialized,
+        })
+    }
+}
+
+impl From<&bitcoin::Transaction> for BroadcastTransaction {
+    fn from(tx: &bitcoin::Transaction) -> Self {
+        Self::Bitcoin {
+            txid: tx.txid().to_string(),
+            serialized: bitcoin::consensus::encode::serialize_hex(tx),
+        }
+    }
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -35,4 +127,18 @@ impl SbtcOp {
             _ => None,
         }
     }
+
+    pub fn txid(&self) -> Txid {
+        match self {
+            Self::PegIn(op) => op.txid,
+            Self::PegOutRequest(op) => op.txid,
+        }
+    }
+
+    pub fn burn_header_hash(&self) -> BurnchainHeaderHash {
+        match self {
+            Self::PegIn(op) => op.burn_header_hash,
+            Self::PegOutRequest(op) => op.burn_header_hash,
+        }
+    }
 }
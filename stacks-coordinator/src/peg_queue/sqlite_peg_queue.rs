@@ -1,21 +1,36 @@
 use std::path::Path;
 use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use rusqlite::Connection as RusqliteConnection;
 use rusqlite::Error as SqliteError;
+use rusqlite::OptionalExtension;
 use rusqlite::Row as SqliteRow;
 
 use blockstack_lib::burnchains::Txid;
 use blockstack_lib::types::chainstate::BurnchainHeaderHash;
 use blockstack_lib::util::HexError;
+use tracing::warn;
 
+use crate::bitcoin_node;
+use crate::peg_queue::migrations::{migrate, structured_columns};
+use crate::peg_queue::BroadcastTransaction;
 use crate::peg_queue::PegQueue;
 use crate::peg_queue::SbtcOp;
 use crate::stacks_node;
 
+/// How often an op is processed before it has failed
+pub const MAX_ATTEMPTS: u32 = 5;
+/// How long an op waits before it is processed again after its first transient failure,
+/// the delay doubles with every attempt
+pub const RETRY_DELAY: Duration = Duration::from_secs(30);
+
 pub struct SqlitePegQueue {
     conn: rusqlite::Connection,
     start_block_height: u64,
+    peg_in_depth: u64,
+    peg_out_depth: u64,
+    retry_delay: Duration,
 }
 
 impl SqlitePegQueue {
@@ -27,16 +42,65 @@ impl SqlitePegQueue {
         Self::from_connection(RusqliteConnection::open_in_memory()?, start_block_height)
     }
 
-    fn from_connection(conn: RusqliteConnection, start_block_height: u64) -> Result<Self, Error> {
+    fn from_connection(
+        mut conn: RusqliteConnection,
+        start_block_height: u64,
+    ) -> Result<Self, Error> {
+        migrate(&mut conn)?;
         let this = Self {
             conn,
             start_block_height,
+            peg_in_depth: 0,
+            peg_out_depth: 0,
+            retry_delay: RETRY_DELAY,
         };
-        this.conn.execute(Self::sql_schema(), rusqlite::params![])?;
+        this.recover_pending()?;
         Ok(this)
     }
 
+    /// An op is handed out once the burn chain is the depth of its kind above its block,
+    /// 0 by default.
+    pub fn with_depths(mut self, peg_in_depth: u64, peg_out_depth: u64) -> Self {
+        self.peg_in_depth = peg_in_depth;
+        self.peg_out_depth = peg_out_depth;
+        self
+    }
+
+    /// The delay before the first retry of an op, `RETRY_DELAY` by default.
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// An op which was pending when the coordinator stopped is processed again. Its
+    /// transactions weren't signed yet, so they are created again. A signed op keeps its
+    /// transactions, the coordinator broadcasts them again.
+    fn recover_pending(&self) -> Result<(), Error> {
+        for mut entry in self.get_entries_with_status(&Status::Pending)? {
+            warn!("Recovering op {} which was pending", entry.txid);
+            entry.status = Status::New;
+            self.insert(&entry)?;
+        }
+
+        Ok(())
+    }
+
+    fn is_deep_enough(&self, entry: &Entry, burn_block_height: u64) -> bool {
+        let depth = match entry.op {
+            SbtcOp::PegIn(_) => self.peg_in_depth,
+            SbtcOp::PegOutRequest(_) => self.peg_out_depth,
+        };
+        burn_block_height.saturating_sub(entry.block_height) >= depth
+    }
+
+    /// The time of the next attempt after a transient failure
+    fn retry_at(&self, attempts: u32) -> u64 {
+        let delay = self.retry_delay.as_secs() << attempts.saturating_sub(1).min(16);
+        now() + delay
+    }
+
     fn insert(&self, entry: &Entry) -> Result<(), Error> {
+        let (op_type, amount, recipient) = structured_columns(&entry.op);
         self.conn.execute(
             Self::sql_insert(),
             rusqlite::params![
@@ -45,19 +109,54 @@ impl SqlitePegQueue {
                 entry.block_height as i64, // Stacks will crash before the coordinator if this is invalid
                 serde_json::to_string(&entry.op)?,
                 entry.status.as_str(),
+                entry.attempts,
+                entry.last_error,
+                serde_json::to_string(&entry.transactions)?,
+                entry.created_at as i64,
+                now() as i64,
+                entry.retry_at as i64,
+                op_type,
+                amount,
+                recipient,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Adds an op found by polling, an op which is already in the queue keeps its status unless
+    /// it was orphaned, then its block is canonical again and it is new again
+    fn insert_new(&self, entry: &Entry) -> Result<(), Error> {
+        let (op_type, amount, recipient) = structured_columns(&entry.op);
+        self.conn.execute(
+            Self::sql_insert_new(),
+            rusqlite::params![
+                entry.txid.to_hex(),
+                entry.burn_header_hash.to_hex(),
+                entry.block_height as i64,
+                serde_json::to_string(&entry.op)?,
+                entry.status.as_str(),
+                entry.attempts,
+                entry.last_error,
+                serde_json::to_string(&entry.transactions)?,
+                entry.created_at as i64,
+                now() as i64,
+                entry.retry_at as i64,
+                op_type,
+                amount,
+                recipient,
             ],
         )?;
 
         Ok(())
     }
 
-    fn get_single_entry_with_status(&self, status: &Status) -> Result<Option<Entry>, Error> {
+    fn get_entries_with_status(&self, status: &Status) -> Result<Vec<Entry>, Error> {
         Ok(self
             .conn
             .prepare(Self::sql_select_status())?
             .query_map(rusqlite::params![status.as_str()], Entry::from_row)?
-            .next()
-            .transpose()?)
+            .collect::<Result<Vec<_>, _>>()?)
     }
 
     fn get_entry(
@@ -71,6 +170,85 @@ impl SqlitePegQueue {
         )?)
     }
 
+    fn burn_header_hash(&self, block_height: u64) -> Result<Option<BurnchainHeaderHash>, Error> {
+        let hash = self
+            .conn
+            .query_row(
+                Self::sql_select_burn_block(),
+                rusqlite::params![block_height as i64],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+
+        Ok(hash
+            .map(|hash| BurnchainHeaderHash::from_hex(&hash))
+            .transpose()?)
+    }
+
+    fn insert_burn_block(
+        &self,
+        block_height: u64,
+        burn_header_hash: &BurnchainHeaderHash,
+    ) -> Result<(), Error> {
+        self.conn.execute(
+            Self::sql_insert_burn_block(),
+            rusqlite::params![block_height as i64, burn_header_hash.to_hex()],
+        )?;
+
+        Ok(())
+    }
+
+    /// Forgets a block which left the canonical chain. Its ops which weren't processed yet are
+    /// orphaned, the processed ones can only be reported.
+    fn orphan_burn_block(&self, block_height: u64) -> Result<(), Error> {
+        warn!("Burn block at {} was orphaned", block_height);
+
+        for status in [Status::Signed, Status::Broadcast, Status::Acknowledged] {
+            let processed = self
+                .conn
+                .prepare(Self::sql_select_height_status())?
+                .query_map(
+                    rusqlite::params![block_height as i64, status.as_str()],
+                    Entry::from_row,
+                )?
+                .collect::<Result<Vec<_>, _>>()?;
+            for entry in processed {
+                warn!(
+                    "Op {} of the orphaned burn block {} was already processed",
+                    entry.txid, entry.burn_header_hash
+                );
+            }
+        }
+
+        for status in [Status::New, Status::Pending, Status::Failed] {
+            self.conn.execute(
+                Self::sql_update_height_status(),
+                rusqlite::params![
+                    Status::Orphaned.as_str(),
+                    block_height as i64,
+                    status.as_str()
+                ],
+            )?;
+        }
+
+        self.conn.execute(
+            Self::sql_delete_burn_block(),
+            rusqlite::params![block_height as i64],
+        )?;
+
+        Ok(())
+    }
+
+    /// A transaction of an orphaned block may be mined again in another block, an op of a
+    /// transaction which was processed in any block is not processed again.
+    fn was_processed_before(&self, txid: &Txid) -> crate::error::Result<bool> {
+        let processed = self.is_acknowledged(txid)?;
+        if processed {
+            warn!("Op {} was processed before", txid);
+        }
+        Ok(processed)
+    }
+
     fn max_observed_block_height(&self) -> Result<u64, Error> {
         Ok(self
             .conn
@@ -86,87 +264,263 @@ impl SqlitePegQueue {
             .map(|count| count as u64)?)
     }
 
-    const fn sql_schema() -> &'static str {
+    const fn sql_insert() -> &'static str {
         r#"
-        CREATE TABLE IF NOT EXISTS sbtc_ops (
-            txid TEXT NOT NULL,
-            burn_header_hash TEXT NOT NULL,
-            block_height INTEGER NOT NULL,
-            op TEXT NOT NULL,
-            status TEXT NOT NULL,
-
-            PRIMARY KEY(txid, burn_header_hash)
-        )
+        REPLACE INTO sbtc_ops (txid, burn_header_hash, block_height, op, status, attempts, last_error, transactions, created_at, updated_at, retry_at, op_type, amount, recipient) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
         "#
     }
 
-    const fn sql_insert() -> &'static str {
+    const fn sql_insert_new() -> &'static str {
         r#"
-        REPLACE INTO sbtc_ops (txid, burn_header_hash, block_height, op, status) VALUES (?1, ?2, ?3, ?4, ?5)
+        INSERT INTO sbtc_ops (txid, burn_header_hash, block_height, op, status, attempts, last_error, transactions, created_at, updated_at, retry_at, op_type, amount, recipient) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+        ON CONFLICT(txid, burn_header_hash) DO UPDATE SET status=excluded.status, attempts=excluded.attempts, last_error=excluded.last_error, updated_at=excluded.updated_at, retry_at=excluded.retry_at WHERE status='orphaned'
         "#
     }
 
     const fn sql_select_status() -> &'static str {
         r#"
-        SELECT txid, burn_header_hash, block_height, op, status FROM sbtc_ops WHERE status=?1 ORDER BY block_height, op ASC
+        SELECT txid, burn_header_hash, block_height, op, status, attempts, last_error, transactions, created_at, updated_at, retry_at FROM sbtc_ops WHERE status=?1 ORDER BY block_height, op ASC
         "#
     }
 
     const fn sql_select_pk() -> &'static str {
         r#"
-        SELECT txid, burn_header_hash, block_height, op, status FROM sbtc_ops WHERE txid=?1 AND burn_header_hash=?2
+        SELECT txid, burn_header_hash, block_height, op, status, attempts, last_error, transactions, created_at, updated_at, retry_at FROM sbtc_ops WHERE txid=?1 AND burn_header_hash=?2
+        "#
+    }
+
+    const fn sql_count_txid_status() -> &'static str {
+        r#"
+        SELECT COUNT(*) FROM sbtc_ops WHERE txid=?1 AND status IN (?2, ?3, ?4)
+        "#
+    }
+
+    const fn sql_select_height_status() -> &'static str {
+        r#"
+        SELECT txid, burn_header_hash, block_height, op, status, attempts, last_error, transactions, created_at, updated_at, retry_at FROM sbtc_ops WHERE block_height=?1 AND status=?2
+        "#
+    }
+
+    const fn sql_update_height_status() -> &'static str {
+        r#"
+        UPDATE sbtc_ops SET status=?1 WHERE block_height=?2 AND status=?3
+        "#
+    }
+
+    const fn sql_select_burn_block() -> &'static str {
+        r#"
+        SELECT burn_header_hash FROM burn_blocks WHERE block_height=?1
+        "#
+    }
+
+    const fn sql_insert_burn_block() -> &'static str {
+        r#"
+        REPLACE INTO burn_blocks (block_height, burn_header_hash) VALUES (?1, ?2)
+        "#
+    }
+
+    const fn sql_delete_burn_block() -> &'static str {
+        r#"
+        DELETE FROM burn_blocks WHERE block_height=?1
         "#
     }
 
     const fn sql_select_max_burn_height() -> &'static str {
         r#"
-        SELECT MAX(block_height) FROM sbtc_ops
+        SELECT MAX(block_height) FROM (
+            SELECT block_height FROM sbtc_ops UNION ALL SELECT block_height FROM burn_blocks
+        )
         "#
     }
 }
 
 impl PegQueue for SqlitePegQueue {
     fn sbtc_op(&self) -> crate::error::Result<Option<SbtcOp>> {
-        let maybe_entry = self.get_single_entry_with_status(&Status::New)?;
+        let burn_block_height = self.max_observed_block_height()?;
+        let now = now();
+        let maybe_entry = self
+            .get_entries_with_status(&Status::New)?
+            .into_iter()
+            .find(|entry| entry.retry_at <= now && self.is_deep_enough(entry, burn_block_height));
 
         let Some(mut entry) = maybe_entry else {
             return Ok(None)
         };
 
         entry.status = Status::Pending;
+        entry.attempts += 1;
         self.insert(&entry)?;
 
         Ok(Some(entry.op))
     }
 
-    fn poll<N: stacks_node::StacksNode>(&self, stacks_node: &N) -> crate::error::Result<()> {
-        let target_block_height = stacks_node.burn_block_height();
+    fn unconfirmed_ops(&self) -> crate::error::Result<Vec<SbtcOp>> {
+        let burn_block_height = self.max_observed_block_height()?;
+        Ok(self
+            .get_entries_with_status(&Status::New)?
+            .into_iter()
+            .filter(|entry| !self.is_deep_enough(entry, burn_block_height))
+            .map(|entry| entry.op)
+            .collect())
+    }
+
+    fn poll<N: stacks_node::StacksNode, B: bitcoin_node::BitcoinNode>(
+        &self,
+        stacks_node: &N,
+        bitcoin_node: &B,
+    ) -> crate::error::Result<()> {
+        let target_block_height = stacks_node.burn_block_height()?;
+
+        let mut block_height = self.max_observed_block_height()?;
+        while block_height > self.start_block_height {
+            // the hashes of blocks observed before they were recorded are unknown
+            let canonical = block_height <= target_block_height
+                && match self.burn_header_hash(block_height)? {
+                    Some(hash) => bitcoin_node.burn_header_hash(block_height)? == hash,
+                    None => true,
+                };
+            if canonical {
+                break;
+            }
+            self.orphan_burn_block(block_height)?;
+            block_height -= 1;
+        }
+
+        for block_height in (block_height + 1)..=target_block_height {
+            let burn_header_hash = bitcoin_node.burn_header_hash(block_height)?;
+            let peg_in_ops = stacks_node.get_peg_in_ops(block_height)?;
+            let peg_out_request_ops = stacks_node.get_peg_out_request_ops(block_height)?;
+
+            // the stacks node may not have followed a reorg of bitcoind yet, the block is
+            // scanned again by the next poll
+            let other_block = peg_in_ops
+                .iter()
+                .map(|op| &op.burn_header_hash)
+                .chain(peg_out_request_ops.iter().map(|op| &op.burn_header_hash))
+                .any(|hash| *hash != burn_header_hash);
+            if other_block {
+                warn!(
+                    "The stacks node has ops of another burn block at {}",
+                    block_height
+                );
+                break;
+            }
+            self.insert_burn_block(block_height, &burn_header_hash)?;
 
-        for block_height in (self.max_observed_block_height()? + 1)..=target_block_height {
-            for peg_in_op in stacks_node.get_peg_in_ops(block_height) {
+            for peg_in_op in peg_in_ops {
+                if self.was_processed_before(&peg_in_op.txid)? {
+                    continue;
+                }
                 let entry = Entry {
                     block_height,
                     status: Status::New,
                     txid: peg_in_op.txid,
                     burn_header_hash: peg_in_op.burn_header_hash,
+                    attempts: 0,
+                    last_error: None,
+                    transactions: Vec::new(),
+                    created_at: now(),
+                    retry_at: 0,
                     op: SbtcOp::PegIn(peg_in_op),
                 };
 
-                self.insert(&entry)?;
+                self.insert_new(&entry)?;
             }
 
-            for peg_out_request_op in stacks_node.get_peg_out_request_ops(block_height) {
+            for peg_out_request_op in peg_out_request_ops {
+                if self.was_processed_before(&peg_out_request_op.txid)? {
+                    continue;
+                }
                 let entry = Entry {
                     block_height,
                     status: Status::New,
                     txid: peg_out_request_op.txid,
                     burn_header_hash: peg_out_request_op.burn_header_hash,
+                    attempts: 0,
+                    last_error: None,
+                    transactions: Vec::new(),
+                    created_at: now(),
+                    retry_at: 0,
                     op: SbtcOp::PegOutRequest(peg_out_request_op),
                 };
 
-                self.insert(&entry)?;
+                self.insert_new(&entry)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn sign(
+        &self,
+        txid: &Txid,
+        burn_header_hash: &BurnchainHeaderHash,
+        transactions: &[BroadcastTransaction],
+    ) -> crate::error::Result<()> {
+        let mut entry = self.get_entry(txid, burn_header_hash)?;
+
+        entry.status = Status::Signed;
+        entry.transactions = transactions.to_vec();
+        self.insert(&entry)?;
+
+        Ok(())
+    }
+
+    fn signed_ops(&self) -> crate::error::Result<Vec<(SbtcOp, Vec<BroadcastTransaction>)>> {
+        Ok(self
+            .get_entries_with_status(&Status::Signed)?
+            .into_iter()
+            .map(|entry| (entry.op, entry.transactions))
+            .collect())
+    }
+
+    fn broadcast(
+        &self,
+        txid: &Txid,
+        burn_header_hash: &BurnchainHeaderHash,
+    ) -> crate::error::Result<()> {
+        let mut entry = self.get_entry(txid, burn_header_hash)?;
+
+        entry.status = Status::Broadcast;
+        self.insert(&entry)?;
+
+        Ok(())
+    }
+
+    fn broadcast_ops(&self) -> crate::error::Result<Vec<(SbtcOp, Vec<BroadcastTransaction>)>> {
+        Ok(self
+            .get_entries_with_status(&Status::Broadcast)?
+            .into_iter()
+            .map(|entry| (entry.op, entry.transactions))
+            .collect())
+    }
+
+    fn fail(
+        &self,
+        txid: &Txid,
+        burn_header_hash: &BurnchainHeaderHash,
+        error: &str,
+        transient: bool,
+    ) -> crate::error::Result<()> {
+        let mut entry = self.get_entry(txid, burn_header_hash)?;
+        // an op which was orphaned meanwhile stays orphaned
+        if !matches!(entry.status, Status::Pending | Status::Signed) {
+            return Ok(());
+        }
+
+        entry.last_error = Some(error.to_string());
+        if entry.status == Status::Signed {
+            // a signed op is only ever broadcast again, until its transactions can't be
+            if !transient {
+                entry.status = Status::Failed;
             }
+        } else if transient && entry.attempts < MAX_ATTEMPTS {
+            entry.status = Status::New;
+            entry.retry_at = self.retry_at(entry.attempts);
+        } else {
+            entry.status = Status::Failed;
         }
+        self.insert(&entry)?;
 
         Ok(())
     }
@@ -183,6 +537,34 @@ impl PegQueue for SqlitePegQueue {
 
         Ok(())
     }
+
+    fn is_acknowledged(&self, txid: &Txid) -> crate::error::Result<bool> {
+        let count: i64 = self
+            .conn
+            .query_row(
+                Self::sql_count_txid_status(),
+                rusqlite::params![
+                    txid.to_hex(),
+                    Status::Signed.as_str(),
+                    Status::Broadcast.as_str(),
+                    Status::Acknowledged.as_str()
+                ],
+                |row| row.get(0),
+            )
+            .map_err(Error::from)?;
+
+        Ok(count > 0)
+    }
+
+    fn is_orphaned(
+        &self,
+        txid: &Txid,
+        burn_header_hash: &BurnchainHeaderHash,
+    ) -> crate::error::Result<bool> {
+        let entry = self.get_entry(txid, burn_header_hash)?;
+
+        Ok(entry.status == Status::Orphaned)
+    }
 }
 
 #[derive(Debug)]
@@ -192,6 +574,11 @@ struct Entry {
     block_height: u64,
     op: SbtcOp,
     status: Status,
+    attempts: u32,
+    last_error: Option<String>,
+    transactions: Vec<BroadcastTransaction>,
+    created_at: u64,
+    retry_at: u64,
 }
 
 impl Entry {
@@ -207,21 +594,40 @@ impl Entry {
 
         let status: Status = row.get::<_, String>(4)?.parse()?;
 
+        let transactions: Vec<BroadcastTransaction> =
+            serde_json::from_str(&row.get::<_, String>(7)?).map_err(Error::from)?;
+
         Ok(Self {
             burn_header_hash,
             txid,
             block_height,
             op,
             status,
+            attempts: row.get(5)?,
+            last_error: row.get(6)?,
+            transactions,
+            created_at: row.get::<_, i64>(8)? as u64,
+            retry_at: row.get::<_, i64>(10)? as u64,
         })
     }
 }
 
+/// The current time in seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
 #[derive(Debug, PartialEq, Eq)]
 enum Status {
     New,
     Pending,
+    Signed,
+    Broadcast,
     Acknowledged,
+    Failed,
+    Orphaned,
 }
 
 impl Status {
@@ -229,7 +635,11 @@ impl Status {
         match self {
             Self::New => "new",
             Self::Pending => "pending",
+            Self::Signed => "signed",
+            Self::Broadcast => "broadcast",
             Self::Acknowledged => "acknowledged",
+            Self::Failed => "failed",
+            Self::Orphaned => "orphaned",
         }
     }
 }
@@ -241,7 +651,11 @@ impl FromStr for Status {
         Ok(match s {
             "new" => Self::New,
             "pending" => Self::Pending,
+            "signed" => Self::Signed,
+            "broadcast" => Self::Broadcast,
             "acknowledged" => Self::Acknowledged,
+            "failed" => Self::Failed,
+            "orphaned" => Self::Orphaned,
             other => return Err(Error::UnrecognizedStatusString(other.to_owned())),
         })
     }
@@ -264,6 +678,9 @@ pub enum Error {
 
     #[error("Entry does not exist")]
     EntryDoesNotExist,
+
+    #[error("Database schema version {0} is newer than the supported version {1}")]
+    UnsupportedSchemaVersion(u32, u32),
 }
 
 // Workaround to allow non-perfect conversions in `Entry::from_row`
@@ -282,7 +699,10 @@ mod tests {
         util::{hash::Hash160, secp256k1::MessageSignature},
     };
 
+    use crate::bitcoin_wallet::script_pubkey;
+    use crate::peg_queue::migrations::schema_version;
     use crate::peg_queue::PegQueue;
+    use crate::peg_queue::SCHEMA_VERSION;
 
     use super::*;
 
@@ -297,7 +717,9 @@ mod tests {
         assert!(peg_queue.sbtc_op().unwrap().is_none());
 
         // Should cause the peg_queue to fetch 3 peg in ops
-        peg_queue.poll(&stacks_node_mock).unwrap();
+        peg_queue
+            .poll(&stacks_node_mock, &default_bitcoin_node_mock())
+            .unwrap();
 
         for height in 1..=number_of_simulated_blocks {
             let next_op = peg_queue.sbtc_op().unwrap().unwrap();
@@ -318,7 +740,9 @@ mod tests {
         let stacks_node_mock = default_stacks_node_mock(number_of_simulated_blocks);
 
         // Fast forward past first poll
-        peg_queue.poll(&stacks_node_mock).unwrap();
+        peg_queue
+            .poll(&stacks_node_mock, &default_bitcoin_node_mock())
+            .unwrap();
         for _ in 1..=number_of_simulated_blocks {
             peg_queue.sbtc_op().unwrap().unwrap();
             peg_queue.sbtc_op().unwrap().unwrap();
@@ -328,12 +752,14 @@ mod tests {
 
         stacks_node_mock
             .expect_burn_block_height()
-            .return_const(number_of_simulated_blocks);
+            .returning(move || Ok(number_of_simulated_blocks));
 
         stacks_node_mock.expect_get_peg_in_ops().never();
         stacks_node_mock.expect_get_peg_out_request_ops().never();
 
-        peg_queue.poll(&stacks_node_mock).unwrap();
+        peg_queue
+            .poll(&stacks_node_mock, &default_bitcoin_node_mock())
+            .unwrap();
     }
 
     #[test]
@@ -345,14 +771,18 @@ mod tests {
         let stacks_node_mock = default_stacks_node_mock(number_of_simulated_blocks);
 
         // Fast forward past first poll
-        peg_queue.poll(&stacks_node_mock).unwrap();
+        peg_queue
+            .poll(&stacks_node_mock, &default_bitcoin_node_mock())
+            .unwrap();
         for _ in 1..=number_of_simulated_blocks {
             peg_queue.sbtc_op().unwrap().unwrap();
             peg_queue.sbtc_op().unwrap().unwrap();
         }
 
         let stacks_node_mock = default_stacks_node_mock(number_of_simulated_blocks_second_poll);
-        peg_queue.poll(&stacks_node_mock).unwrap();
+        peg_queue
+            .poll(&stacks_node_mock, &default_bitcoin_node_mock())
+            .unwrap();
 
         for height in number_of_simulated_blocks + 1..=number_of_simulated_blocks_second_poll {
             let next_op = peg_queue.sbtc_op().unwrap().unwrap();
@@ -371,7 +801,9 @@ mod tests {
         let number_of_simulated_blocks: u64 = 1;
 
         let stacks_node_mock = default_stacks_node_mock(number_of_simulated_blocks);
-        peg_queue.poll(&stacks_node_mock).unwrap();
+        peg_queue
+            .poll(&stacks_node_mock, &default_bitcoin_node_mock())
+            .unwrap();
 
         let next_op = peg_queue.sbtc_op().unwrap().unwrap();
         let peg_in_op = next_op.as_peg_in().unwrap();
@@ -386,25 +818,640 @@ mod tests {
         assert_eq!(entry.status, Status::Acknowledged);
     }
 
+    #[test]
+    fn is_acknowledged_should_only_be_true_after_acknowledge() {
+        let peg_queue = SqlitePegQueue::in_memory(0).unwrap();
+
+        let stacks_node_mock = default_stacks_node_mock(1);
+        peg_queue
+            .poll(&stacks_node_mock, &default_bitcoin_node_mock())
+            .unwrap();
+
+        let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+        let peg_in_op = next_op.as_peg_in().unwrap();
+        assert!(!peg_queue.is_acknowledged(&peg_in_op.txid).unwrap());
+
+        peg_queue
+            .acknowledge(&peg_in_op.txid, &peg_in_op.burn_header_hash)
+            .unwrap();
+        assert!(peg_queue.is_acknowledged(&peg_in_op.txid).unwrap());
+    }
+
+    #[test]
+    fn transient_failures_should_be_retried_until_the_attempts_run_out() {
+        let peg_queue = SqlitePegQueue::in_memory(0)
+            .unwrap()
+            .with_retry_delay(Duration::ZERO);
+        peg_queue
+            .poll(&default_stacks_node_mock(1), &default_bitcoin_node_mock())
+            .unwrap();
+
+        let op = peg_in_op(1, 0);
+        for attempt in 1..=MAX_ATTEMPTS {
+            let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+            assert_eq!(next_op.txid(), op.txid);
+            peg_queue
+                .fail(&op.txid, &op.burn_header_hash, "node down", true)
+                .unwrap();
+
+            let entry = peg_queue.get_entry(&op.txid, &op.burn_header_hash).unwrap();
+            assert_eq!(entry.attempts, attempt);
+            assert_eq!(entry.last_error.as_deref(), Some("node down"));
+        }
+
+        let entry = peg_queue.get_entry(&op.txid, &op.burn_header_hash).unwrap();
+        assert_eq!(entry.status, Status::Failed);
+        // only the peg-out request is left
+        let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+        assert!(next_op.as_peg_out_request().is_some());
+        assert!(peg_queue.sbtc_op().unwrap().is_none());
+    }
+
+    #[test]
+    fn transient_failures_should_wait_for_the_retry_delay() {
+        let peg_queue = SqlitePegQueue::in_memory(0).unwrap();
+        peg_queue
+            .poll(&default_stacks_node_mock(1), &default_bitcoin_node_mock())
+            .unwrap();
+
+        let op = peg_in_op(1, 0);
+        peg_queue.sbtc_op().unwrap().unwrap();
+        peg_queue
+            .fail(&op.txid, &op.burn_header_hash, "node down", true)
+            .unwrap();
+
+        let entry = peg_queue.get_entry(&op.txid, &op.burn_header_hash).unwrap();
+        assert_eq!(entry.status, Status::New);
+        assert!(entry.retry_at > now());
+        let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+        assert!(next_op.as_peg_out_request().is_some());
+        assert!(peg_queue.sbtc_op().unwrap().is_none());
+    }
+
+    #[test]
+    fn permanent_failures_should_not_be_retried() {
+        let peg_queue = SqlitePegQueue::in_memory(0)
+            .unwrap()
+            .with_retry_delay(Duration::ZERO);
+        peg_queue
+            .poll(&default_stacks_node_mock(1), &default_bitcoin_node_mock())
+            .unwrap();
+
+        let op = peg_in_op(1, 0);
+        peg_queue.sbtc_op().unwrap().unwrap();
+        peg_queue
+            .fail(&op.txid, &op.burn_header_hash, "invalid peg-in", false)
+            .unwrap();
+
+        let entry = peg_queue.get_entry(&op.txid, &op.burn_header_hash).unwrap();
+        assert_eq!(entry.status, Status::Failed);
+        assert_eq!(entry.attempts, 1);
+        assert_eq!(entry.last_error.as_deref(), Some("invalid peg-in"));
+    }
+
+    fn stacks_transactions() -> Vec<BroadcastTransaction> {
+        vec![BroadcastTransaction::Stacks {
+            sender: "ST000000000000000000002AMW42H".to_string(),
+            nonce: 7,
+            serialized: "00ff".to_string(),
+        }]
+    }
+
+    #[test]
+    fn broadcast_ops_should_return_their_transactions_until_acknowledged() {
+        let peg_queue = SqlitePegQueue::in_memory(0).unwrap();
+        peg_queue
+            .poll(&default_stacks_node_mock(1), &default_bitcoin_node_mock())
+            .unwrap();
+
+        let op = peg_in_op(1, 0);
+        peg_queue.sbtc_op().unwrap().unwrap();
+        let transactions = stacks_transactions();
+        peg_queue
+            .sign(&op.txid, &op.burn_header_hash, &transactions)
+            .unwrap();
+        assert!(peg_queue.is_acknowledged(&op.txid).unwrap());
+        assert!(peg_queue.broadcast_ops().unwrap().is_empty());
+        peg_queue.broadcast(&op.txid, &op.burn_header_hash).unwrap();
+        assert!(peg_queue.signed_ops().unwrap().is_empty());
+
+        let broadcast_ops = peg_queue.broadcast_ops().unwrap();
+        assert_eq!(broadcast_ops.len(), 1);
+        assert_eq!(broadcast_ops[0].0.txid(), op.txid);
+        assert_eq!(broadcast_ops[0].1, transactions);
+
+        peg_queue
+            .acknowledge(&op.txid, &op.burn_header_hash)
+            .unwrap();
+        assert!(peg_queue.broadcast_ops().unwrap().is_empty());
+    }
+
+    #[test]
+    fn pending_entries_should_be_recovered() {
+        let peg_queue = SqlitePegQueue::in_memory(0).unwrap();
+        peg_queue
+            .poll(&default_stacks_node_mock(1), &default_bitcoin_node_mock())
+            .unwrap();
+
+        let taken = peg_queue.sbtc_op().unwrap().unwrap();
+        peg_queue.recover_pending().unwrap();
+
+        let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+        assert_eq!(next_op.txid(), taken.txid());
+        let entry = peg_queue
+            .get_entry(&taken.txid(), &taken.burn_header_hash())
+            .unwrap();
+        assert_eq!(entry.attempts, 2);
+    }
+
+    #[test]
+    fn signed_entries_should_keep_their_transactions_when_recovered() {
+        let peg_queue = SqlitePegQueue::in_memory(0).unwrap();
+        peg_queue
+            .poll(&default_stacks_node_mock(1), &default_bitcoin_node_mock())
+            .unwrap();
+
+        let op = peg_queue.sbtc_op().unwrap().unwrap();
+        let transactions = stacks_transactions();
+        peg_queue
+            .sign(&op.txid(), &op.burn_header_hash(), &transactions)
+            .unwrap();
+        // a failure while broadcasting doesn't make the op new again
+        peg_queue
+            .fail(&op.txid(), &op.burn_header_hash(), "node down", true)
+            .unwrap();
+        peg_queue.recover_pending().unwrap();
+
+        let signed_ops = peg_queue.signed_ops().unwrap();
+        assert_eq!(signed_ops.len(), 1);
+        assert_eq!(signed_ops[0].0.txid(), op.txid());
+        assert_eq!(signed_ops[0].1, transactions);
+        let entry = peg_queue
+            .get_entry(&op.txid(), &op.burn_header_hash())
+            .unwrap();
+        assert_eq!(entry.last_error.as_deref(), Some("node down"));
+        // only the other op of the block is processed
+        assert!(peg_queue
+            .sbtc_op()
+            .unwrap()
+            .unwrap()
+            .as_peg_out_request()
+            .is_some());
+        assert!(peg_queue.sbtc_op().unwrap().is_none());
+
+        // a signed op fails once its transactions can't be broadcast
+        peg_queue
+            .fail(&op.txid(), &op.burn_header_hash(), "inputs spent", false)
+            .unwrap();
+        assert!(peg_queue.signed_ops().unwrap().is_empty());
+        let entry = peg_queue
+            .get_entry(&op.txid(), &op.burn_header_hash())
+            .unwrap();
+        assert_eq!(entry.status, Status::Failed);
+    }
+
+    #[test]
+    fn new_databases_should_have_the_latest_schema() {
+        let peg_queue = SqlitePegQueue::in_memory(0).unwrap();
+        assert_eq!(
+            schema_version(&peg_queue.conn).unwrap(),
+            Some(SCHEMA_VERSION)
+        );
+
+        peg_queue
+            .poll(&default_stacks_node_mock(1), &default_bitcoin_node_mock())
+            .unwrap();
+        let (op_type, amount): (String, i64) = peg_queue
+            .conn
+            .query_row(
+                "SELECT op_type, amount FROM sbtc_ops WHERE status='new' ORDER BY op",
+                rusqlite::params![],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(op_type, "peg_in");
+        assert_eq!(amount, 1337);
+    }
+
+    #[test]
+    fn databases_without_schema_version_should_be_upgraded() {
+        let conn = RusqliteConnection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE sbtc_ops (
+                txid TEXT NOT NULL,
+                burn_header_hash TEXT NOT NULL,
+                block_height INTEGER NOT NULL,
+                op TEXT NOT NULL,
+                status TEXT NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                last_error TEXT,
+                transactions TEXT NOT NULL DEFAULT '[]',
+                created_at INTEGER NOT NULL DEFAULT 0,
+                updated_at INTEGER NOT NULL DEFAULT 0,
+                retry_at INTEGER NOT NULL DEFAULT 0,
+
+                PRIMARY KEY(txid, burn_header_hash)
+            );
+            CREATE TABLE burn_blocks (
+                block_height INTEGER PRIMARY KEY,
+                burn_header_hash TEXT NOT NULL
+            );
+            "#,
+        )
+        .unwrap();
+        let sbtc_op = SbtcOp::PegOutRequest(peg_out_request_op(2, 0));
+        let op = sbtc_op.as_peg_out_request().unwrap();
+        conn.execute(
+            "INSERT INTO sbtc_ops (txid, burn_header_hash, block_height, op, status, attempts) VALUES (?1, ?2, 2, ?3, 'failed', 5)",
+            rusqlite::params![
+                op.txid.to_hex(),
+                op.burn_header_hash.to_hex(),
+                serde_json::to_string(&sbtc_op).unwrap()
+            ],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO burn_blocks (block_height, burn_header_hash) VALUES (2, ?1)",
+            rusqlite::params![op.burn_header_hash.to_hex()],
+        )
+        .unwrap();
+
+        let peg_queue = SqlitePegQueue::from_connection(conn, 0).unwrap();
+        assert_eq!(
+            schema_version(&peg_queue.conn).unwrap(),
+            Some(SCHEMA_VERSION)
+        );
+
+        let entry = peg_queue.get_entry(&op.txid, &op.burn_header_hash).unwrap();
+        assert_eq!(entry.status, Status::Failed);
+        assert_eq!(entry.attempts, 5);
+        assert_eq!(peg_queue.max_observed_block_height().unwrap(), 2);
+
+        let (op_type, amount, recipient): (String, i64, String) = peg_queue
+            .conn
+            .query_row(
+                "SELECT op_type, amount, recipient FROM sbtc_ops",
+                rusqlite::params![],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(op_type, "peg_out_request");
+        assert_eq!(amount, 1337);
+        assert_eq!(
+            recipient,
+            hex::encode(script_pubkey(&op.recipient).as_bytes())
+        );
+    }
+
+    #[test]
+    fn databases_of_a_newer_schema_should_be_refused() {
+        let conn = RusqliteConnection::open_in_memory().unwrap();
+        conn.execute_batch(&format!(
+            r#"
+            CREATE TABLE schema_version (version INTEGER NOT NULL);
+            INSERT INTO schema_version (version) VALUES ({});
+            "#,
+            SCHEMA_VERSION + 1
+        ))
+        .unwrap();
+
+        match SqlitePegQueue::from_connection(conn, 0) {
+            Err(Error::UnsupportedSchemaVersion(version, supported)) => {
+                assert_eq!(version, SCHEMA_VERSION + 1);
+                assert_eq!(supported, SCHEMA_VERSION);
+            }
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("a newer schema was accepted"),
+        }
+    }
+
+    #[test]
+    fn databases_of_the_first_schema_should_be_upgraded() {
+        let conn = RusqliteConnection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE sbtc_ops (
+                txid TEXT NOT NULL,
+                burn_header_hash TEXT NOT NULL,
+                block_height INTEGER NOT NULL,
+                op TEXT NOT NULL,
+                status TEXT NOT NULL,
+
+                PRIMARY KEY(txid, burn_header_hash)
+            );
+            "#,
+        )
+        .unwrap();
+        let sbtc_op = SbtcOp::PegIn(peg_in_op(1, 0));
+        let op = sbtc_op.as_peg_in().unwrap();
+        conn.execute(
+            "INSERT INTO sbtc_ops (txid, burn_header_hash, block_height, op, status) VALUES (?1, ?2, 1, ?3, 'new')",
+            rusqlite::params![
+                op.txid.to_hex(),
+                op.burn_header_hash.to_hex(),
+                serde_json::to_string(&sbtc_op).unwrap()
+            ],
+        )
+        .unwrap();
+
+        let peg_queue = SqlitePegQueue::from_connection(conn, 0).unwrap();
+        assert_eq!(
+            schema_version(&peg_queue.conn).unwrap(),
+            Some(SCHEMA_VERSION)
+        );
+
+        let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+        assert_eq!(next_op.txid(), op.txid);
+        let recipient: String = peg_queue
+            .conn
+            .query_row(
+                "SELECT recipient FROM sbtc_ops",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(recipient, op.recipient.to_string());
+
+        // the first block after the recorded ops is scanned next
+        peg_queue
+            .poll(&default_stacks_node_mock(2), &default_bitcoin_node_mock())
+            .unwrap();
+        let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+        assert_eq!(next_op.as_peg_in().unwrap().block_height, 2);
+    }
+
+    #[test]
+    fn calling_poll_should_orphan_ops_of_replaced_blocks() {
+        let peg_queue = SqlitePegQueue::in_memory(0).unwrap();
+
+        peg_queue
+            .poll(&default_stacks_node_mock(3), &default_bitcoin_node_mock())
+            .unwrap();
+
+        // the ops of block 1 and the peg-in of block 2 are taken
+        let taken: Vec<_> = (0..3)
+            .map(|_| peg_queue.sbtc_op().unwrap().unwrap())
+            .collect();
+        let acknowledged = taken[0].as_peg_in().unwrap();
+        peg_queue
+            .acknowledge(&acknowledged.txid, &acknowledged.burn_header_hash)
+            .unwrap();
+
+        // blocks 2 and 3 are replaced and block 4 is added
+        peg_queue
+            .poll(&forked_stacks_node_mock(4, 2), &forked_bitcoin_node_mock(2))
+            .unwrap();
+
+        let pending = taken[2].as_peg_in().unwrap();
+        assert_eq!(pending.block_height, 2);
+        assert!(peg_queue
+            .is_orphaned(&pending.txid, &pending.burn_header_hash)
+            .unwrap());
+        let new = peg_out_request_op(3, 0);
+        assert!(peg_queue
+            .is_orphaned(&new.txid, &new.burn_header_hash)
+            .unwrap());
+        assert!(!peg_queue
+            .is_orphaned(&acknowledged.txid, &acknowledged.burn_header_hash)
+            .unwrap());
+
+        // the ops of the new branch follow
+        for height in 2..=4 {
+            let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+            assert_eq!(next_op.as_peg_in().unwrap().txid, peg_in_op(height, 1).txid);
+
+            let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+            assert_eq!(
+                next_op.as_peg_out_request().unwrap().txid,
+                peg_out_request_op(height, 1).txid
+            );
+        }
+        assert!(peg_queue.sbtc_op().unwrap().is_none());
+    }
+
+    #[test]
+    fn calling_poll_should_not_process_an_op_again_when_its_transaction_is_mined_again() {
+        let peg_queue = SqlitePegQueue::in_memory(0).unwrap();
+        let broadcast = |op: &SbtcOp| {
+            peg_queue
+                .sign(&op.txid(), &op.burn_header_hash(), &[])
+                .unwrap();
+            peg_queue
+                .broadcast(&op.txid(), &op.burn_header_hash())
+                .unwrap();
+        };
+
+        peg_queue
+            .poll(&default_stacks_node_mock(3), &default_bitcoin_node_mock())
+            .unwrap();
+        // the ops of block 1 and the peg-in of block 2 are broadcast
+        for _ in 0..3 {
+            broadcast(&peg_queue.sbtc_op().unwrap().unwrap());
+        }
+
+        // the transactions of blocks 2 and 3 are mined again in other blocks
+        peg_queue
+            .poll(
+                &remined_stacks_node_mock(3, 2),
+                &forked_bitcoin_node_mock(2),
+            )
+            .unwrap();
+        let orphaned = peg_out_request_op(2, 0);
+        assert!(peg_queue
+            .is_orphaned(&orphaned.txid, &orphaned.burn_header_hash)
+            .unwrap());
+        // only the peg-out which wasn't broadcast is processed in its new block
+        let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+        assert_eq!(next_op.txid(), orphaned.txid);
+        assert_eq!(next_op.burn_header_hash(), burn_header_hash(2, 1));
+        broadcast(&next_op);
+        for _ in 0..2 {
+            assert_eq!(
+                peg_queue.sbtc_op().unwrap().unwrap().burn_header_hash(),
+                burn_header_hash(3, 1)
+            );
+        }
+        assert!(peg_queue.sbtc_op().unwrap().is_none());
+
+        // back on the first branch, the peg-out of block 2 was broadcast in the other block 2
+        // and the orphaned ops of block 3 are new again
+        peg_queue
+            .poll(&default_stacks_node_mock(3), &default_bitcoin_node_mock())
+            .unwrap();
+        let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+        assert_eq!(next_op.txid(), peg_in_op(3, 0).txid);
+        assert_eq!(next_op.burn_header_hash(), burn_header_hash(3, 0));
+        let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+        assert_eq!(next_op.txid(), peg_out_request_op(3, 0).txid);
+        assert!(peg_queue.sbtc_op().unwrap().is_none());
+    }
+
+    #[test]
+    fn calling_poll_should_rescan_blocks_the_stacks_node_has_other_ops_of() {
+        let peg_queue = SqlitePegQueue::in_memory(0).unwrap();
+
+        // bitcoind replaced the blocks from 2 on, the stacks node didn't follow yet
+        peg_queue
+            .poll(&default_stacks_node_mock(3), &forked_bitcoin_node_mock(2))
+            .unwrap();
+        for _ in 0..2 {
+            let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+            assert_eq!(next_op.burn_header_hash(), burn_header_hash(1, 0));
+        }
+        assert!(peg_queue.sbtc_op().unwrap().is_none());
+
+        // the ops of the new blocks are found once the stacks node has them
+        peg_queue
+            .poll(&forked_stacks_node_mock(3, 2), &forked_bitcoin_node_mock(2))
+            .unwrap();
+        for height in 2..=3 {
+            let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+            assert_eq!(next_op.txid(), peg_in_op(height, 1).txid);
+            let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+            assert_eq!(next_op.txid(), peg_out_request_op(height, 1).txid);
+        }
+        assert!(peg_queue.sbtc_op().unwrap().is_none());
+    }
+
+    #[test]
+    fn calling_poll_should_orphan_blocks_above_a_shorter_chain() {
+        let peg_queue = SqlitePegQueue::in_memory(0).unwrap();
+
+        peg_queue
+            .poll(&default_stacks_node_mock(3), &default_bitcoin_node_mock())
+            .unwrap();
+
+        // the chain ends with another block 2
+        peg_queue
+            .poll(&forked_stacks_node_mock(2, 2), &forked_bitcoin_node_mock(2))
+            .unwrap();
+
+        let orphaned = peg_in_op(3, 0);
+        assert!(peg_queue
+            .is_orphaned(&orphaned.txid, &orphaned.burn_header_hash)
+            .unwrap());
+
+        let expected = [peg_in_op(1, 0).txid, peg_in_op(2, 1).txid];
+        for txid in expected {
+            let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+            assert_eq!(next_op.as_peg_in().unwrap().txid, txid);
+            peg_queue.sbtc_op().unwrap().unwrap();
+        }
+        assert!(peg_queue.sbtc_op().unwrap().is_none());
+    }
+
+    #[test]
+    fn calling_sbtc_op_should_wait_for_the_depth_of_the_op() {
+        let peg_queue = SqlitePegQueue::in_memory(0).unwrap().with_depths(1, 2);
+
+        peg_queue
+            .poll(&default_stacks_node_mock(2), &default_bitcoin_node_mock())
+            .unwrap();
+
+        // only the peg-in of block 1 is deep enough
+        let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+        assert_eq!(next_op.as_peg_in().unwrap().block_height, 1);
+        assert!(peg_queue.sbtc_op().unwrap().is_none());
+
+        let unconfirmed = peg_queue.unconfirmed_ops().unwrap();
+        assert_eq!(unconfirmed.len(), 3);
+        assert_eq!(unconfirmed[0].as_peg_out_request().unwrap().block_height, 1);
+        assert_eq!(unconfirmed[1].as_peg_in().unwrap().block_height, 2);
+        assert_eq!(unconfirmed[2].as_peg_out_request().unwrap().block_height, 2);
+
+        peg_queue
+            .poll(&default_stacks_node_mock(3), &default_bitcoin_node_mock())
+            .unwrap();
+
+        let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+        assert_eq!(next_op.as_peg_out_request().unwrap().block_height, 1);
+        let next_op = peg_queue.sbtc_op().unwrap().unwrap();
+        assert_eq!(next_op.as_peg_in().unwrap().block_height, 2);
+        assert!(peg_queue.sbtc_op().unwrap().is_none());
+        assert_eq!(peg_queue.unconfirmed_ops().unwrap().len(), 3);
+    }
+
     fn default_stacks_node_mock(block_height: u64) -> stacks_node::MockStacksNode {
+        forked_stacks_node_mock(block_height, block_height + 1)
+    }
+
+    /// The blocks from `fork_height` on are replaced by blocks with other ops
+    fn forked_stacks_node_mock(block_height: u64, fork_height: u64) -> stacks_node::MockStacksNode {
+        let fork = move |height: u64| u64::from(height >= fork_height);
+        let mut stacks_node_mock = stacks_node::MockStacksNode::new();
+
+        stacks_node_mock
+            .expect_burn_block_height()
+            .returning(move || Ok(block_height));
+
+        stacks_node_mock
+            .expect_get_peg_in_ops()
+            .returning(move |height| Ok(vec![peg_in_op(height, fork(height))]));
+
+        stacks_node_mock
+            .expect_get_peg_out_request_ops()
+            .returning(move |height| Ok(vec![peg_out_request_op(height, fork(height))]));
+
+        stacks_node_mock
+    }
+
+    /// Like `forked_stacks_node_mock`, but the blocks from `fork_height` on have the
+    /// transactions of the replaced blocks
+    fn remined_stacks_node_mock(
+        block_height: u64,
+        fork_height: u64,
+    ) -> stacks_node::MockStacksNode {
+        let fork = move |height: u64| u64::from(height >= fork_height);
         let mut stacks_node_mock = stacks_node::MockStacksNode::new();
 
         stacks_node_mock
             .expect_burn_block_height()
-            .return_const(block_height);
+            .returning(move || Ok(block_height));
 
         stacks_node_mock
             .expect_get_peg_in_ops()
-            .returning(|height| vec![peg_in_op(height)]);
+            .returning(move |height| {
+                Ok(vec![stacks_node::PegInOp {
+                    txid: peg_in_op(height, 0).txid,
+                    ..peg_in_op(height, fork(height))
+                }])
+            });
 
         stacks_node_mock
             .expect_get_peg_out_request_ops()
-            .returning(|height| vec![peg_out_request_op(height)]);
+            .returning(move |height| {
+                Ok(vec![stacks_node::PegOutRequestOp {
+                    txid: peg_out_request_op(height, 0).txid,
+                    ..peg_out_request_op(height, fork(height))
+                }])
+            });
 
         stacks_node_mock
     }
 
-    fn peg_in_op(block_height: u64) -> stacks_node::PegInOp {
+    fn default_bitcoin_node_mock() -> bitcoin_node::MockBitcoinNode {
+        forked_bitcoin_node_mock(u64::MAX)
+    }
+
+    /// The blocks from `fork_height` on have other hashes
+    fn forked_bitcoin_node_mock(fork_height: u64) -> bitcoin_node::MockBitcoinNode {
+        let mut bitcoin_node_mock = bitcoin_node::MockBitcoinNode::new();
+
+        bitcoin_node_mock
+            .expect_burn_header_hash()
+            .returning(move |height| {
+                Ok(burn_header_hash(height, u64::from(height >= fork_height)))
+            });
+
+        bitcoin_node_mock
+    }
+
+    fn burn_header_hash(block_height: u64, fork: u64) -> BurnchainHeaderHash {
+        BurnchainHeaderHash(hash_and_expand(block_height, 3 * fork))
+    }
+
+    fn peg_in_op(block_height: u64, fork: u64) -> stacks_node::PegInOp {
         let recipient_stx_addr = StacksAddress::new(26, Hash160([0; 20]));
         let peg_wallet_address =
             PoxAddress::Standard(StacksAddress::new(0, Hash160([0; 20])), None);
@@ -414,14 +1461,14 @@ mod tests {
             peg_wallet_address,
             amount: 1337,
             memo: vec![1, 3, 3, 7],
-            txid: Txid(hash_and_expand(block_height, 1)),
-            burn_header_hash: BurnchainHeaderHash(hash_and_expand(block_height, 0)),
+            txid: Txid(hash_and_expand(block_height, 3 * fork + 1)),
+            burn_header_hash: burn_header_hash(block_height, fork),
             block_height,
             vtxindex: 0,
         }
     }
 
-    fn peg_out_request_op(block_height: u64) -> stacks_node::PegOutRequestOp {
+    fn peg_out_request_op(block_height: u64, fork: u64) -> stacks_node::PegOutRequestOp {
         let recipient_stx_addr = StacksAddress::new(26, Hash160([0; 20]));
         let peg_wallet_address =
             PoxAddress::Standard(StacksAddress::new(0, Hash160([0; 20])), None);
@@ -433,8 +1480,8 @@ mod tests {
             fulfillment_fee: 1000,
             signature: MessageSignature([0; 65]),
             memo: vec![1, 3, 3, 7],
-            txid: Txid(hash_and_expand(block_height, 2)),
-            burn_header_hash: BurnchainHeaderHash(hash_and_expand(block_height, 0)),
+            txid: Txid(hash_and_expand(block_height, 3 * fork + 2)),
+            burn_header_hash: burn_header_hash(block_height, fork),
             block_height,
             vtxindex: 0,
         }
@@ -1,21 +1,39 @@
-use serde::Serialize;
+use blockstack_lib::types::chainstate::StacksAddress;
 
 use crate::bitcoin_node;
+use crate::bitcoin_wallet::{BitcoinWallet as BitcoinWalletImpl, PegOutTransaction};
 use crate::error::Result;
 use crate::stacks_node;
 use crate::stacks_transaction::StacksTransaction;
+use crate::stacks_wallet_js::StacksWalletJs;
 
+/// A wallet which signs the contract calls of the coordinator. `nonce` is the lowest nonce a
+/// transaction may have, a transaction gets a higher one when the wallet signed one with it before.
+#[cfg_attr(test, mockall::automock)]
 pub trait StacksWallet {
-    fn mint(&mut self, op: &stacks_node::PegInOp) -> Result<StacksTransaction>;
-    fn burn(&mut self, op: &stacks_node::PegOutRequestOp) -> Result<StacksTransaction>;
-    fn set_wallet_address(&mut self, address: PegWalletAddress) -> Result<StacksTransaction>;
+    /// The address which signs the transactions and pays their fees
+    fn address(&self) -> StacksAddress;
+    fn mint(&mut self, op: &stacks_node::PegInOp, nonce: u64) -> Result<StacksTransaction>;
+    fn burn(&mut self, op: &stacks_node::PegOutRequestOp, nonce: u64) -> Result<StacksTransaction>;
+    /// Sets the address of the peg wallet in the contract
+    fn set_wallet_address(
+        &mut self,
+        address: &bitcoin::Address,
+        nonce: u64,
+    ) -> Result<StacksTransaction>;
 }
 
+#[cfg_attr(test, mockall::automock)]
 pub trait BitcoinWallet {
+    /// The address which holds the peg funds
+    fn address(&self) -> Result<bitcoin::Address>;
+    /// An unsigned transaction which fulfills a peg-out request with some of `utxos`
     fn fulfill_peg_out(
         &self,
         op: &stacks_node::PegOutRequestOp,
-    ) -> bitcoin_node::BitcoinTransaction;
+        utxos: &[bitcoin_node::Utxo],
+        fee_rate: u64,
+    ) -> Result<PegOutTransaction>;
 }
 
 pub trait PegWallet {
@@ -25,7 +43,19 @@ pub trait PegWallet {
     fn bitcoin_mut(&mut self) -> &mut Self::BitcoinWallet;
 }
 
-// TODO: Representation
-// Should correspond to a [u8; 32] - perhaps reuse a FROST type?
-#[derive(Serialize)]
-pub struct PegWalletAddress(pub [u8; 32]);
+/// The fee wallet of the coordinator, which signs stacks transactions with the stacks private key.
+pub struct WrapPegWallet {
+    pub stacks_wallet: StacksWalletJs,
+    pub bitcoin_wallet: BitcoinWalletImpl,
+}
+
+impl PegWallet for WrapPegWallet {
+    type StacksWallet = StacksWalletJs;
+    type BitcoinWallet = BitcoinWalletImpl;
+    fn stacks_mut(&mut self) -> &mut StacksWalletJs {
+        &mut self.stacks_wallet
+    }
+    fn bitcoin_mut(&mut self) -> &mut BitcoinWalletImpl {
+        &mut self.bitcoin_wallet
+    }
+}
@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+use backoff::{ExponentialBackoff, ExponentialBackoffBuilder};
+use blockstack_lib::types::chainstate::StacksAddress;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::stacks_node::{Error, PegInOp, PegOutRequestOp, StacksNode};
+
+/// How long a request may take
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a failing request is repeated by default
+pub const RETRY_TIME: Duration = Duration::from_secs(60);
+
+/// A client of the RPC API of a stacks node, for example `http://localhost:20443`.
+///
+/// A request which fails because the node can't be reached or has an internal error is
+/// repeated with an exponential backoff.
+pub struct NodeClient {
+    node_url: String,
+    agent: ureq::Agent,
+    retry_time: Duration,
+}
+
+impl NodeClient {
+    pub fn new(node_url: &str) -> Self {
+        Self {
+            node_url: node_url.trim_end_matches('/').to_string(),
+            agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build(),
+            retry_time: RETRY_TIME,
+        }
+    }
+
+    /// Repeats a failing request for `retry_time`, zero doesn't repeat it.
+    pub fn with_retry_time(mut self, retry_time: Duration) -> Self {
+        self.retry_time = retry_time;
+        self
+    }
+
+    fn backoff(&self) -> ExponentialBackoff {
+        ExponentialBackoffBuilder::new()
+            .with_max_elapsed_time(Some(self.retry_time))
+            .build()
+    }
+
+    fn retry<T>(&self, request: impl Fn() -> Result<T, Error>) -> Result<T, Error> {
+        backoff::retry(self.backoff(), || {
+            request().map_err(|e| {
+                if e.is_transient() {
+                    warn!("{}, retrying", e);
+                    backoff::Error::transient(e)
+                } else {
+                    backoff::Error::permanent(e)
+                }
+            })
+        })
+        .map_err(|e| match e {
+            backoff::Error::Permanent(e) => e,
+            backoff::Error::Transient { err, .. } => err,
+        })
+    }
+
+    fn get(&self, path: &str) -> Result<Value, Error> {
+        let url = format!("{}{}", self.node_url, path);
+        self.retry(|| {
+            debug!("GET {}", url);
+            self.agent
+                .get(&url)
+                .call()?
+                .into_json()
+                .map_err(|e| Error::InvalidResponse(e.to_string()))
+        })
+    }
+
+    fn get_burn_ops<T: DeserializeOwned>(
+        &self,
+        block_height: u64,
+        op_type: &str,
+    ) -> Result<Vec<T>, Error> {
+        let json = match self.get(&format!("/v2/burn_ops/{}/{}", block_height, op_type)) {
+            Ok(json) => json,
+            // the node doesn't know burn ops of a block without any
+            Err(Error::StatusError(404, _)) => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        serde_json::from_value(json[op_type].clone())
+            .map_err(|e| Error::InvalidResponse(format!("{} ops: {}", op_type, e)))
+    }
+
+    fn get_u64(&self, path: &str, field: &str) -> Result<u64, Error> {
+        self.get(path)?[field]
+            .as_u64()
+            .ok_or_else(|| Error::InvalidResponse(format!("no {} in {}", field, path)))
+    }
+}
+
+impl StacksNode for NodeClient {
+    fn get_peg_in_ops(&self, block_height: u64) -> Result<Vec<PegInOp>, Error> {
+        self.get_burn_ops(block_height, "peg_in")
+    }
+
+    fn get_peg_out_request_ops(&self, block_height: u64) -> Result<Vec<PegOutRequestOp>, Error> {
+        self.get_burn_ops(block_height, "peg_out_request")
+    }
+
+    fn burn_block_height(&self) -> Result<u64, Error> {
+        self.get_u64("/v2/info", "burn_block_height")
+    }
+
+    fn next_nonce(&self, addr: StacksAddress) -> Result<u64, Error> {
+        self.get_u64(&format!("/v2/accounts/{}?proof=0", addr), "nonce")
+    }
+
+    fn broadcast_transaction(&self, tx: &[u8]) -> Result<(), Error> {
+        let url = format!("{}/v2/transactions", self.node_url);
+        let txid = self.retry(|| {
+            debug!("POST {}", url);
+            self.agent
+                .post(&url)
+                .set("content-type", "application/octet-stream")
+                .send_bytes(tx)?
+                .into_string()
+                .map_err(|e| Error::InvalidResponse(e.to_string()))
+        })?;
+        debug!("broadcast {}", txid);
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use blockstack_lib::chainstate::burn::operations as burn_ops;
+use blockstack_lib::types::chainstate::StacksAddress;
+
+pub mod client;
+
+#[cfg_attr(test, mockall::automock)]
+pub trait StacksNode {
+    fn get_peg_in_ops(&self, block_height: u64) -> Result<Vec<PegInOp>, Error>;
+    fn get_peg_out_request_ops(&self, block_height: u64) -> Result<Vec<PegOutRequestOp>, Error>;
+    fn burn_block_height(&self) -> Result<u64, Error>;
+    fn next_nonce(&self, addr: StacksAddress) -> Result<u64, Error>;
+    /// Broadcasts a signed transaction in its wire format
+    fn broadcast_transaction(&self, tx: &[u8]) -> Result<(), Error>;
+}
+
+pub type PegInOp = burn_ops::PegInOp;
+pub type PegOutRequestOp = burn_ops::PegOutRequestOp;
+
+/// Kinds of errors of a stacks node
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The node could not be reached
+    #[error("Failed to reach the stacks node: {0}")]
+    NetworkError(String),
+    /// The node answered with an error status
+    #[error("The stacks node answered {0}: {1}")]
+    StatusError(u16, String),
+    /// The node answered with something else than expected
+    #[error("Invalid response from the stacks node: {0}")]
+    InvalidResponse(String),
+    /// The transaction can't be broadcast
+    #[error("Invalid transaction: {0}")]
+    InvalidTransaction(String),
+}
+
+impl Error {
+    /// Errors which may go away when the request is repeated
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::NetworkError(_) => true,
+            Self::StatusError(code, _) => *code == 429 || *code >= 500,
+            _ => false,
+        }
+    }
+}
+
+impl From<ureq::Error> for Error {
+    fn from(e: ureq::Error) -> Self {
+        match e {
+            ureq::Error::Status(code, response) => {
+                Self::StatusError(code, response.into_string().unwrap_or_default())
+            }
+            ureq::Error::Transport(e) => Self::NetworkError(e.to_string()),
+        }
+    }
+}
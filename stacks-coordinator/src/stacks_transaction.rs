@@ -1,4 +1,7 @@
+use blockstack_lib::types::chainstate::StacksAddress;
+use blockstack_lib::util::hash::Hash160;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::make_contract_call::{
     AnchorMode, Authorization, ChainID, LengthPrefixedList, Payload, PostConditionMode,
@@ -17,4 +20,30 @@ pub struct StacksTransaction {
     pub payload: Payload,
     pub postConditionMode: PostConditionMode,
     pub postConditions: LengthPrefixedList,
+    /// The wire format in hex, which is broadcast to a stacks node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serialized: Option<String>,
+}
+
+impl StacksTransaction {
+    /// The address which signed a standard transaction and its nonce
+    pub fn sender_nonce(&self) -> Option<(StacksAddress, u64)> {
+        let condition = &self.auth["spendingCondition"];
+        let signer = Hash160::from_hex(condition["signer"].as_str()?).ok()?;
+        // stacks.js serializes big integers as strings
+        let nonce = match &condition["nonce"] {
+            Value::String(nonce) => nonce.parse().ok()?,
+            nonce => nonce.as_u64()?,
+        };
+        let mainnet = self.version.as_u64()? == 0;
+        // P2PKH and P2WPKH are single-sig, P2SH and P2WSH multi-sig
+        let singlesig = matches!(condition["hashMode"].as_u64()?, 0 | 2);
+        let version = match (mainnet, singlesig) {
+            (true, true) => 22,
+            (true, false) => 20,
+            (false, true) => 26,
+            (false, false) => 21,
+        };
+        Some((StacksAddress::new(version, signer), nonce))
+    }
 }
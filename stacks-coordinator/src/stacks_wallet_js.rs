@@ -1,7 +1,12 @@
+use blockstack_lib::types::chainstate::StacksAddress;
+use blockstack_lib::types::AddressHashMode;
+use blockstack_lib::util::secp256k1::{Secp256k1PrivateKey, Secp256k1PublicKey};
+use blockstack_lib::vm::Value;
+
 use crate::{
-    error::Result,
+    error::{Error, Result},
     make_contract_call::{MakeContractCall, SignedContractCallOptions, ANY},
-    peg_wallet::{PegWalletAddress, StacksWallet},
+    peg_wallet::StacksWallet,
     stacks_node::{PegInOp, PegOutRequestOp},
     stacks_transaction::StacksTransaction,
 };
@@ -9,46 +14,97 @@ use crate::{
 pub struct StacksWalletJs {
     make_contract_call: MakeContractCall,
     contract_address: String,
+    contract_name: String,
     sender_key: String,
+    address: StacksAddress,
+    mainnet: bool,
+    fee: u64,
+    // the nonce after the last transaction signed by this wallet, which may not be confirmed yet
+    next_nonce: u64,
 }
 
 impl StacksWalletJs {
-    pub fn new(path: &str, contract_address: String, sender_key: String) -> Result<Self> {
+    /// A wallet which signs with `sender_key` on mainnet or testnet and pays `fee` microSTX
+    /// for every transaction
+    pub fn new(
+        path: &str,
+        contract_address: String,
+        contract_name: String,
+        sender_key: String,
+        mainnet: bool,
+        fee: u64,
+    ) -> Result<Self> {
+        let private_key = Secp256k1PrivateKey::from_hex(&sender_key)
+            .map_err(|e| Error::InvalidStacksPrivateKey(e.to_string()))?;
+        // the c32 versions of single signature addresses on mainnet and testnet
+        let version = if mainnet { 22 } else { 26 };
+        let address = StacksAddress::from_public_keys(
+            version,
+            &AddressHashMode::SerializeP2PKH,
+            1,
+            &vec![Secp256k1PublicKey::from_private(&private_key)],
+        )
+        .ok_or_else(|| Error::InvalidStacksPrivateKey("no address".to_string()))?;
         Ok(Self {
             make_contract_call: MakeContractCall::new(path)?,
             contract_address,
+            contract_name,
             sender_key,
+            address,
+            mainnet,
+            fee,
+            next_nonce: 0,
         })
     }
-    fn call(&mut self, function_name: String) -> Result<StacksTransaction> {
-        let input = SignedContractCallOptions {
-            contractAddress: self.contract_address.clone(),
-            contractName: "".to_string(),
-            functionName: function_name,
-            functionArgs: Vec::default(),
-            fee: Some(0.to_string()),
-            feeEstimateApiUrl: None,
-            nonce: None,
-            network: None,
-            anchorMode: ANY,
-            postConditionMode: None,
-            postConditions: None,
-            validateWithAbi: None,
-            sponsored: None,
-            senderKey: self.sender_key.clone(),
-        };
-        self.make_contract_call.call(&input)
+    fn call(
+        &mut self,
+        function_name: &str,
+        function_args: &[Value],
+        nonce: u64,
+    ) -> Result<StacksTransaction> {
+        let nonce = nonce.max(self.next_nonce);
+        let input = SignedContractCallOptions::new(
+            self.contract_address.clone(),
+            self.contract_name.clone(),
+            function_name,
+            function_args,
+            ANY,
+            self.sender_key.clone(),
+        )
+        .with_network(if self.mainnet { "mainnet" } else { "testnet" })
+        .with_nonce(nonce)
+        .with_fee(self.fee.into());
+        let tx = self.make_contract_call.call_serialized(&input)?;
+        self.next_nonce = nonce + 1;
+        Ok(tx)
     }
 }
 
 impl StacksWallet for StacksWalletJs {
-    fn mint(&mut self, _op: &PegInOp) -> Result<StacksTransaction> {
-        self.call("mint".to_string())
+    fn address(&self) -> StacksAddress {
+        self.address.clone()
+    }
+    /// Mints the amount of the peg-in for its recipient
+    fn mint(&mut self, op: &PegInOp, nonce: u64) -> Result<StacksTransaction> {
+        self.call(
+            "mint",
+            &[
+                Value::UInt(op.amount.into()),
+                Value::Principal(op.recipient.clone()),
+            ],
+            nonce,
+        )
     }
-    fn burn(&mut self, _op: &PegOutRequestOp) -> Result<StacksTransaction> {
-        self.call("burn".to_string())
+    fn burn(&mut self, _op: &PegOutRequestOp, nonce: u64) -> Result<StacksTransaction> {
+        self.call("burn", &[], nonce)
     }
-    fn set_wallet_address(&mut self, _address: PegWalletAddress) -> Result<StacksTransaction> {
-        self.call("set_wallet_address".to_string())
+    fn set_wallet_address(
+        &mut self,
+        address: &bitcoin::Address,
+        nonce: u64,
+    ) -> Result<StacksTransaction> {
+        let address = Value::string_ascii_from_bytes(address.to_string().into_bytes())
+            .map_err(|_| Error::ContractError)?;
+        self.call("set-bitcoin-wallet-address", &[address], nonce)
     }
 }
@@ -0,0 +1,181 @@
+mod common;
+
+use std::str::FromStr;
+
+use bitcoin::{Address, PackedLockTime, Script, Transaction, TxOut, Txid};
+use blockstack_lib::types::chainstate::BurnchainHeaderHash;
+use serde_json::{json, Value};
+use stacks_coordinator::bitcoin_node::{client::BitcoinClient, BitcoinNode, Error};
+
+use common::StubServer;
+
+const TXID: &str = "6bd5e7dc66f1cc1e3e5c1c0d2b4ac5d25c38d5c4b2f2ef9b6b0fb3c6a4bd1d3e";
+
+fn result(result: Value) -> (u16, String) {
+    (
+        200,
+        json!({"result": result, "error": null, "id": "stacks-coordinator"}).to_string(),
+    )
+}
+
+fn rpc_request(server: &StubServer, i: usize) -> Value {
+    serde_json::from_slice(&server.requests()[i].content).unwrap()
+}
+
+#[test]
+fn broadcast_transaction_test() {
+    let tx = Transaction {
+        version: 2,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            value: 1000,
+            script_pubkey: Script::new(),
+        }],
+    };
+    let server = StubServer::start(vec![result(json!(TXID))]);
+    let txid = BitcoinClient::new(&server.url)
+        .broadcast_transaction(&tx)
+        .unwrap();
+    assert_eq!(txid, Txid::from_str(TXID).unwrap());
+    let request = rpc_request(&server, 0);
+    assert_eq!(request["method"], "sendrawtransaction");
+    assert_eq!(
+        request["params"][0],
+        bitcoin::consensus::encode::serialize_hex(&tx)
+    );
+}
+
+#[test]
+fn broadcast_transaction_with_spent_inputs_test() {
+    let rejected = |code: i64, message: &str| {
+        (
+            500,
+            json!({"result": null, "error": {"code": code, "message": message}}).to_string(),
+        )
+    };
+    let server = StubServer::start(vec![
+        rejected(-25, "bad-txns-inputs-missingorspent"),
+        rejected(-26, "txn-mempool-conflict"),
+        rejected(-26, "min relay fee not met"),
+    ]);
+    let client = BitcoinClient::new(&server.url);
+    let tx = Transaction {
+        version: 2,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![],
+        output: vec![],
+    };
+    for _ in 0..2 {
+        assert!(matches!(
+            client.broadcast_transaction(&tx),
+            Err(Error::InputsSpent(_))
+        ));
+    }
+    assert!(matches!(
+        client.broadcast_transaction(&tx),
+        Err(Error::RpcError(-26, _))
+    ));
+}
+
+#[test]
+fn rpc_errors_test() {
+    let server = StubServer::start(vec![(
+        500,
+        json!({"result": null, "error": {"code": -26, "message": "min relay fee not met"}})
+            .to_string(),
+    )]);
+    match BitcoinClient::new(&server.url).estimate_fee_rate(6) {
+        Err(Error::RpcError(-26, message)) => assert_eq!(message, "min relay fee not met"),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn confirmations_test() {
+    let server = StubServer::start(vec![
+        result(json!({"txid": TXID, "confirmations": 3})),
+        // in the mempool
+        result(json!({ "txid": TXID })),
+        (
+            500,
+            json!({"result": null, "error": {"code": -5, "message": "No such mempool or blockchain transaction"}})
+                .to_string(),
+        ),
+    ]);
+    let client = BitcoinClient::new(&server.url);
+    let txid = Txid::from_str(TXID).unwrap();
+    assert_eq!(client.confirmations(&txid).unwrap(), Some(3));
+    assert_eq!(client.confirmations(&txid).unwrap(), Some(0));
+    assert_eq!(client.confirmations(&txid).unwrap(), None);
+    assert_eq!(rpc_request(&server, 0)["params"], json!([TXID, true]));
+}
+
+#[test]
+fn list_unspent_test() {
+    let address = Address::from_str("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080").unwrap();
+    let server = StubServer::start(vec![result(json!({
+        "success": true,
+        "height": 110,
+        "unspents": [{
+            "txid": TXID,
+            "vout": 1,
+            "scriptPubKey": "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+            "amount": 0.5,
+            "height": 101,
+        }],
+    }))]);
+    let utxos = BitcoinClient::new(&server.url)
+        .list_unspent(&address)
+        .unwrap();
+    assert_eq!(utxos.len(), 1);
+    assert_eq!(utxos[0].txid, Txid::from_str(TXID).unwrap());
+    assert_eq!(utxos[0].vout, 1);
+    assert_eq!(utxos[0].amount, 50_000_000);
+    assert_eq!(utxos[0].script_pubkey, address.script_pubkey());
+    assert_eq!(utxos[0].confirmations, 10);
+    assert_eq!(
+        rpc_request(&server, 0)["params"],
+        json!(["start", [format!("addr({})", address)]])
+    );
+}
+
+#[test]
+fn estimate_fee_rate_test() {
+    let server = StubServer::start(vec![
+        result(json!({"feerate": 0.00012, "blocks": 6})),
+        result(json!({"errors": ["Insufficient data or no feerate found"], "blocks": 0})),
+    ]);
+    let client = BitcoinClient::new(&server.url);
+    // 12000 sats per 1000 virtual bytes
+    assert_eq!(client.estimate_fee_rate(6).unwrap(), 12);
+    assert!(matches!(
+        client.estimate_fee_rate(6),
+        Err(Error::NoFeeEstimate)
+    ));
+}
+
+#[test]
+fn burn_header_hash_test() {
+    let hash = "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206";
+    let server = StubServer::start(vec![
+        result(json!(hash)),
+        (
+            500,
+            json!({"result": null, "error": {"code": -8, "message": "Block height out of range"}})
+                .to_string(),
+        ),
+    ]);
+    let client = BitcoinClient::new(&server.url);
+    assert_eq!(
+        client.burn_header_hash(3).unwrap(),
+        BurnchainHeaderHash::from_hex(hash).unwrap()
+    );
+    assert!(matches!(
+        client.burn_header_hash(4),
+        Err(Error::RpcError(-8, _))
+    ));
+    let request = rpc_request(&server, 0);
+    assert_eq!(request["method"], "getblockhash");
+    assert_eq!(request["params"], json!([3]));
+}
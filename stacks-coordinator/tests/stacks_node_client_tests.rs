@@ -0,0 +1,113 @@
+mod common;
+
+use std::time::Duration;
+
+use blockstack_lib::{
+    burnchains::Txid,
+    chainstate::{burn::operations::PegInOp, stacks::address::PoxAddress},
+    types::chainstate::{BurnchainHeaderHash, StacksAddress},
+    util::hash::Hash160,
+    vm::types::{PrincipalData, StandardPrincipalData},
+};
+use serde_json::json;
+use stacks_coordinator::stacks_node::{client::NodeClient, Error, StacksNode};
+
+use common::StubServer;
+
+fn client(server: &StubServer) -> NodeClient {
+    NodeClient::new(&server.url).with_retry_time(Duration::from_secs(5))
+}
+
+fn peg_in_op(block_height: u64) -> PegInOp {
+    PegInOp {
+        recipient: PrincipalData::Standard(StandardPrincipalData(26, [1; 20])),
+        peg_wallet_address: PoxAddress::Standard(StacksAddress::new(0, Hash160([0; 20])), None),
+        amount: 1337,
+        memo: vec![1, 3, 3, 7],
+        txid: Txid([2; 32]),
+        vtxindex: 0,
+        block_height,
+        burn_header_hash: BurnchainHeaderHash([3; 32]),
+    }
+}
+
+#[test]
+fn burn_block_height_test() {
+    let server = StubServer::start(vec![(
+        200,
+        json!({"burn_block_height": 42, "stacks_tip_height": 7}).to_string(),
+    )]);
+    assert_eq!(client(&server).burn_block_height().unwrap(), 42);
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "GET");
+    assert_eq!(requests[0].path, "/v2/info");
+}
+
+#[test]
+fn retry_transient_errors_test() {
+    let server = StubServer::start(vec![
+        (503, "{}".to_string()),
+        (200, json!({"burn_block_height": 5}).to_string()),
+    ]);
+    assert_eq!(client(&server).burn_block_height().unwrap(), 5);
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[test]
+fn permanent_errors_are_returned_test() {
+    let server = StubServer::start(vec![(400, "\"bad request\"".to_string())]);
+    match client(&server).next_nonce(StacksAddress::new(26, Hash160([0; 20]))) {
+        Err(Error::StatusError(400, _)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn invalid_responses_are_errors_test() {
+    let server = StubServer::start(vec![(200, json!({"peg_in": 1}).to_string())]);
+    assert!(matches!(
+        client(&server).get_peg_in_ops(1),
+        Err(Error::InvalidResponse(_))
+    ));
+}
+
+#[test]
+fn get_peg_in_ops_test() {
+    let server = StubServer::start(vec![
+        (200, json!({ "peg_in": [peg_in_op(3)] }).to_string()),
+        (404, "\"Could not find burn block\"".to_string()),
+    ]);
+    let client = client(&server);
+    let ops = client.get_peg_in_ops(3).unwrap();
+    assert_eq!(ops.len(), 1);
+    assert_eq!(ops[0].block_height, 3);
+    assert_eq!(ops[0].amount, 1337);
+    // a block without peg-ins
+    assert!(client.get_peg_in_ops(4).unwrap().is_empty());
+    let paths: Vec<_> = server.requests().into_iter().map(|r| r.path).collect();
+    assert_eq!(paths, ["/v2/burn_ops/3/peg_in", "/v2/burn_ops/4/peg_in"]);
+}
+
+#[test]
+fn next_nonce_test() {
+    let address = StacksAddress::new(26, Hash160([0; 20]));
+    let path = format!("/v2/accounts/{}?proof=0", address);
+    let server = StubServer::start(vec![(
+        200,
+        json!({"balance": "0x0", "nonce": 9}).to_string(),
+    )]);
+    assert_eq!(client(&server).next_nonce(address).unwrap(), 9);
+    assert_eq!(server.requests()[0].path, path);
+}
+
+#[test]
+fn broadcast_transaction_test() {
+    let server = StubServer::start(vec![(200, "\"txid\"".to_string())]);
+    client(&server).broadcast_transaction(&[0, 0xff]).unwrap();
+    let requests = server.requests();
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, "/v2/transactions");
+    assert_eq!(requests[0].content, [0, 0xff]);
+}
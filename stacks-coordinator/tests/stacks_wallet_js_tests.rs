@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 use blockstack_lib::{
     burnchains::Txid,
     chainstate::{
@@ -8,10 +10,7 @@ use blockstack_lib::{
     util::{hash::Hash160, secp256k1::MessageSignature},
     vm::types::{PrincipalData, StandardPrincipalData},
 };
-use stacks_coordinator::{
-    peg_wallet::{PegWalletAddress, StacksWallet},
-    stacks_wallet_js::StacksWalletJs,
-};
+use stacks_coordinator::{peg_wallet::StacksWallet, stacks_wallet_js::StacksWalletJs};
 
 fn pox_address() -> PoxAddress {
     PoxAddress::Standard(StacksAddress::new(0, Hash160::from_data(&[0; 20])), None)
@@ -21,7 +20,10 @@ fn stacks_wallet_js() -> StacksWalletJs {
     StacksWalletJs::new(
         "..",
         "SP3FBR2AGK5H9QBDH3EEN6DF8EK8JY7RX8QJ5SVTE".to_string(),
+        "".to_string(),
         "0001020304050607080910111213141516171819202122232425262728293031".to_string(),
+        false,
+        10_000,
     )
     .unwrap()
 }
@@ -39,7 +41,7 @@ fn stacks_mint_test() {
         burn_header_hash: BurnchainHeaderHash([0; 32]),
     };
     let mut wallet = stacks_wallet_js();
-    let _result = wallet.mint(&p);
+    let _result = wallet.mint(&p, 0);
     // assert_eq!(result, "Mint");
 }
 
@@ -58,14 +60,23 @@ fn stacks_burn_test() {
         burn_header_hash: BurnchainHeaderHash([0; 32]),
     };
     let mut wallet = stacks_wallet_js();
-    let _result = wallet.burn(&p);
+    let _result = wallet.burn(&p, 0);
     // assert_eq!(result, "Burn");
 }
 
 #[test]
 fn stacks_set_wallet_address_test() {
-    let p = PegWalletAddress([0; 32]);
+    let address = bitcoin::Address::from_str(
+        "tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c",
+    )
+    .unwrap();
     let mut wallet = stacks_wallet_js();
-    let _result = wallet.set_wallet_address(p);
-    // assert_eq!(result, "SetWalletAddress");
+    let tx = wallet.set_wallet_address(&address, 0).unwrap();
+    // the next transaction of the wallet gets the next nonce
+    let (sender, nonce) = tx.sender_nonce().unwrap();
+    assert_eq!(sender, wallet.address());
+    assert_eq!(nonce, 0);
+    let tx = wallet.set_wallet_address(&address, 0).unwrap();
+    assert_eq!(tx.sender_nonce().unwrap().1, 1);
+    assert!(tx.serialized.is_some());
 }
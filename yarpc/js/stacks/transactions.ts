@@ -1,18 +1,32 @@
 import { listenStdio, dispatch, CommandMap } from '../lib.ts'
 import {
     type SignedContractCallOptions,
+    type StacksTransaction,
     makeContractCall,
     deserializeCV,
 } from 'npm:@stacks/transactions'
+import { bytesToHex } from 'npm:@stacks/common'
 
 type MakeContractCallInput = {
     readonly[k in keyof SignedContractCallOptions]:
         k extends 'functionArgs' ? readonly string[] : SignedContractCallOptions[k]
 }
 
+const contractCall = (input: MakeContractCallInput) =>
+    makeContractCall({ ...input, functionArgs: input.functionArgs.map(deserializeCV) })
+
+/// The wire format in hex, which a stacks node accepts.
+const serialize = (tx: StacksTransaction) => {
+    const bytes: Uint8Array | string = tx.serialize()
+    return typeof bytes === 'string' ? bytes : bytesToHex(bytes)
+}
+
 const t = {
-    makeContractCall: (input: MakeContractCallInput) =>
-        makeContractCall({ ...input, functionArgs: input.functionArgs.map(deserializeCV) })
+    makeContractCall: contractCall,
+    makeSerializedContractCall: async (input: MakeContractCallInput) => {
+        const tx = await contractCall(input)
+        return { ...tx, serialized: serialize(tx) }
+    },
 }
 
 listenStdio(dispatch(t as unknown as CommandMap))